@@ -0,0 +1,37 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Chain spec of any chain, without its runtime genesis config.
+
+use serde::{Deserialize, Serialize};
+
+use sp_core::storage::Storage;
+use sp_runtime::BuildStorage;
+
+/// Chain spec which is only loaded from raw JSON files, whose genesis storage is given as key-value pairs.
+pub type ChainSpec = sc_chain_spec::GenericChainSpec<Genesis>;
+
+/// Genesis config of a runtime which is not compiled in.
+/// Kept as JSON, it can't build the genesis storage, which the archive reads from the chain database anyway.
+#[derive(Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Genesis(serde_json::Value);
+
+impl BuildStorage for Genesis {
+	fn assimilate_storage(&self, _: &mut Storage) -> Result<(), String> {
+		Err("the genesis storage of a plain chain spec can't be built without the runtime, use a raw chain spec".into())
+	}
+}
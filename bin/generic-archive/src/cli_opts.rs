@@ -0,0 +1,43 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use substrate_archive::cli;
+
+use crate::chain_spec::ChainSpec;
+
+#[derive(Clone, StructOpt)]
+#[structopt(author, about)]
+pub struct CliOpts {
+	#[structopt(flatten)]
+	pub archive: cli::CliOpts,
+	/// Path to the raw chain spec JSON file of the chain.
+	#[structopt(short = "s", long = "spec", alias = "chain-spec", name = "CHAIN", parse(try_from_str = parse_chain_spec))]
+	pub chain_spec: ChainSpec,
+}
+
+fn parse_chain_spec(spec: &str) -> Result<ChainSpec, String> {
+	ChainSpec::from_json_file(PathBuf::from(spec)).map_err(|e| format!("Chain spec could not be loaded: {}", e))
+}
+
+impl CliOpts {
+	pub fn init() -> Self {
+		CliOpts::from_args()
+	}
+}
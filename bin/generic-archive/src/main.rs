@@ -0,0 +1,46 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+mod chain_spec;
+mod cli_opts;
+
+use std::sync::mpsc;
+
+use anyhow::Result;
+
+use substrate_archive::{cli, generic::GenericArchiveBuilder, Archive, SecondaryRocksDb};
+
+fn main() -> Result<()> {
+	let opts = cli_opts::CliOpts::init();
+	let config = opts.archive.config()?;
+	if let Some(cmd) = opts.archive.command() {
+		return Ok(cli::run_command(cmd, config)?);
+	}
+
+	let mut archive =
+		GenericArchiveBuilder::<SecondaryRocksDb>::with_config(config).chain_spec(Box::new(opts.chain_spec)).build()?;
+	archive.drive()?;
+
+	let (tx, rx) = mpsc::channel();
+	ctrlc::set_handler(move || {
+		let _ = tx.send(());
+	})
+	.expect("Error setting Ctrl-C handler");
+	rx.recv().expect("Could not receive from Ctrl-C handler");
+	log::info!("Shutting down, waiting for running tasks to finish");
+	archive.shutdown()?;
+	Ok(())
+}
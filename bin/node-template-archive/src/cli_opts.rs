@@ -14,45 +14,33 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::{fs, path::PathBuf};
+use std::path::PathBuf;
 
-use anyhow::Result;
 use structopt::StructOpt;
 
-use substrate_archive::ArchiveConfig;
+use substrate_archive::cli;
 
 #[derive(Clone, StructOpt)]
 #[structopt(author, about)]
 pub struct CliOpts {
-	/// Sets a custom config file
-	#[structopt(short = "c", long, name = "FILE")]
-	pub config: Option<PathBuf>,
-	/// Sets spec for chain to run in (dev/local).
-	#[structopt(short = "s", long = "spec", name = "CHAIN", parse(from_str = parse_chain_spec))]
+	#[structopt(flatten)]
+	pub archive: cli::CliOpts,
+	/// Sets spec for chain to run in (dev/local), or the path to a raw or plain chain spec JSON file.
+	#[structopt(short = "s", long = "spec", alias = "chain-spec", name = "CHAIN", parse(try_from_str = parse_chain_spec))]
 	pub chain_spec: node_template::chain_spec::ChainSpec,
 }
 
-fn parse_chain_spec(spec: &str) -> node_template::chain_spec::ChainSpec {
-	let spec = match spec {
+fn parse_chain_spec(spec: &str) -> Result<node_template::chain_spec::ChainSpec, String> {
+	match spec {
 		"dev" => node_template::chain_spec::development_config(),
 		"" | "local" => node_template::chain_spec::local_testnet_config(),
 		path => node_template::chain_spec::ChainSpec::from_json_file(PathBuf::from(path)),
-	};
-	spec.expect("Chain spec could not be loaded")
+	}
+	.map_err(|e| format!("Chain spec could not be loaded: {}", e))
 }
 
 impl CliOpts {
 	pub fn init() -> Self {
 		CliOpts::from_args()
 	}
-
-	pub fn parse(&self) -> Result<Option<ArchiveConfig>> {
-		if let Some(config) = &self.config {
-			let toml_str = fs::read_to_string(config.as_path())?;
-			let config = toml::from_str::<ArchiveConfig>(toml_str.as_str())?;
-			Ok(Some(config))
-		} else {
-			Ok(None)
-		}
-	}
 }
@@ -15,33 +15,34 @@
 
 mod cli_opts;
 
-use std::sync::{
-	atomic::{AtomicBool, Ordering},
-	Arc,
-};
+use std::sync::mpsc;
+
+use anyhow::Result;
 
 use node_template::service::Executor;
 use node_template_runtime::{opaque::Block, RuntimeApi};
 
-use substrate_archive::{Archive, ArchiveBuilder, SecondaryRocksDb};
+use substrate_archive::{cli, Archive, ArchiveBuilder, ArchiveConfig, SecondaryRocksDb};
 
-fn main() -> anyhow::Result<()> {
-	let cli = cli_opts::CliOpts::init();
-	let config = cli.parse()?;
+fn main() -> Result<()> {
+	let opts = cli_opts::CliOpts::init();
+	let config = opts.archive.config()?;
+	if let Some(cmd) = opts.archive.command() {
+		return Ok(cli::run_command(cmd, config)?);
+	}
 
 	let mut archive = ArchiveBuilder::<Block, RuntimeApi, Executor, SecondaryRocksDb>::with_config(config)
-		.chain_spec(Box::new(cli.chain_spec))
+		.chain_spec(Box::new(opts.chain_spec))
 		.build()?;
 	archive.drive()?;
 
-	let running = Arc::new(AtomicBool::new(true));
-	let r = running.clone();
-
+	let (tx, rx) = mpsc::channel();
 	ctrlc::set_handler(move || {
-		r.store(false, Ordering::SeqCst);
+		let _ = tx.send(());
 	})
 	.expect("Error setting Ctrl-C handler");
-	while running.load(Ordering::SeqCst) {}
+	rx.recv().expect("Could not receive from Ctrl-C handler");
+	log::info!("Shutting down, waiting for running tasks to finish");
 	archive.shutdown()?;
 	Ok(())
 }
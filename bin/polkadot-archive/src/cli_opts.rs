@@ -14,36 +14,28 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::{fs, path::PathBuf};
+use std::path::PathBuf;
 
-use anyhow::Result;
 use structopt::StructOpt;
 
-use substrate_archive::ArchiveConfig;
+use substrate_archive::cli;
 
 #[derive(Clone, Debug, StructOpt)]
 #[structopt(author, about)]
 pub struct CliOpts {
-	/// Sets a custom config file
-	#[structopt(short = "c", long, name = "FILE")]
-	pub config: Option<PathBuf>,
+	#[structopt(flatten)]
+	pub archive: cli::CliOpts,
 	/// The chain to run substrate-archive for. One of kusama, westend, polkadot.
 	#[structopt(short = "s", long = "spec", name = "CHAIN", default_value = "polkadot")]
 	pub chain_spec: String,
+	/// Path to a raw or plain chain spec JSON file, to archive a chain other than the built-in ones.
+	/// Takes precedence over `--spec`.
+	#[structopt(long = "chain-spec", name = "SPEC_FILE", parse(from_os_str))]
+	pub chain_spec_file: Option<PathBuf>,
 }
 
 impl CliOpts {
 	pub fn init() -> Self {
 		CliOpts::from_args()
 	}
-
-	pub fn parse(&self) -> Result<Option<ArchiveConfig>> {
-		if let Some(config) = &self.config {
-			let toml_str = fs::read_to_string(config.as_path())?;
-			let config = toml::from_str::<ArchiveConfig>(toml_str.as_str())?;
-			Ok(Some(config))
-		} else {
-			Ok(None)
-		}
-	}
 }
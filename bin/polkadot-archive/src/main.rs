@@ -15,18 +15,15 @@
 
 mod cli_opts;
 
-use std::sync::{
-	atomic::{AtomicBool, Ordering},
-	Arc,
-};
+use std::{path::Path, sync::mpsc};
 
 use anyhow::{anyhow, Result};
 use polkadot_service::kusama_runtime as ksm_rt;
 use polkadot_service::polkadot_runtime as dot_rt;
 use polkadot_service::westend_runtime as wnd_rt;
-use polkadot_service::Block;
+use polkadot_service::{Block, ChainSpec, IdentifyVariant, KusamaChainSpec, PolkadotChainSpec, WestendChainSpec};
 use substrate_archive::{
-	native_executor_instance, Archive, ArchiveBuilder, ArchiveConfig, ReadOnlyDb, SecondaryRocksDb,
+	cli, native_executor_instance, Archive, ArchiveBuilder, ArchiveConfig, ArchiveGroup, ReadOnlyDb, SecondaryRocksDb,
 };
 
 native_executor_instance!(
@@ -51,50 +48,119 @@ native_executor_instance!(
 );
 
 pub fn main() -> Result<()> {
-	let cli = cli_opts::CliOpts::init();
-	let config = cli.parse()?;
+	let opts = cli_opts::CliOpts::init();
+	let config = opts.archive.config()?;
+	if let Some(cmd) = opts.archive.command() {
+		return Ok(cli::run_command(cmd, config)?);
+	}
+	if let Some(config) = config.filter(|c| !c.chains.is_empty()) {
+		return run_chains(config);
+	}
 
-	let mut archive = run_archive::<SecondaryRocksDb>(&cli.chain_spec, config)?;
+	let spec = match &opts.chain_spec_file {
+		Some(path) => load_chain_spec(path)?,
+		None => builtin_chain_spec(&opts.chain_spec)?,
+	};
+	let mut archive = run_archive::<SecondaryRocksDb>(spec, config, None)?;
 	archive.drive()?;
-	let running = Arc::new(AtomicBool::new(true));
-	let r = running.clone();
 
-	ctrlc::set_handler(move || {
-		r.store(false, Ordering::SeqCst);
-	})
-	.expect("Error setting Ctrl-C handler");
-	while running.load(Ordering::SeqCst) {}
+	wait_for_ctrlc();
+	log::info!("Shutting down, waiting for running tasks to finish");
 	archive.boxed_shutdown()?;
 
 	Ok(())
 }
 
-fn run_archive<D: ReadOnlyDb + 'static>(
-	chain_spec: &str,
-	config: Option<ArchiveConfig>,
-) -> Result<Box<dyn Archive<Block, D>>> {
-	match chain_spec.to_ascii_lowercase().as_str() {
-		"kusama" | "ksm" => {
-			let spec = polkadot_service::chain_spec::kusama_config().map_err(|err| anyhow!("{}", err))?;
-			let archive = ArchiveBuilder::<Block, wnd_rt::RuntimeApi, KusamaExecutor, D>::with_config(config)
-				.chain_spec(Box::new(spec))
-				.build()?;
-			Ok(Box::new(archive))
-		}
+/// Run one archive for each of the `[[chains]]` in the config.
+/// The chain spec of each chain is loaded from its `chain_spec` file, or picked by its name.
+fn run_chains(config: ArchiveConfig) -> Result<()> {
+	let mut group = ArchiveGroup::<Block, SecondaryRocksDb>::default();
+	for entry in config.chains.iter() {
+		let spec = match &entry.chain_spec {
+			Some(path) => load_chain_spec(path)?,
+			None => builtin_chain_spec(&entry.name)?,
+		};
+		let archive = run_archive::<SecondaryRocksDb>(spec, Some(config.for_chain(entry)), Some(&entry.name))?;
+		group.add(entry.name.as_str(), archive);
+	}
+	group.drive()?;
+	if let Some(metrics) = config.metrics {
+		group.serve(metrics.address);
+	}
+
+	wait_for_ctrlc();
+	log::info!("Shutting down, waiting for running tasks to finish");
+	group.shutdown()?;
+
+	Ok(())
+}
+
+fn wait_for_ctrlc() {
+	let (tx, rx) = mpsc::channel();
+	ctrlc::set_handler(move || {
+		let _ = tx.send(());
+	})
+	.expect("Error setting Ctrl-C handler");
+	rx.recv().expect("Could not receive from Ctrl-C handler");
+}
+
+/// Get the chain spec of one of the built-in chains.
+fn builtin_chain_spec(chain: &str) -> Result<Box<dyn ChainSpec>> {
+	let spec: Box<dyn ChainSpec> = match chain.to_ascii_lowercase().as_str() {
+		"kusama" | "ksm" => Box::new(polkadot_service::chain_spec::kusama_config().map_err(|err| anyhow!("{}", err))?),
 		"westend" | "wnd" => {
-			let spec = polkadot_service::chain_spec::westend_config().map_err(|err| anyhow!("{}", err))?;
-			let archive = ArchiveBuilder::<Block, wnd_rt::RuntimeApi, WestendExecutor, D>::with_config(config)
-				.chain_spec(Box::new(spec))
-				.build()?;
-			Ok(Box::new(archive))
+			Box::new(polkadot_service::chain_spec::westend_config().map_err(|err| anyhow!("{}", err))?)
 		}
 		"polkadot" | "dot" => {
-			let spec = polkadot_service::chain_spec::polkadot_config().map_err(|err| anyhow!("{}", err))?;
-			let archive = ArchiveBuilder::<Block, dot_rt::RuntimeApi, PolkadotExecutor, D>::with_config(config)
-				.chain_spec(Box::new(spec))
-				.build()?;
-			Ok(Box::new(archive))
+			Box::new(polkadot_service::chain_spec::polkadot_config().map_err(|err| anyhow!("{}", err))?)
 		}
-		c => Err(anyhow!("unknown chain {}", c)),
+		c => return Err(anyhow!("unknown chain {}", c)),
+	};
+	Ok(spec)
+}
+
+/// Load a raw or plain chain spec from a JSON file.
+/// Like polkadot itself, the spec is read as a Kusama or Westend spec if its id says so.
+fn load_chain_spec(path: &Path) -> Result<Box<dyn ChainSpec>> {
+	let load_err = |err| anyhow!("Failed to load chain spec {}: {}", path.display(), err);
+	let spec: Box<dyn ChainSpec> = Box::new(PolkadotChainSpec::from_json_file(path.to_path_buf()).map_err(load_err)?);
+	let spec: Box<dyn ChainSpec> = if spec.is_kusama() {
+		Box::new(KusamaChainSpec::from_json_file(path.to_path_buf()).map_err(load_err)?)
+	} else if spec.is_westend() {
+		Box::new(WestendChainSpec::from_json_file(path.to_path_buf()).map_err(load_err)?)
+	} else {
+		spec
+	};
+	Ok(spec)
+}
+
+/// Build the archive of a chain, with the runtime of the chain its spec belongs to.
+/// Chains other than Kusama and Westend are archived with the Polkadot runtime,
+/// which executes from Wasm whenever the runtime on chain is not Polkadot's.
+fn run_archive<D: ReadOnlyDb + 'static>(
+	spec: Box<dyn ChainSpec>,
+	config: Option<ArchiveConfig>,
+	name: Option<&str>,
+) -> Result<Box<dyn Archive<Block, D>>> {
+	if spec.is_kusama() {
+		let archive =
+			ArchiveBuilder::<Block, ksm_rt::RuntimeApi, KusamaExecutor, D>::with_config(config).chain_spec(spec);
+		Ok(Box::new(named(archive, name).build()?))
+	} else if spec.is_westend() {
+		let archive =
+			ArchiveBuilder::<Block, wnd_rt::RuntimeApi, WestendExecutor, D>::with_config(config).chain_spec(spec);
+		Ok(Box::new(named(archive, name).build()?))
+	} else {
+		let archive =
+			ArchiveBuilder::<Block, dot_rt::RuntimeApi, PolkadotExecutor, D>::with_config(config).chain_spec(spec);
+		Ok(Box::new(named(archive, name).build()?))
+	}
+}
+
+/// Name the chain of the archive, if it is one of several.
+fn named<R, E, D>(builder: ArchiveBuilder<Block, R, E, D>, name: Option<&str>) -> ArchiveBuilder<Block, R, E, D> {
+	match name {
+		Some(name) => builder.chain_name(name),
+		None => builder,
 	}
 }
@@ -33,12 +33,11 @@ use sc_client_api::{
 };
 use sc_executor::{NativeExecutionDispatch, NativeExecutor, WasmExecutionMethod};
 use sc_service::{ChainSpec, ClientConfig, LocalCallExecutor};
-use sp_api::ConstructRuntimeApi;
 use sp_core::traits::SpawnNamed;
-use sp_runtime::traits::{BlakeTwo256, Block as BlockT};
+use sp_runtime::traits::Block as BlockT;
 
-pub use self::client::{Client, GetMetadata};
-use crate::{database::ReadOnlyDb, error::BackendError, read_only_backend::ReadOnlyBackend, RuntimeApiCollection};
+pub use self::client::{Client, ExecuteBlock, GetMetadata};
+use crate::{database::ReadOnlyDb, error::BackendError, read_only_backend::ReadOnlyBackend};
 
 /// Archive Client Condensed Type
 pub type TArchiveClient<TBl, TRtApi, TExecDisp, D> = Client<TFullCallExecutor<TBl, TExecDisp, D>, TBl, TRtApi, D>;
@@ -146,13 +145,8 @@ pub fn runtime_api<Block, Runtime, Dispatch, D: ReadOnlyDb + 'static>(
 where
 	Block: BlockT,
 	Block::Hash: FromStr,
-	Runtime: ConstructRuntimeApi<Block, TArchiveClient<Block, Runtime, Dispatch, D>> + Send + Sync + 'static,
-	Runtime::RuntimeApi: RuntimeApiCollection<Block, StateBackend = sc_client_api::StateBackendFor<ReadOnlyBackend<Block, D>, Block>>
-		+ Send
-		+ Sync
-		+ 'static,
+	Runtime: Send + Sync + 'static,
 	Dispatch: NativeExecutionDispatch + 'static,
-	<Runtime::RuntimeApi as sp_api::ApiExt<Block>>::StateBackend: sp_api::StateBackend<BlakeTwo256>,
 {
 	let backend = Arc::new(ReadOnlyBackend::new(db, true));
 
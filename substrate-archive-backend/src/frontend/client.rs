@@ -21,20 +21,21 @@
 //! It's recommended to use the backend (ReadOnlyBackend) for anything that requires getting blocks, querying
 //! storage, or similar operations. Client usage should be reserved for calling into the Runtime
 
-use std::{marker::PhantomData, panic::UnwindSafe, sync::Arc};
+use std::{cell::RefCell, marker::PhantomData, panic::UnwindSafe, sync::Arc};
 
 use codec::{Decode, Encode};
 
 use sc_client_api::{backend::Backend as _, execution_extensions::ExecutionExtensions, CallExecutor};
 use sc_executor::RuntimeVersion;
 use sp_api::{
-	ApiError, ApiRef, CallApiAt, CallApiAtParams, ConstructRuntimeApi, Core as CoreApi, Metadata, ProvideRuntimeApi,
+	ApiError, ApiRef, CallApiAt, CallApiAtParams, ConstructRuntimeApi, Core as CoreApi, InitializeBlock,
+	OverlayedChanges, ProvideRuntimeApi, StorageChanges, StorageTransactionCache,
 };
 use sp_blockchain::HeaderBackend as _;
-use sp_core::NativeOrEncoded;
+use sp_core::{ExecutionContext, NativeOrEncoded};
 use sp_runtime::{
 	generic::BlockId,
-	traits::{Block as BlockT, Header as HeaderT, One},
+	traits::{Block as BlockT, HashFor, Header as HeaderT, One},
 };
 use sp_version::{GetRuntimeVersion, NativeVersion};
 
@@ -50,6 +51,12 @@ pub trait GetMetadata<Block: BlockT>: Send + Sync {
 	fn metadata(&self, id: &BlockId<Block>) -> Result<sp_core::OpaqueMetadata, BackendError>;
 }
 
+/// Trait to execute a block and collect its storage changes, without a runtime API type
+pub trait ExecuteBlock<Block: BlockT, State: sp_api::StateBackend<HashFor<Block>>>: Send + Sync {
+	/// Execute `block` on top of `state`, the state of its parent.
+	fn execute_block(&self, block: Block, state: &State) -> Result<StorageChanges<State, Block>, BackendError>;
+}
+
 /// Archive Client
 pub struct Client<Exec, Block: BlockT, RA, D: ReadOnlyDb> {
 	backend: Arc<ReadOnlyBackend<Block, D>>,
@@ -100,6 +107,40 @@ where
 			Default::default(),
 		))
 	}
+
+	/// Call `method` of the runtime at `at` through the call executor.
+	/// The runtime is executed natively only if the executor has a native runtime that can execute `at`,
+	/// otherwise from the Wasm code on chain.
+	fn call_runtime<R: Encode + Decode + PartialEq>(
+		&self,
+		at: &BlockId<Block>,
+		method: &str,
+		call_data: &[u8],
+		changes: &RefCell<OverlayedChanges>,
+		storage_transaction_cache: Option<&RefCell<StorageTransactionCache<Block, TrieState<Block, D>>>>,
+	) -> Result<R, BackendError>
+	where
+		Exec: CallExecutor<Block, Backend = ReadOnlyBackend<Block, D>>,
+	{
+		let (manager, extensions) = self.execution_extensions.manager_and_extensions(at, ExecutionContext::Importing);
+		let ret = self.executor.contextual_call::<_, fn(_, _) -> _, _, _>(
+			|| Ok(()),
+			at,
+			method,
+			call_data,
+			changes,
+			storage_transaction_cache,
+			InitializeBlock::Skip,
+			manager,
+			None::<fn() -> Result<R, ApiError>>,
+			&None,
+			Some(extensions),
+		)?;
+		match ret {
+			NativeOrEncoded::Native(ret) => Ok(ret),
+			NativeOrEncoded::Encoded(ret) => Ok(R::decode(&mut ret.as_slice())?),
+		}
+	}
 }
 
 impl<Exec, Block, RA, D> GetRuntimeVersion<Block> for Client<Exec, Block, RA, D>
@@ -123,11 +164,36 @@ where
 	D: ReadOnlyDb + 'static,
 	Exec: CallExecutor<Block, Backend = ReadOnlyBackend<Block, D>> + GetRuntimeVersion<Block> + Send + Sync,
 	Block: BlockT,
-	RA: ConstructRuntimeApi<Block, Self> + Send + Sync,
-	RA::RuntimeApi: sp_api::Metadata<Block> + Send + Sync + 'static,
+	RA: Send + Sync,
 {
 	fn metadata(&self, id: &BlockId<Block>) -> Result<sp_core::OpaqueMetadata, BackendError> {
-		self.runtime_api().metadata(id).map_err(Into::into)
+		self.call_runtime(id, "Metadata_metadata", &[], &Default::default(), None)
+	}
+}
+
+impl<Exec, Block, RA, D> ExecuteBlock<Block, TrieState<Block, D>> for Client<Exec, Block, RA, D>
+where
+	D: ReadOnlyDb + 'static,
+	Exec: CallExecutor<Block, Backend = ReadOnlyBackend<Block, D>> + GetRuntimeVersion<Block> + Send + Sync,
+	Block: BlockT,
+	RA: Send + Sync,
+{
+	fn execute_block(
+		&self,
+		block: Block,
+		state: &TrieState<Block, D>,
+	) -> Result<StorageChanges<TrieState<Block, D>, Block>, BackendError> {
+		let parent_hash = *block.header().parent_hash();
+		let changes = RefCell::new(OverlayedChanges::default());
+		let cache = RefCell::new(StorageTransactionCache::default());
+		self.call_runtime::<()>(
+			&BlockId::Hash(parent_hash),
+			"Core_execute_block",
+			&block.encode(),
+			&changes,
+			Some(&cache),
+		)?;
+		changes.into_inner().into_storage_changes(state, None, parent_hash, cache.into_inner()).map_err(Into::into)
 	}
 }
 
@@ -29,11 +29,10 @@ mod util;
 use std::sync::Arc;
 
 use sc_client_api::Backend as BackendT;
-use sp_api::{CallApiAt, ConstructRuntimeApi, ProvideRuntimeApi};
-use sp_runtime::traits::{BlakeTwo256, Block as BlockT};
+use sp_runtime::traits::Block as BlockT;
 use sp_version::GetRuntimeVersion;
 
-use self::frontend::GetMetadata;
+use self::frontend::{ExecuteBlock, GetMetadata};
 // re-exports
 pub use self::{
 	database::{KeyValuePair, ReadOnlyDb, SecondaryRocksDb},
@@ -45,49 +44,19 @@ pub use self::{
 
 pub type Meta<B> = Arc<dyn GetMetadata<B>>;
 
-/// supertrait for accessing methods that rely on internal runtime api
-pub trait ApiAccess<Block, Backend, Runtime>:
-	ProvideRuntimeApi<Block, Api = Runtime::RuntimeApi>
-	+ Sized
-	+ Send
-	+ Sync
-	+ CallApiAt<Block, StateBackend = Backend::State>
-	+ GetMetadata<Block>
-	+ GetRuntimeVersion<Block>
+/// supertrait for accessing methods that call into the runtime
+pub trait ApiAccess<Block, Backend>:
+	ExecuteBlock<Block, Backend::State> + GetMetadata<Block> + GetRuntimeVersion<Block> + Sized + Send + Sync
 where
 	Block: BlockT,
 	Backend: BackendT<Block>,
-	Runtime: ConstructRuntimeApi<Block, Self>,
 {
 }
 
-impl<Client, Block, Backend, Runtime> ApiAccess<Block, Backend, Runtime> for Client
+impl<Client, Block, Backend> ApiAccess<Block, Backend> for Client
 where
 	Block: BlockT,
-	Runtime: ConstructRuntimeApi<Block, Self>,
 	Backend: BackendT<Block>,
-	Client: ProvideRuntimeApi<Block, Api = Runtime::RuntimeApi>
-		+ CallApiAt<Block, StateBackend = Backend::State>
-		+ GetMetadata<Block>
-		+ GetRuntimeVersion<Block>
-		+ Sized
-		+ Send
-		+ Sync,
-{
-}
-
-/// A set of APIs that runtimes must implement in order to be compatible with substrate-archive.
-pub trait RuntimeApiCollection<Block>: sp_api::ApiExt<Block>
-where
-	Block: BlockT,
-	<Self as sp_api::ApiExt<Block>>::StateBackend: sp_api::StateBackend<BlakeTwo256>,
-{
-}
-
-impl<Api, Block> RuntimeApiCollection<Block> for Api
-where
-	Block: BlockT,
-	Api: sp_api::ApiExt<Block>,
-	<Self as sp_api::ApiExt<Block>>::StateBackend: sp_api::StateBackend<BlakeTwo256>,
+	Client: ExecuteBlock<Block, Backend::State> + GetMetadata<Block> + GetRuntimeVersion<Block> + Sized + Send + Sync,
 {
 }
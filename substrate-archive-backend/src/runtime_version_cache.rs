@@ -19,7 +19,10 @@
 use std::{
 	collections::hash_map::DefaultHasher,
 	hash::{Hash, Hasher as _},
-	sync::Arc,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc,
+	},
 };
 
 use arc_swap::ArcSwap;
@@ -48,6 +51,10 @@ pub struct RuntimeVersionCache<B: BlockT, D: ReadOnlyDb> {
 	versions: ArcSwap<HashMap<u64, RuntimeVersion>>,
 	backend: Arc<ReadOnlyBackend<B, D>>,
 	exec: WasmExecutor,
+	/// Number of lookups answered from the cache
+	hits: AtomicU64,
+	/// Number of lookups that required reading the runtime version from WASM
+	misses: AtomicU64,
 }
 
 impl<B: BlockT, D: ReadOnlyDb + 'static> RuntimeVersionCache<B, D> {
@@ -76,7 +83,23 @@ impl<B: BlockT, D: ReadOnlyDb + 'static> RuntimeVersionCache<B, D> {
 
 		// TODO: https://github.com/paritytech/substrate-archive/issues/247
 		let exec = WasmExecutor::new(WasmExecutionMethod::Interpreted, Some(128), funs, 1, None);
-		Self { versions: ArcSwap::from_pointee(HashMap::new()), backend, exec }
+		Self {
+			versions: ArcSwap::from_pointee(HashMap::new()),
+			backend,
+			exec,
+			hits: AtomicU64::new(0),
+			misses: AtomicU64::new(0),
+		}
+	}
+
+	/// Total number of version lookups served from the cache.
+	pub fn hits(&self) -> u64 {
+		self.hits.load(Ordering::Relaxed)
+	}
+
+	/// Total number of version lookups that missed the cache.
+	pub fn misses(&self) -> u64 {
+		self.misses.load(Ordering::Relaxed)
 	}
 
 	/// Get a version of the runtime for some Block Hash
@@ -88,8 +111,10 @@ impl<B: BlockT, D: ReadOnlyDb + 'static> RuntimeVersionCache<B, D> {
 
 		let code_hash = make_hash(&code);
 		if self.versions.load().contains_key(&code_hash) {
+			self.hits.fetch_add(1, Ordering::Relaxed);
 			Ok(self.versions.load().get(&code_hash).cloned())
 		} else {
+			self.misses.fetch_add(1, Ordering::Relaxed);
 			log::debug!("Adding new runtime code hash to cache: {:#X?}", code_hash);
 			let mut ext = BasicExternalities::default();
 			ext.register_extension(sp_core::traits::ReadRuntimeVersionExt::new(self.exec.clone()));
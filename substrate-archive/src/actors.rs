@@ -19,17 +19,23 @@
 mod actor_pool;
 mod workers;
 
-use std::{marker::PhantomData, panic::AssertUnwindSafe, sync::Arc, time::Duration};
+use std::{
+	convert::TryFrom,
+	marker::PhantomData,
+	net::SocketAddr,
+	panic::AssertUnwindSafe,
+	sync::Arc,
+	time::{Duration, Instant},
+};
 
 use coil::Job as _;
 use futures::{future::BoxFuture, FutureExt};
-use hashbrown::HashSet;
+use parking_lot::Mutex;
 use serde::{de::DeserializeOwned, Deserialize};
+use sqlx::Connection as _;
 use xtra::{prelude::*, spawn::Smol, Disconnected};
 
-use sc_client_api::backend;
-use sp_api::{ApiExt, ConstructRuntimeApi};
-use sp_block_builder::BlockBuilder as BlockBuilderApi;
+use sp_blockchain::HeaderBackend as _;
 use sp_runtime::traits::{Block as BlockT, Header as _, NumberFor};
 
 use substrate_archive_backend::{ApiAccess, Meta, ReadOnlyBackend, ReadOnlyDb};
@@ -41,12 +47,29 @@ pub use self::{
 };
 use crate::{
 	archive::Archive,
-	database::{models::BlockModelDecoder, queries, Channel, Listener},
-	error::Result,
-	tasks::{Environment, TaskExecutor},
+	database::{
+		models::{BlockModel, BlockModelDecoder},
+		queries, BlockSelection, Channel, Listener, PoolConfig,
+	},
+	error::{ArchiveError, Result},
+	http,
+	metrics::Metrics,
+	parachain::{self, RelayLink},
+	partitions,
+	retention::{self, RetentionConfig},
+	sink::Sink,
+	status::{ActorStatus, ArchiveStatus, Sample, StatusTracker},
+	storage_filter::StorageFilter,
+	tasks::{Environment, JobErrors, RunningTasks, TaskExecutor},
 	types::Die,
+	watch::Watcher,
 };
 
+/// Number of blocks loaded at once, when queueing blocks to be executed again.
+const REEXECUTE_BATCH: i64 = 10_000;
+/// Number of unexecuted blocks loaded at once, when restoring missing storage on startup.
+const RESTORE_BATCH: i64 = 10_000;
+
 // TODO: Split this up into two objects
 // System should be a factory that produces objects that should be spawned
 
@@ -58,9 +81,27 @@ where
 {
 	pub backend: Arc<ReadOnlyBackend<B, D>>,
 	pub pg_url: String,
+	/// Url of a read replica, for read-heavy queries.
+	pub(crate) read_url: Option<String>,
 	pub meta: Meta<B>,
 	pub control: ControlConfig,
 	pub tracing_targets: Option<String>,
+	pub metrics: Metrics,
+	/// Address to serve metrics on, if enabled.
+	pub metrics_address: Option<SocketAddr>,
+	pub status: StatusTracker,
+	/// Sinks to write to in addition to PostgreSQL.
+	pub sinks: Vec<Arc<dyn Sink<B>>>,
+	/// Relay chain storage to link the blocks to, if archiving a parachain.
+	pub(crate) relay_link: Option<RelayLink>,
+	/// Retention policies applied periodically, if old data is deleted.
+	pub(crate) retention: Option<RetentionConfig>,
+	/// Storage changes which are indexed.
+	pub(crate) storage_filter: StorageFilter,
+	/// Whether storage values are kept once in `storage_values`.
+	pub(crate) deduplicate_values: bool,
+	/// Connection pool of the database actors and the job queue.
+	pub(crate) pool: PoolConfig,
 }
 
 impl<B: BlockT + Unpin, D: ReadOnlyDb> Clone for SystemConfig<B, D>
@@ -71,9 +112,19 @@ where
 		SystemConfig {
 			backend: Arc::clone(&self.backend),
 			pg_url: self.pg_url.clone(),
+			read_url: self.read_url.clone(),
 			meta: self.meta.clone(),
 			control: self.control,
 			tracing_targets: self.tracing_targets.clone(),
+			metrics: self.metrics.clone(),
+			metrics_address: self.metrics_address,
+			status: self.status.clone(),
+			sinks: self.sinks.clone(),
+			relay_link: self.relay_link.clone(),
+			retention: self.retention.clone(),
+			storage_filter: self.storage_filter.clone(),
+			deduplicate_values: self.deduplicate_values,
+			pool: self.pool.clone(),
 		}
 	}
 }
@@ -96,6 +147,52 @@ pub struct ControlConfig {
 	/// Maximum amount of blocks to index at once.
 	#[serde(default = "default_max_block_load")]
 	pub(crate) max_block_load: u32,
+	/// Maximum amount of time to wait for running tasks to finish on shutdown, in seconds.
+	/// Tasks still running afterwards are abandoned, and retried on the next start.
+	#[serde(default = "default_shutdown_timeout")]
+	pub(crate) shutdown_timeout: u64,
+	/// Number of times the execution of a block is retried, before it is moved to the `failed_blocks` table.
+	/// Retrying forever if 0.
+	#[serde(default = "default_max_retries")]
+	pub(crate) max_retries: u32,
+	/// Order in which blocks are executed.
+	#[serde(default)]
+	pub(crate) execution_order: ExecutionOrder,
+	/// Blocks at most this far behind the best block of the node are considered near the head of the chain.
+	#[serde(default = "default_head_distance")]
+	pub(crate) head_distance: u32,
+	/// Number of blocks in each partition of `storage` and `state_traces`.
+	#[serde(default = "default_partition_blocks")]
+	pub(crate) partition_blocks: u32,
+}
+
+/// Order in which blocks are executed.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionOrder {
+	/// Blocks near the head of the chain are executed before blocks of the historical backfill,
+	/// so that the head of the chain is queryable within seconds.
+	HeadFirst,
+	/// Blocks of the historical backfill are executed before blocks near the head of the chain,
+	/// so that the history is complete as soon as possible.
+	HistoryFirst,
+}
+
+impl Default for ExecutionOrder {
+	fn default() -> Self {
+		Self::HeadFirst
+	}
+}
+
+impl ExecutionOrder {
+	/// The priority of executing a block, either `near_head` of the chain or of the backfill.
+	/// Blocks of a higher priority are executed first.
+	pub(crate) fn priority(&self, near_head: bool) -> i32 {
+		match (self, near_head) {
+			(ExecutionOrder::HeadFirst, true) | (ExecutionOrder::HistoryFirst, false) => 1,
+			_ => 0,
+		}
+	}
 }
 
 impl Default for ControlConfig {
@@ -106,6 +203,11 @@ impl Default for ControlConfig {
 			task_timeout: default_task_timeout(),
 			max_tasks: default_max_tasks(),
 			max_block_load: default_max_block_load(),
+			shutdown_timeout: default_shutdown_timeout(),
+			max_retries: default_max_retries(),
+			execution_order: ExecutionOrder::default(),
+			head_distance: default_head_distance(),
+			partition_blocks: default_partition_blocks(),
 		}
 	}
 }
@@ -130,18 +232,102 @@ const fn default_max_block_load() -> u32 {
 	100_000
 }
 
+const fn default_shutdown_timeout() -> u64 {
+	60
+}
+
+const fn default_max_retries() -> u32 {
+	5
+}
+
+const fn default_head_distance() -> u32 {
+	256
+}
+
+const fn default_partition_blocks() -> u32 {
+	100_000
+}
+
 impl<B: BlockT + Unpin, D: ReadOnlyDb> SystemConfig<B, D>
 where
 	B::Hash: Unpin,
 {
+	/// A configuration which indexes all storage into the database at `pg_url`, without serving metrics.
+	/// The other options are set with the `with_*` methods.
 	pub fn new(
 		backend: Arc<ReadOnlyBackend<B, D>>,
 		pg_url: String,
 		meta: Meta<B>,
 		control: ControlConfig,
 		tracing_targets: Option<String>,
+		metrics: Metrics,
 	) -> Self {
-		Self { backend, pg_url, meta, control, tracing_targets }
+		Self {
+			backend,
+			pg_url,
+			read_url: None,
+			meta,
+			control,
+			tracing_targets,
+			metrics,
+			metrics_address: None,
+			status: StatusTracker::default(),
+			sinks: Vec::new(),
+			relay_link: None,
+			retention: None,
+			storage_filter: StorageFilter::default(),
+			deduplicate_values: false,
+			pool: PoolConfig::default(),
+		}
+	}
+
+	/// Route read-heavy queries to the read replica at `read_url`.
+	pub fn with_read_url(mut self, read_url: Option<String>) -> Self {
+		self.read_url = read_url;
+		self
+	}
+
+	/// Serve metrics on `address`.
+	pub fn with_metrics_address(mut self, address: Option<SocketAddr>) -> Self {
+		self.metrics_address = address;
+		self
+	}
+
+	/// Report the status of the archive to `status`.
+	pub fn with_status(mut self, status: StatusTracker) -> Self {
+		self.status = status;
+		self
+	}
+
+	/// Write to `sinks` in addition to PostgreSQL.
+	pub fn with_sinks(mut self, sinks: Vec<Arc<dyn Sink<B>>>) -> Self {
+		self.sinks = sinks;
+		self
+	}
+
+	/// Link the blocks to the relay chain of `relay_link`.
+	pub(crate) fn with_relay_link(mut self, relay_link: Option<RelayLink>) -> Self {
+		self.relay_link = relay_link;
+		self
+	}
+
+	/// Apply the retention policies of `retention` periodically.
+	pub(crate) fn with_retention(mut self, retention: Option<RetentionConfig>) -> Self {
+		self.retention = retention;
+		self
+	}
+
+	/// Index the storage changes kept by `filter`, keeping their values once if `deduplicate_values`.
+	pub(crate) fn with_storage(mut self, filter: StorageFilter, deduplicate_values: bool) -> Self {
+		self.storage_filter = filter;
+		self.deduplicate_values = deduplicate_values;
+		self
+	}
+
+	/// Configure the connection pool of the database actors and the job queue.
+	pub(crate) fn with_pool(mut self, pool: PoolConfig) -> Self {
+		self.pool = pool;
+		self
 	}
 
 	pub fn backend(&self) -> &Arc<ReadOnlyBackend<B, D>> {
@@ -155,6 +341,14 @@ where
 	pub fn meta(&self) -> &Meta<B> {
 		&self.meta
 	}
+
+	pub fn metrics(&self) -> &Metrics {
+		&self.metrics
+	}
+
+	pub fn status(&self) -> &StatusTracker {
+		&self.status
+	}
 }
 
 struct Actors<B: BlockT + Unpin, D: ReadOnlyDb + 'static>
@@ -168,9 +362,39 @@ where
 	db_pool: Address<ActorPool<DatabaseActor<B>>>,
 }
 
+impl<B: BlockT + Unpin, D: ReadOnlyDb + 'static> Clone for Actors<B, D>
+where
+	B::Hash: Unpin,
+	NumberFor<B>: Into<u32>,
+{
+	fn clone(&self) -> Self {
+		Self {
+			storage: self.storage.clone(),
+			blocks: self.blocks.clone(),
+			metadata: self.metadata.clone(),
+			db_pool: self.db_pool.clone(),
+		}
+	}
+}
+
+impl<B: BlockT + Unpin, D: ReadOnlyDb + 'static> Actors<B, D>
+where
+	B::Hash: Unpin,
+	NumberFor<B>: Into<u32>,
+{
+	fn status(&self) -> ActorStatus {
+		ActorStatus {
+			blocks: self.blocks.is_connected(),
+			metadata: self.metadata.is_connected(),
+			storage: self.storage.is_connected(),
+			db_pool: self.db_pool.is_connected(),
+		}
+	}
+}
+
 /// Control the execution of the indexing engine.
 /// Will exit on Drop.
-pub struct System<B, R, C, D>
+pub struct System<B, C, D>
 where
 	D: ReadOnlyDb + 'static,
 	B: BlockT + Unpin,
@@ -182,21 +406,16 @@ where
 	kill_tx: flume::Sender<()>,
 	/// handle to the futures runtime indexing the running chain
 	handle: jod_thread::JoinHandle<Result<()>>,
-	_marker: PhantomData<(B, R, C, D)>,
+	/// connection pool of the database actors and the job queue, once they are started
+	pool: Arc<Mutex<Option<sqlx::PgPool>>>,
+	_marker: PhantomData<(B, C, D)>,
 }
 
-impl<B, R, C, D> System<B, R, C, D>
+impl<B, C, D> System<B, C, D>
 where
 	D: ReadOnlyDb + 'static,
 	B: BlockT + Unpin + DeserializeOwned,
-	R: ConstructRuntimeApi<B, C> + Send + Sync + 'static,
-	R::RuntimeApi: BlockBuilderApi<B>
-		+ sp_api::Metadata<B>
-		+ ApiExt<B, StateBackend = backend::StateBackendFor<ReadOnlyBackend<B, D>, B>>
-		+ Send
-		+ Sync
-		+ 'static,
-	C: ApiAccess<B, ReadOnlyBackend<B, D>, R> + 'static,
+	C: ApiAccess<B, ReadOnlyBackend<B, D>> + 'static,
 	NumberFor<B>: Into<u32> + From<u32> + Unpin,
 	B::Hash: Unpin,
 	B::Header: serde::de::DeserializeOwned,
@@ -211,9 +430,10 @@ where
 		client_api: Arc<C>,
 		config: SystemConfig<B, D>,
 	) -> Result<Self> {
-		let (start_tx, kill_tx, handle) = Self::start(config.clone(), client_api);
+		let pool = Arc::new(Mutex::new(None));
+		let (start_tx, kill_tx, handle) = Self::start(config.clone(), client_api, pool.clone());
 
-		Ok(Self { config, start_tx, kill_tx, handle, _marker: PhantomData })
+		Ok(Self { config, start_tx, kill_tx, handle, pool, _marker: PhantomData })
 	}
 
 	fn drive(&self) {
@@ -221,9 +441,11 @@ where
 	}
 
 	/// Start the actors and begin driving their execution
+	/// The connection pool is put into `pool` once the actors are started.
 	pub fn start(
 		conf: SystemConfig<B, D>,
 		client: Arc<C>,
+		pool: Arc<Mutex<Option<sqlx::PgPool>>>,
 	) -> (flume::Sender<()>, flume::Sender<()>, jod_thread::JoinHandle<Result<()>>) {
 		let (tx_start, rx_start) = flume::bounded(1);
 		let (tx_kill, rx_kill) = flume::bounded(1);
@@ -232,36 +454,80 @@ where
 			// block until we receive the message to start
 			let _ = rx_start.recv();
 
-			smol::block_on(Self::main_loop(conf, rx_kill, client))?;
+			smol::block_on(Self::main_loop(conf, rx_kill, client, pool))?;
 			Ok(())
 		});
 
 		(tx_start, tx_kill, handle)
 	}
 
-	async fn main_loop(conf: SystemConfig<B, D>, rx: flume::Receiver<()>, client: Arc<C>) -> Result<()> {
+	async fn main_loop(
+		conf: SystemConfig<B, D>,
+		rx: flume::Receiver<()>,
+		client: Arc<C>,
+		shared_pool: Arc<Mutex<Option<sqlx::PgPool>>>,
+	) -> Result<()> {
 		let actors = Self::spawn_actors(conf.clone()).await?;
 		let pool = actors.db_pool.send(GetState::Pool.into()).await??.pool();
-		let listener = Self::init_listeners(conf.pg_url()).await?;
+		*shared_pool.lock() = Some(pool.clone());
+		let read_pool = actors.db_pool.send(GetState::ReadPool.into()).await??.pool();
+		let listener = Self::init_listeners(&conf).await?;
 		let mut conn = pool.acquire().await?;
-		Self::restore_missing_storage(&mut *conn).await?;
-		let env = Environment::<B, R, C, D>::new(
+		Self::create_partitions(&conf, &mut *conn).await?;
+		Self::restore_missing_storage(&mut *read_pool.acquire().await?, &mut *conn, conf.control.execution_order)
+			.await?;
+		// both tasks are cancelled once dropped at the end of the main loop
+		let _partitions = smol::spawn(Self::manage_partitions(conf.clone(), pool.clone()));
+		let _sampler = smol::spawn(Self::sample(conf.clone(), pool.clone(), read_pool, actors.clone()));
+		let _metrics_server = conf.metrics_address.map(|addr| {
+			let metrics = conf.metrics().clone();
+			let status = conf.status().clone();
+			smol::spawn(async move {
+				let serve = http::serve(addr, move |path| {
+					let response = match path.as_str() {
+						"/health" | "/ready" | "/status" => status.respond(&path),
+						_ => metrics.respond(&path),
+					};
+					async move { response }
+				});
+				if let Err(e) = serve.await {
+					log::error!("Metrics endpoint stopped: {}", e);
+				}
+			})
+		});
+		let _relay_linker = conf.relay_link.clone().map(|link| smol::spawn(parachain::link::<B>(pool.clone(), link)));
+		let _retention = conf.retention.clone().map(|config| smol::spawn(retention::run(pool.clone(), config)));
+		let running = RunningTasks::default();
+		let errors = JobErrors::default();
+		let _dead_letter = (conf.control.max_retries > 0)
+			.then(|| smol::spawn(Self::dead_letter(pool.clone(), conf.control.max_retries, errors.clone())));
+		let env = Environment::<B, C, D>::new(
 			conf.backend().clone(),
 			client,
 			actors.storage.clone(),
 			conf.tracing_targets.clone(),
+			conf.metrics().clone(),
+			running.clone(),
+			errors,
 		);
 		let env = AssertUnwindSafe(env);
 
 		let runner = coil::Runner::builder(env, TaskExecutor, &pool)
-			.register_job::<crate::tasks::execute_block::Job<B, R, C, D>>()
+			.register_job::<crate::tasks::execute_block::Job<B, C, D>>()
 			.num_threads(conf.control.task_workers)
 			// times out if tasks don't start execution on the threadpool within 20 seconds.
 			.timeout(Duration::from_secs(conf.control.task_timeout))
 			.max_tasks(conf.control.max_tasks)
 			.build()?;
 
+		// coil runs the jobs of its queue in the order they were queued,
+		// so it only gets the next few pending jobs at a time
+		let queued = i64::try_from(conf.control.max_tasks.saturating_mul(2)).unwrap_or(i64::MAX);
+		conf.status().set_running(true);
 		loop {
+			if let Err(e) = queries::release_pending_jobs(&mut *conn, queued).await {
+				log::error!("Failed to queue pending tasks: {}", e);
+			}
 			let tasks = runner.run_all_sync_tasks().fuse();
 			futures::pin_mut!(tasks);
 			futures::select! {
@@ -275,19 +541,137 @@ where
 						Err(e) => log::error!("{:?}", e),
 					}
 				},
-				_ = rx.recv_async() => break,
+				_ = rx.recv_async() => {
+					conf.status().set_running(false);
+					// stop crawling for new blocks, and no longer fetch new tasks
+					let _ = actors.blocks.send(Die).await;
+					let timeout = Duration::from_secs(conf.control.shutdown_timeout);
+					log::info!("Waiting up to {:?} for running tasks to finish", timeout);
+					let deadline = Instant::now() + timeout;
+					let mut timer = smol::Timer::at(deadline).fuse();
+					futures::select! {
+						t = tasks => {
+							if let Err(e) = t {
+								log::error!("{:?}", e);
+							}
+						},
+						_ = timer => {},
+					}
+					if !running.wait_until(deadline).await {
+						log::warn!("{} tasks did not finish in time. They will be retried on the next start", running.count());
+					}
+					break;
+				},
 			}
 		}
+		// storage and traces of finished tasks are flushed before the database actors stop
 		Self::kill_actors(actors).await?;
 		listener.kill_async().await;
+		log::info!("Archive stopped");
+		Ok(())
+	}
+
+	/// Periodically update the metrics and status which are sampled rather than recorded as they happen.
+	async fn sample(conf: SystemConfig<B, D>, pool: sqlx::PgPool, read_pool: sqlx::PgPool, actors: Actors<B, D>) {
+		loop {
+			conf.status().set_actors(actors.status());
+			if let Err(e) = Self::update_metrics(&conf, &pool, &read_pool).await {
+				log::warn!("Failed to update metrics: {}", e);
+				conf.status().sample_failed(e);
+			}
+			smol::Timer::after(Duration::from_secs(5)).await;
+		}
+	}
+
+	async fn update_metrics(conf: &SystemConfig<B, D>, pool: &sqlx::PgPool, read_pool: &sqlx::PgPool) -> Result<()> {
+		let metrics = conf.metrics();
+		let info = conf.backend().info();
+		let best_head: u32 = info.best_number.into();
+		let finalized_head: u32 = info.finalized_number.into();
+		metrics.best_block.set(best_head.into());
+		metrics.finalized_block.set(finalized_head.into());
+		metrics.db_connections.set(pool.size().into());
+		metrics.db_connections_idle.set(pool.num_idle() as i64);
+
+		let mut conn = read_pool.acquire().await?;
+		let indexed_head = queries::max_block(&mut conn).await?;
+		if let Some(max) = indexed_head {
+			metrics.indexed_head.set(max.into());
+		}
+		let pending_jobs = queries::queue_depth(&mut conn).await?;
+		metrics.task_queue_depth.set(pending_jobs as i64);
+		let failed_jobs = queries::failed_jobs(&mut conn).await?;
+		let reexecution_remaining = match conf.status().reexecution() {
+			Some(blocks) => Some(queries::count_unexecuted(&mut conn, &blocks).await?),
+			None => None,
+		};
+		conf.status().update(Sample {
+			indexed_head,
+			best_head,
+			finalized_head,
+			pending_jobs,
+			failed_jobs,
+			reexecution_remaining,
+		});
 		Ok(())
 	}
 
+	/// Create the partitions of `storage` and `state_traces` ahead of the best block of the node.
+	async fn create_partitions(conf: &SystemConfig<B, D>, conn: &mut sqlx::PgConnection) -> Result<()> {
+		let best_head: u32 = conf.backend().info().best_number.into();
+		partitions::create_ahead(conn, best_head, conf.control.partition_blocks).await?;
+		Ok(())
+	}
+
+	/// Keep creating partitions, as the node follows the chain.
+	async fn manage_partitions(conf: SystemConfig<B, D>, pool: sqlx::PgPool) {
+		loop {
+			smol::Timer::after(Duration::from_secs(60)).await;
+			let created = async {
+				let mut conn = pool.acquire().await?;
+				Self::create_partitions(&conf, &mut *conn).await
+			};
+			if let Err(e) = created.await {
+				log::error!("Failed to create partitions: {}", e);
+			}
+		}
+	}
+
+	/// Periodically move blocks which failed execution `max_retries` times into `failed_blocks`,
+	/// so that they don't keep being retried.
+	async fn dead_letter(pool: sqlx::PgPool, max_retries: u32, errors: JobErrors) {
+		loop {
+			smol::Timer::after(Duration::from_secs(30)).await;
+			let moved = async {
+				let mut conn = pool.acquire().await?;
+				queries::dead_letter_jobs::<B>(&mut conn, max_retries, |hash| errors.take(hash)).await
+			};
+			match moved.await {
+				Ok(0) => {}
+				Ok(n) => log::warn!("{} blocks failed execution {} times. Moved to `failed_blocks`", n, max_retries),
+				Err(e) => log::error!("Failed to move failed blocks: {}", e),
+			}
+		}
+	}
+
 	async fn spawn_actors(conf: SystemConfig<B, D>) -> Result<Actors<B, D>> {
-		let db = workers::DatabaseActor::<B>::new(conf.pg_url().into()).await?;
+		let db = workers::DatabaseActor::<B>::new(
+			conf.pg_url().into(),
+			conf.read_url.clone(),
+			conf.metrics().clone(),
+			conf.sinks.clone(),
+			conf.deduplicate_values,
+			conf.control.partition_blocks,
+			&conf.pool,
+		)
+		.await?;
+		conf.metrics().db_actors.set(conf.control.db_actor_pool_size as i64);
+		let watcher = Watcher::new(db.database().clone(), conf.metrics().clone());
 		let db_pool =
 			actor_pool::ActorPool::new(db, conf.control.db_actor_pool_size).create(None).spawn(&mut Smol::Global);
-		let storage = workers::StorageAggregator::new(db_pool.clone()).create(None).spawn(&mut Smol::Global);
+		let storage = workers::StorageAggregator::new(db_pool.clone(), watcher, conf.storage_filter.clone())
+			.create(None)
+			.spawn(&mut Smol::Global);
 		let metadata = workers::MetadataActor::new(db_pool.clone(), conf.meta().clone())
 			.await?
 			.create(None)
@@ -309,12 +693,22 @@ where
 		Ok(())
 	}
 
-	async fn init_listeners(pg_url: &str) -> Result<Listener> {
-		Listener::builder(pg_url, move |notif, conn| {
+	async fn init_listeners(conf: &SystemConfig<B, D>) -> Result<Listener> {
+		let backend = conf.backend().clone();
+		let control = conf.control;
+		Listener::builder(conf.pg_url(), move |notif, conn| {
+			let backend = backend.clone();
 			async move {
 				let sql_block = queries::get_full_block_by_id(conn, notif.id).await?;
 				let b = sql_block.into_block_and_spec()?;
-				crate::tasks::execute_block::<B, R, C, D>(b.0, PhantomData).enqueue(conn).await?;
+				let number: u32 = (*b.0.header().number()).into();
+				let hash = b.0.hash();
+				let best: u32 = backend.info().best_number.into();
+				let priority = control.execution_order.priority(number.saturating_add(control.head_distance) >= best);
+				let mut tx = conn.begin().await?;
+				crate::tasks::execute_block::<B, C, D>(b.0, PhantomData).enqueue(&mut *tx).await?;
+				queries::defer_last_job(&mut tx, hash.as_ref(), priority).await?;
+				tx.commit().await?;
 				Ok(())
 			}
 			.boxed()
@@ -327,46 +721,61 @@ where
 	/// Checks if any blocks that should be executed are missing
 	/// from the task queue.
 	/// If any are found, they are re-queued.
-	async fn restore_missing_storage(conn: &mut sqlx::PgConnection) -> Result<()> {
-		let blocks: HashSet<u32> = queries::get_all_blocks::<B>(conn)
-			.await?
-			.map(|b| Ok((*b?.header().number()).into()))
-			.collect::<Result<_>>()?;
-		let mut missing_storage_blocks = queries::blocks_storage_intersection(conn).await?;
-		let difference: HashSet<u32> = missing_storage_blocks
-			.iter()
-			.map(|b| b.block_num as u32)
-			.collect::<HashSet<u32>>()
-			.difference(&blocks)
-			.copied()
-			.collect();
-		missing_storage_blocks.retain(|b| difference.contains(&(b.block_num as u32)));
-		let jobs: Vec<crate::tasks::execute_block::Job<B, R, C, D>> =
-			BlockModelDecoder::with_vec(missing_storage_blocks)?
-				.into_iter()
-				.map(|b| crate::tasks::execute_block::<B, R, C, D>(b.inner.block, PhantomData))
-				.collect();
-		log::info!("Restoring {} missing storage entries. This could take a few minutes...", jobs.len());
-		coil::JobExt::enqueue_batch(jobs, &mut *conn).await?;
-		log::info!("Storage restored");
+	/// Only blocks which were not executed yet are scanned, in batches, through `read`,
+	/// and only the jobs of each batch are looked up in the queue, through `conn`.
+	/// The blocks are queued with the priority of the backfill.
+	async fn restore_missing_storage(
+		read: &mut sqlx::PgConnection,
+		conn: &mut sqlx::PgConnection,
+		order: ExecutionOrder,
+	) -> Result<()> {
+		let tagged = queries::tag_queued_jobs::<B>(conn).await?;
+		if tagged > 0 {
+			log::info!("Recorded the block hashes of {} queued jobs", tagged);
+		}
+		let (mut after, mut restored) = (0, 0);
+		loop {
+			let models = queries::unexecuted_blocks(read, after, RESTORE_BATCH).await?;
+			after = match models.last() {
+				Some(last) => last.id,
+				None => break,
+			};
+			let missing = Self::not_queued(conn, models).await?;
+			restored += missing.len();
+			Self::enqueue_blocks(missing, order.priority(false), conn).await?;
+		}
+		log::info!("Restored {} missing storage entries", restored);
+		Ok(())
+	}
+
+	/// Decode the blocks of `models` which are not queued for execution.
+	async fn not_queued(conn: &mut sqlx::PgConnection, models: Vec<BlockModel>) -> Result<Vec<B>> {
+		let hashes = models.iter().map(|m| m.hash.clone()).collect::<Vec<_>>();
+		let queued = queries::queued_blocks(conn, &hashes).await?;
+		let models = models.into_iter().filter(|m| !queued.contains(&m.hash)).collect();
+		Ok(BlockModelDecoder::<B>::with_vec(models)?.into_iter().map(|b| b.inner.block).collect())
+	}
+
+	/// Queue `blocks` for execution with `priority`, recording the hash of each block on its job.
+	async fn enqueue_blocks(blocks: Vec<B>, priority: i32, conn: &mut sqlx::PgConnection) -> Result<()> {
+		let mut tx = conn.begin().await?;
+		for block in blocks {
+			let hash = block.hash();
+			crate::tasks::execute_block::<B, C, D>(block, PhantomData).enqueue(&mut *tx).await?;
+			queries::defer_last_job(&mut tx, hash.as_ref(), priority).await?;
+		}
+		tx.commit().await?;
 		Ok(())
 	}
 }
 
 #[async_trait::async_trait(?Send)]
-impl<B, R, C, D> Archive<B, D> for System<B, R, C, D>
+impl<B, C, D> Archive<B, D> for System<B, C, D>
 where
 	D: ReadOnlyDb + 'static,
 	B: BlockT + Unpin + DeserializeOwned,
 	<B as BlockT>::Hash: Unpin,
-	R: ConstructRuntimeApi<B, C> + Send + Sync + 'static,
-	R::RuntimeApi: BlockBuilderApi<B>
-		+ sp_api::Metadata<B>
-		+ ApiExt<B, StateBackend = backend::StateBackendFor<ReadOnlyBackend<B, D>, B>>
-		+ Send
-		+ Sync
-		+ 'static,
-	C: ApiAccess<B, ReadOnlyBackend<B, D>, R> + 'static,
+	C: ApiAccess<B, ReadOnlyBackend<B, D>> + 'static,
 	NumberFor<B>: Into<u32> + From<u32> + Unpin,
 	B::Hash: Unpin,
 	B::Header: serde::de::DeserializeOwned,
@@ -397,4 +806,30 @@ where
 	fn context(&self) -> &SystemConfig<B, D> {
 		&self.config
 	}
+
+	fn status(&self) -> ArchiveStatus {
+		self.config.status().snapshot()
+	}
+
+	async fn reexecute(&self, blocks: BlockSelection) -> Result<u64> {
+		// the actors are not running yet if the pool is not set
+		let pool = self.pool.lock().clone().ok_or(ArchiveError::Disconnected)?;
+		let mut conn = pool.acquire().await?;
+		queries::delete_execution_results(&mut conn, &blocks).await?;
+		let (mut after, mut selected, mut enqueued) = (0, 0, 0);
+		loop {
+			let models = queries::selected_blocks(&mut conn, &blocks, after, REEXECUTE_BATCH).await?;
+			after = match models.last() {
+				Some(last) => last.id,
+				None => break,
+			};
+			selected += models.len() as u64;
+			let missing = Self::not_queued(&mut conn, models).await?;
+			enqueued += missing.len() as u64;
+			Self::enqueue_blocks(missing, self.config.control.execution_order.priority(false), &mut conn).await?;
+		}
+		log::info!("Queued {} of {} blocks ({:?}) to be executed again", enqueued, selected, blocks);
+		self.config.status().start_reexecution(blocks, selected);
+		Ok(enqueued)
+	}
 }
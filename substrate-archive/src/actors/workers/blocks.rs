@@ -34,6 +34,7 @@ use crate::{
 	},
 	database::queries,
 	error::{ArchiveError, Result},
+	metrics::{self, Metrics},
 	types::{BatchBlock, Block, Die},
 };
 
@@ -56,6 +57,7 @@ where
 	last_max: u32,
 	/// the maximum amount of blocks to index at once
 	max_block_load: u32,
+	metrics: Metrics,
 }
 
 impl<B: BlockT + Unpin, D: ReadOnlyDb + 'static> BlocksIndexer<B, D>
@@ -71,6 +73,7 @@ where
 			db,
 			meta,
 			max_block_load: conf.control.max_block_load,
+			metrics: conf.metrics().clone(),
 		}
 	}
 
@@ -102,6 +105,8 @@ where
 			})
 		})
 		.await?;
+		metrics::sync_counter(&self.metrics.rt_cache_hits, self.rt_cache.hits());
+		metrics::sync_counter(&self.metrics.rt_cache_misses, self.rt_cache.misses());
 		Ok(blocks)
 	}
 
@@ -113,10 +118,12 @@ where
 	}
 
 	/// First run of indexing
-	/// gets any blocks that are missing from database and indexes those.
-	/// sets the `last_max` value.
+	/// gets any blocks that are missing from database after the persisted cursor and indexes those.
+	/// sets the `last_max` value, and moves the cursor up to the first missing block.
 	async fn re_index(&mut self) -> Result<()> {
-		let mut conn = self.db.send(GetState::Conn.into()).await??.conn();
+		let mut primary = self.db.send(GetState::Conn.into()).await??.conn();
+		let cursor = queries::indexer_cursor(&mut primary).await?;
+		let mut conn = self.db.send(GetState::ReadConn.into()).await??.conn();
 		let cur_max = if let Some(m) = queries::max_block(&mut conn).await? {
 			m
 		} else {
@@ -126,9 +133,13 @@ where
 		};
 
 		let mut missing_blocks = 0;
-		let mut min = self.last_max;
+		let mut first_missing = None;
+		let mut min = reindex_from(cursor);
 		loop {
 			let batch = queries::missing_blocks_min_max(&mut conn, min, self.max_block_load).await?;
+			if first_missing.is_none() {
+				first_missing = batch.iter().min().copied();
+			}
 			if !batch.is_empty() {
 				missing_blocks += batch.len();
 				min += self.max_block_load;
@@ -140,6 +151,10 @@ where
 
 		self.last_max = cur_max;
 		log::info!("{} missing blocks", missing_blocks);
+		// missing blocks are inserted later, moving the cursor further
+		if let Some(cursor) = cursor_after_reindex(first_missing, cur_max) {
+			queries::set_indexer_cursor(&mut primary, cursor).await?;
+		}
 
 		Ok(())
 	}
@@ -244,3 +259,37 @@ where
 		ctx.stop();
 	}
 }
+
+/// The first block looked at when re-indexing, the block after the cursor.
+fn reindex_from(cursor: Option<u32>) -> u32 {
+	cursor.map(|c| c + 1).unwrap_or(0)
+}
+
+/// The indexer cursor after re-indexing: the block before the first missing block, or the highest block
+/// if none are missing. There is no cursor while the genesis block is missing.
+fn cursor_after_reindex(first_missing: Option<u32>, max: u32) -> Option<u32> {
+	match first_missing {
+		Some(first) => first.checked_sub(1),
+		None => Some(max),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn should_reindex_after_cursor() {
+		assert_eq!(reindex_from(None), 0);
+		assert_eq!(reindex_from(Some(0)), 1);
+		assert_eq!(reindex_from(Some(41)), 42);
+	}
+
+	#[test]
+	fn should_move_cursor_before_first_missing_block() {
+		assert_eq!(cursor_after_reindex(None, 100), Some(100));
+		assert_eq!(cursor_after_reindex(Some(42), 100), Some(41));
+		assert_eq!(cursor_after_reindex(Some(1), 100), Some(0));
+		assert_eq!(cursor_after_reindex(Some(0), 100), None);
+	}
+}
@@ -14,97 +14,76 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::{marker::PhantomData, time::Duration};
+use std::sync::Arc;
 
 use sp_runtime::traits::{Block as BlockT, NumberFor};
 
 use xtra::prelude::*;
 
 use crate::{
-	database::{models::StorageModel, queries, Database, DbConn},
+	database::{Database, DbConn, PoolConfig},
 	error::Result,
+	metrics::Metrics,
+	sink::{PostgresSink, Sink, SinkData},
 	types::{BatchBlock, BatchStorage, Block, Die, Metadata, Storage},
 	wasm_tracing::Traces,
 };
 
+/// Writes indexed data to PostgreSQL, and to every other configured sink.
 #[derive(Clone)]
 pub struct DatabaseActor<B: BlockT> {
 	db: Database,
-	_marker: PhantomData<B>,
+	/// PostgreSQL, followed by the sinks added by the user.
+	sinks: Vec<Arc<dyn Sink<B>>>,
+	metrics: Metrics,
 }
 
-impl<B: BlockT> DatabaseActor<B> {
-	pub async fn new(url: String) -> Result<Self> {
-		Ok(Self { db: Database::new(url).await?, _marker: PhantomData })
-	}
-
-	#[allow(unused)]
-	pub fn with_db(db: Database) -> Self {
-		Self { db, _marker: PhantomData }
-	}
-
-	async fn block_handler(&self, blk: Block<B>) -> Result<()>
-	where
-		NumberFor<B>: Into<u32>,
-	{
-		let mut conn = self.db.conn().await?;
-		while !queries::check_if_meta_exists(blk.spec, &mut conn).await? {
-			smol::Timer::after(Duration::from_millis(20)).await;
-		}
-		std::mem::drop(conn);
-		self.db.insert(blk).await?;
-		Ok(())
-	}
-
-	// Returns true if all versions are in database
-	// false if versions are missing
-	async fn db_contains_metadata(blocks: &[Block<B>], conn: &mut DbConn) -> Result<bool> {
-		let specs: hashbrown::HashSet<u32> = blocks.iter().map(|b| b.spec).collect();
-		let versions: hashbrown::HashSet<u32> = queries::get_versions(conn).await?.into_iter().collect();
-		Ok(specs.is_subset(&versions))
+impl<B: BlockT> DatabaseActor<B>
+where
+	NumberFor<B>: Into<u32>,
+{
+	pub async fn new(
+		url: String,
+		read_url: Option<String>,
+		metrics: Metrics,
+		sinks: Vec<Arc<dyn Sink<B>>>,
+		deduplicate_values: bool,
+		partition_blocks: u32,
+		pool: &PoolConfig,
+	) -> Result<Self> {
+		let db = Database::new(url, read_url, pool).await?;
+		Ok(Self::with_db(db, metrics, sinks, deduplicate_values, partition_blocks))
 	}
 
-	async fn batch_block_handler(&self, blks: BatchBlock<B>) -> Result<()>
-	where
-		NumberFor<B>: Into<u32>,
-	{
-		let mut conn = self.db.conn().await?;
-		while !Self::db_contains_metadata(blks.inner(), &mut conn).await? {
-			log::info!("Doesn't contain metadata");
-			smol::Timer::after(Duration::from_millis(50)).await;
-		}
-		std::mem::drop(conn);
-		self.db.insert(blks).await?;
-		Ok(())
+	pub fn with_db(
+		db: Database,
+		metrics: Metrics,
+		sinks: Vec<Arc<dyn Sink<B>>>,
+		deduplicate_values: bool,
+		partition_blocks: u32,
+	) -> Self {
+		let postgres: Arc<dyn Sink<B>> =
+			Arc::new(PostgresSink::new(db.clone(), metrics.clone(), deduplicate_values, partition_blocks));
+		let sinks = std::iter::once(postgres).chain(sinks).collect();
+		Self { db, sinks, metrics }
 	}
+}
 
-	async fn storage_handler(&self, storage: Storage<B>) -> Result<()> {
-		let mut conn = self.db.conn().await?;
-		while !queries::has_block::<B>(*storage.hash(), &mut conn).await? {
-			smol::Timer::after(Duration::from_millis(10)).await;
-		}
-		let storage = Vec::<StorageModel<B>>::from(storage);
-		std::mem::drop(conn);
-		self.db.insert(storage).await?;
-		Ok(())
+impl<B: BlockT> DatabaseActor<B> {
+	pub(crate) fn database(&self) -> &Database {
+		&self.db
 	}
 
-	async fn batch_storage_handler(&self, storages: BatchStorage<B>) -> Result<()> {
-		let mut conn = self.db.conn().await?;
-		let mut block_nums = storages.inner().iter().map(|s| s.block_num()).collect::<Vec<_>>();
-		block_nums.sort_unstable();
-		if !block_nums.is_empty() {
-			log::debug!("Inserting: {:#?}, {} .. {}", block_nums.len(), block_nums[0], block_nums.last().unwrap());
+	/// Write `data` to all sinks at once.
+	/// Only an error from PostgreSQL is returned, errors of other sinks are logged.
+	async fn write<T: SinkData<B>>(&self, data: T) -> Result<()> {
+		let (postgres, others) = self.sinks.split_first().expect("PostgreSQL sink always exists; qed");
+		let others = futures::future::join_all(others.iter().map(|sink| data.clone().write_to(&**sink)));
+		let (result, others) = futures::join!(data.write_to(&**postgres), others);
+		for e in others.into_iter().filter_map(|r| r.err()) {
+			log::error!("Failed to write to sink: {}", e);
 		}
-		let len = block_nums.len();
-		while queries::has_blocks::<B>(block_nums.as_slice(), &mut conn).await?.len() != len {
-			smol::Timer::after(std::time::Duration::from_millis(50)).await;
-		}
-		// we drop the connection early so that the insert() has the use of all db connections
-		std::mem::drop(conn);
-		let storage = Vec::<StorageModel<B>>::from(storages);
-		self.db.insert(storage).await?;
-		Ok(())
+		result
 	}
 }
 
@@ -117,7 +96,8 @@ where
 	NumberFor<B>: Into<u32>,
 {
 	async fn handle(&mut self, blk: Block<B>, _: &mut Context<Self>) {
-		if let Err(e) = self.block_handler(blk).await {
+		let _busy = self.metrics.db_actor_busy();
+		if let Err(e) = self.write(BatchBlock::new(vec![blk])).await {
 			log::error!("{}", e.to_string())
 		}
 	}
@@ -130,9 +110,10 @@ where
 	NumberFor<B>: Into<u32>,
 {
 	async fn handle(&mut self, blks: BatchBlock<B>, _: &mut Context<Self>) {
+		let _busy = self.metrics.db_actor_busy();
 		let len = blks.inner.len();
 		let now = std::time::Instant::now();
-		if let Err(e) = self.batch_block_handler(blks).await {
+		if let Err(e) = self.write(blks).await {
 			log::error!("{}", e.to_string());
 		}
 		if len > 1000 {
@@ -146,7 +127,8 @@ where
 #[async_trait::async_trait]
 impl<B: BlockT> Handler<Metadata> for DatabaseActor<B> {
 	async fn handle(&mut self, meta: Metadata, _ctx: &mut Context<Self>) {
-		if let Err(e) = self.db.insert(meta).await {
+		let _busy = self.metrics.db_actor_busy();
+		if let Err(e) = self.write(meta).await {
 			log::error!("{}", e.to_string());
 		}
 	}
@@ -155,7 +137,8 @@ impl<B: BlockT> Handler<Metadata> for DatabaseActor<B> {
 #[async_trait::async_trait]
 impl<B: BlockT> Handler<Storage<B>> for DatabaseActor<B> {
 	async fn handle(&mut self, storage: Storage<B>, _ctx: &mut Context<Self>) {
-		if let Err(e) = self.storage_handler(storage).await {
+		let _busy = self.metrics.db_actor_busy();
+		if let Err(e) = self.write(BatchStorage::new(vec![storage])).await {
 			log::error!("{}", e.to_string())
 		}
 	}
@@ -164,9 +147,10 @@ impl<B: BlockT> Handler<Storage<B>> for DatabaseActor<B> {
 #[async_trait::async_trait]
 impl<B: BlockT> Handler<BatchStorage<B>> for DatabaseActor<B> {
 	async fn handle(&mut self, storages: BatchStorage<B>, _ctx: &mut Context<Self>) {
+		let _busy = self.metrics.db_actor_busy();
 		let len = storages.inner.iter().map(|storage| storage.changes.len()).sum::<usize>();
 		let now = std::time::Instant::now();
-		if let Err(e) = self.batch_storage_handler(storages).await {
+		if let Err(e) = self.write(storages).await {
 			log::error!("{}", e.to_string());
 		}
 		log::debug!("Took {:?} to insert {} storage entries", now.elapsed(), len);
@@ -180,8 +164,9 @@ impl Message for Traces {
 #[async_trait::async_trait]
 impl<B: BlockT> Handler<Traces> for DatabaseActor<B> {
 	async fn handle(&mut self, traces: Traces, _: &mut Context<Self>) {
+		let _busy = self.metrics.db_actor_busy();
 		let now = std::time::Instant::now();
-		if let Err(e) = self.db.insert(traces).await {
+		if let Err(e) = self.write(traces).await {
 			log::error!("{}", e.to_string());
 		}
 		log::debug!("took {:?} to insert traces", now.elapsed());
@@ -197,6 +182,10 @@ pub enum GetState {
 	Conn,
 	// Get the Connection Pool
 	Pool,
+	// Get a single connection for read-heavy queries
+	ReadConn,
+	// Get the Connection Pool for read-heavy queries
+	ReadPool,
 }
 
 /// A response to `GetState`
@@ -248,6 +237,14 @@ impl<B: BlockT> Handler<GetState> for DatabaseActor<B> {
 				let pool = self.db.pool().clone();
 				Ok(StateResponse::Pool(pool))
 			}
+			GetState::ReadConn => {
+				let conn = self.db.read_conn().await?;
+				Ok(StateResponse::Conn(conn))
+			}
+			GetState::ReadPool => {
+				let pool = self.db.read_pool().clone();
+				Ok(StateResponse::Pool(pool))
+			}
 		}
 	}
 }
@@ -24,28 +24,37 @@ use sp_runtime::traits::Block as BlockT;
 use crate::{
 	actors::{actor_pool::ActorPool, workers::database::DatabaseActor},
 	error::Result,
+	storage_filter::StorageFilter,
 	types::{BatchStorage, Die, Storage},
 	wasm_tracing::Traces,
+	watch::Watcher,
 };
 
 pub struct StorageAggregator<B: BlockT + Unpin> {
 	db: Address<ActorPool<DatabaseActor<B>>>,
 	storage: Vec<Storage<B>>,
 	traces: Vec<Traces>,
+	watcher: Watcher,
+	filter: StorageFilter,
 }
 
 impl<B: BlockT + Unpin> StorageAggregator<B>
 where
 	B::Hash: Unpin,
 {
-	pub fn new(db: Address<ActorPool<DatabaseActor<B>>>) -> Self {
-		Self { db, storage: Vec::with_capacity(500), traces: Vec::with_capacity(250) }
+	pub fn new(db: Address<ActorPool<DatabaseActor<B>>>, watcher: Watcher, filter: StorageFilter) -> Self {
+		Self { db, storage: Vec::with_capacity(500), traces: Vec::with_capacity(250), watcher, filter }
 	}
 
 	async fn handle_storage(&mut self, ctx: &mut Context<Self>) -> Result<()> {
-		let storage = std::mem::replace(&mut self.storage, Vec::with_capacity(500));
+		let mut storage = std::mem::replace(&mut self.storage, Vec::with_capacity(500));
 		if !storage.is_empty() {
 			log::info!("Indexing {} blocks of storage entries", storage.len());
+			// watches see every change, including those which are not indexed
+			if let Err(e) = self.watcher.notify(&storage).await {
+				log::error!("Failed to find changes of watched storage: {}", e);
+			}
+			self.filter.apply(&mut storage);
 			let send_result = self.db.send(BatchStorage::new(storage).into());
 			// handle_while the actual insert is happening, not the send
 			ctx.handle_while(self, send_result).await?;
@@ -90,7 +99,11 @@ where
 
 	async fn stopped(&mut self) {
 		let len = self.storage.len();
-		let storage = std::mem::take(&mut self.storage);
+		if len == 0 {
+			return;
+		}
+		let mut storage = std::mem::take(&mut self.storage);
+		self.filter.apply(&mut storage);
 		// insert any storage left in queue
 		let task = self.db.send(BatchStorage::new(storage).into()).await;
 
@@ -161,6 +174,13 @@ where
 	B::Hash: Unpin,
 {
 	async fn handle(&mut self, _: Die, ctx: &mut Context<Self>) {
+		// flush everything buffered before stopping, so that the database actors are still around
+		if let Err(e) = self.handle_storage(ctx).await {
+			log::error!("{:?}", e);
+		}
+		if let Err(e) = self.handle_traces(ctx).await {
+			log::error!("{:?}", e);
+		}
 		ctx.stop();
 	}
 }
@@ -14,19 +14,16 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::{env, fs, io, marker::PhantomData, path::PathBuf, sync::Arc};
+use std::{env, fs, io, marker::PhantomData, net::SocketAddr, path::PathBuf, sync::Arc};
 
 use serde::{de::DeserializeOwned, Deserialize};
 
 use sc_chain_spec::ChainSpec;
-use sc_client_api::backend as api_backend;
 use sc_executor::NativeExecutionDispatch;
-use sp_api::{ApiExt, ConstructRuntimeApi};
-use sp_block_builder::BlockBuilder as BlockBuilderApi;
 use sp_blockchain::Backend as BlockchainBackend;
 use sp_runtime::{
 	generic::BlockId,
-	traits::{BlakeTwo256, Block as BlockT, NumberFor},
+	traits::{Block as BlockT, NumberFor},
 };
 
 use substrate_archive_backend::{
@@ -34,10 +31,16 @@ use substrate_archive_backend::{
 };
 
 use crate::{
-	actors::{ControlConfig, System, SystemConfig},
-	database::{self, DatabaseConfig},
+	actors::{ControlConfig, ExecutionOrder, System, SystemConfig},
+	database::{self, BlockSelection, DatabaseConfig},
 	error::Result,
 	logger::{self, FileLoggerConfig, LoggerConfig},
+	metrics::{Metrics, MetricsConfig},
+	parachain::{ParachainConfig, RelayLink},
+	retention::RetentionConfig,
+	sink::Sink,
+	status::{ArchiveStatus, StatusTracker},
+	storage_filter::{StorageConfig, StorageFilter},
 	substrate_archive_default_dir,
 };
 
@@ -109,6 +112,69 @@ pub struct ArchiveConfig {
 	/// Enable state tracing while also specifying the targets
 	/// and directory where the WASM runtimes are stored.
 	pub wasm_tracing: Option<TracingConfig>,
+	/// Serve Prometheus metrics, and the status and health checks of the archive.
+	pub metrics: Option<MetricsConfig>,
+	/// Link the blocks of a parachain to the relay chain.
+	pub parachain: Option<ParachainConfig>,
+	/// Delete old storage changes and traces. Full history is kept if not set.
+	pub retention: Option<RetentionConfig>,
+	/// Storage changes which are indexed.
+	#[serde(default)]
+	pub storage: StorageConfig,
+	/// Chains archived by the same process.
+	/// If empty, only `chain` is archived.
+	#[serde(default)]
+	pub chains: Vec<ChainEntry>,
+}
+
+impl ArchiveConfig {
+	/// The config of one of the `chains`.
+	/// Options the entry does not set are taken from this config.
+	/// Metrics are not served by the archive of the chain itself, they are served for all chains by an
+	/// [`ArchiveGroup`](crate::ArchiveGroup).
+	pub fn for_chain(&self, entry: &ChainEntry) -> ArchiveConfig {
+		let database = entry
+			.database
+			.clone()
+			.or_else(|| self.database.clone().map(|db| DatabaseConfig { schema: Some(entry.name.clone()), ..db }));
+		ArchiveConfig {
+			chain: entry.chain.clone(),
+			runtime: entry.runtime.clone().unwrap_or_else(|| self.runtime.clone()),
+			database,
+			wasm_tracing: entry.wasm_tracing.clone().or_else(|| self.wasm_tracing.clone()),
+			metrics: None,
+			parachain: entry.parachain.clone(),
+			retention: entry.retention.clone().or_else(|| self.retention.clone()),
+			storage: entry.storage.clone().unwrap_or_else(|| self.storage.clone()),
+			chains: Vec::new(),
+			..self.clone()
+		}
+	}
+}
+
+/// One of several chains archived by the same process.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ChainEntry {
+	/// Name of the chain, labels its metrics and status.
+	pub name: String,
+	/// Path to a chain spec JSON file, loaded by the binaries.
+	pub chain_spec: Option<PathBuf>,
+	/// Chain spec and database.
+	#[serde(flatten)]
+	pub chain: ChainConfig,
+	/// Runtime execution, defaults to the shared `runtime`.
+	pub runtime: Option<RuntimeConfig>,
+	/// Postgres config, defaults to the shared `database`, with the tables kept in a schema named after
+	/// the chain.
+	pub database: Option<DatabaseConfig>,
+	/// WASM tracing, defaults to the shared `wasm_tracing`.
+	pub wasm_tracing: Option<TracingConfig>,
+	/// Link the blocks of the chain to a relay chain, if it is a parachain.
+	pub parachain: Option<ParachainConfig>,
+	/// Retention policies, default to the shared `retention`.
+	pub retention: Option<RetentionConfig>,
+	/// Storage changes which are indexed, defaults to the shared `storage`.
+	pub storage: Option<StorageConfig>,
 }
 
 /// The control interface of an archive system.
@@ -131,24 +197,38 @@ where
 
 	/// Get a reference to the context the actors are using
 	fn context(&self) -> &SystemConfig<B, D>;
+
+	/// Get the current indexing status of the archive
+	fn status(&self) -> ArchiveStatus;
+
+	/// Delete the storage and traces of the selected blocks, and queue them to be executed again,
+	/// for instance after a code substitute or tracing runtime was added.
+	/// Blocks already waiting for execution are not queued twice, so it is safe to call again.
+	/// Progress is reported in the `reexecution` of the status.
+	/// Returns the number of blocks queued.
+	async fn reexecute(&self, blocks: BlockSelection) -> Result<u64>;
 }
 
-pub struct ArchiveBuilder<B, R, D, DB> {
+/// Builds an archive of the chain with blocks `B`, executed with the native runtime of `D` if it can execute them.
+/// `R` is the runtime API type of the client, which the archive does not call, `()` if the chain has none.
+pub struct ArchiveBuilder<B: BlockT, R, D, DB> {
 	_marker: PhantomData<(B, R, D, DB)>,
 	config: ArchiveConfig,
+	sinks: Vec<Arc<dyn Sink<B>>>,
+	chain_name: Option<String>,
 }
 
-impl<B, R, D, DB> Default for ArchiveBuilder<B, R, D, DB> {
+impl<B: BlockT, R, D, DB> Default for ArchiveBuilder<B, R, D, DB> {
 	fn default() -> Self {
-		Self { _marker: PhantomData, config: ArchiveConfig::default() }
+		Self { _marker: PhantomData, config: ArchiveConfig::default(), sinks: Vec::new(), chain_name: None }
 	}
 }
 
-impl<B, R, D, DB> ArchiveBuilder<B, R, D, DB> {
+impl<B: BlockT, R, D, DB> ArchiveBuilder<B, R, D, DB> {
 	/// Creates a archive builder with the given config.
 	pub fn with_config(config: Option<ArchiveConfig>) -> Self {
 		if let Some(config) = config {
-			Self { _marker: PhantomData, config }
+			Self { _marker: PhantomData, config, sinks: Vec::new(), chain_name: None }
 		} else {
 			Self::default()
 		}
@@ -164,6 +244,16 @@ impl<B, R, D, DB> ArchiveBuilder<B, R, D, DB> {
 		self
 	}
 
+	/// Name the chain, when several chains are archived by the same process.
+	/// The metrics of the archive are labelled with `chain="<name>"`.
+	///
+	/// # Default
+	/// Metrics are not labelled by default.
+	pub fn chain_name<S: Into<String>>(mut self, name: S) -> Self {
+		self.chain_name = Some(name.into());
+		self
+	}
+
 	/// Set the chain data backend path to use for this instance.
 	///
 	/// # Default
@@ -198,7 +288,27 @@ impl<B, R, D, DB> ArchiveBuilder<B, R, D, DB> {
 	/// # Default
 	/// Defaults to value of the environment variable DATABASE_URL.
 	pub fn pg_url<S: Into<String>>(mut self, url: S) -> Self {
-		self.config.database = Some(DatabaseConfig { url: url.into() });
+		self.config.database.get_or_insert_with(DatabaseConfig::default).url = url.into();
+		self
+	}
+
+	/// Set the url of a read replica of the Postgres database.
+	/// Read-heavy queries, like finding missing blocks and restoring missing storage, are routed to it.
+	///
+	/// # Default
+	/// Defaults to reading from the database at `pg_url`.
+	pub fn pg_read_url<S: Into<String>>(mut self, url: S) -> Self {
+		self.config.database.get_or_insert_with(DatabaseConfig::default).read_url = Some(url.into());
+		self
+	}
+
+	/// Set the Postgres schema the tables of the archive are kept in.
+	/// Archives of different chains can share one database, each in its own schema.
+	///
+	/// # Default
+	/// Defaults to the `public` schema.
+	pub fn pg_schema<S: Into<String>>(mut self, schema: S) -> Self {
+		self.config.database.get_or_insert_with(DatabaseConfig::default).schema = Some(schema.into());
 		self
 	}
 
@@ -274,6 +384,53 @@ impl<B, R, D, DB> ArchiveBuilder<B, R, D, DB> {
 		self
 	}
 
+	/// Set the time in seconds to wait for running tasks to finish on shutdown.
+	///
+	/// # Default
+	/// Defaults to 60 seconds.
+	pub fn shutdown_timeout(mut self, timeout: u64) -> Self {
+		self.config.control.shutdown_timeout = timeout;
+		self
+	}
+
+	/// Set the number of times the execution of a block is retried,
+	/// before it is moved to the `failed_blocks` table. Retries forever if 0.
+	///
+	/// # Default
+	/// Defaults to 5.
+	pub fn max_retries(mut self, max_retries: u32) -> Self {
+		self.config.control.max_retries = max_retries;
+		self
+	}
+
+	/// Set the order in which blocks are executed.
+	///
+	/// # Default
+	/// Defaults to [`ExecutionOrder::HeadFirst`].
+	pub fn execution_order(mut self, order: ExecutionOrder) -> Self {
+		self.config.control.execution_order = order;
+		self
+	}
+
+	/// Set how far behind the best block a block may be to be considered near the head of the chain.
+	///
+	/// # Default
+	/// Defaults to 256.
+	pub fn head_distance(mut self, distance: u32) -> Self {
+		self.config.control.head_distance = distance;
+		self
+	}
+
+	/// Set the number of blocks in each partition of `storage` and `state_traces`.
+	/// Only affects partitions created afterwards.
+	///
+	/// # Default
+	/// Defaults to 100,000.
+	pub fn partition_blocks(mut self, blocks: u32) -> Self {
+		self.config.control.partition_blocks = blocks;
+		self
+	}
+
 	/// Set the log level of stdout.
 	///
 	/// # Default
@@ -335,21 +492,75 @@ impl<B, R, D, DB> ArchiveBuilder<B, R, D, DB> {
 		self.config.wasm_tracing = wasm_tracing;
 		self
 	}
+
+	/// Serve Prometheus metrics on the address `addr`, under the path `/metrics`.
+	/// The status of the archive is served on the same address, under `/status`, `/health` and `/ready`.
+	///
+	/// # Default
+	/// Neither metrics nor status are served by default.
+	pub fn metrics_address(mut self, addr: SocketAddr) -> Self {
+		self.config.metrics = Some(MetricsConfig { address: addr });
+		self
+	}
+
+	/// Archive a parachain, linking each of its blocks to the relay chain blocks it was backed and included in.
+	/// The relay chain storage is read from the tables of the relay chain archive, in the database `relay`.
+	///
+	/// # Default
+	/// Blocks are not linked to a relay chain by default.
+	pub fn parachain(mut self, para_id: u32, relay: DatabaseConfig) -> Self {
+		self.config.parachain = Some(ParachainConfig { para_id, relay });
+		self
+	}
+
+	/// Periodically delete old storage changes and traces, according to the retention policies of `retention`.
+	///
+	/// # Default
+	/// Full history is kept by default.
+	pub fn retention(mut self, retention: RetentionConfig) -> Self {
+		self.config.retention = Some(retention);
+		self
+	}
+
+	/// Only index the storage changes of keys starting with one of the `include` prefixes,
+	/// and none of the `exclude` prefixes.
+	/// Prefixes are hex, or the names of a pallet or storage item, e.g. `Balances` or `System.Account`.
+	///
+	/// # Default
+	/// All storage changes are indexed by default.
+	pub fn storage_prefixes(mut self, include: Vec<String>, exclude: Vec<String>) -> Self {
+		self.config.storage =
+			StorageConfig { include_prefixes: include, exclude_prefixes: exclude, ..self.config.storage };
+		self
+	}
+
+	/// Keep each distinct storage value once in the `storage_values` table, keyed by its blake2-256 hash,
+	/// rather than inline in every change. Saves space for large values which change rarely, like `:code`.
+	///
+	/// # Default
+	/// Values are kept inline by default.
+	pub fn deduplicate_storage_values(mut self, deduplicate: bool) -> Self {
+		self.config.storage.deduplicate_values = deduplicate;
+		self
+	}
+
+	/// Add a sink, which receives all indexed data in addition to PostgreSQL.
+	/// May be called multiple times to add multiple sinks.
+	///
+	/// # Default
+	/// Only PostgreSQL is written to by default.
+	pub fn sink(mut self, sink: impl Sink<B> + 'static) -> Self {
+		self.sinks.push(Arc::new(sink));
+		self
+	}
 }
 
 impl<B, R, D, DB> ArchiveBuilder<B, R, D, DB>
 where
 	DB: ReadOnlyDb + 'static,
 	B: BlockT + Unpin + DeserializeOwned,
-	R: ConstructRuntimeApi<B, TArchiveClient<B, R, D, DB>> + Send + Sync + 'static,
-	R::RuntimeApi: BlockBuilderApi<B>
-		+ sp_api::Metadata<B>
-		+ ApiExt<B, StateBackend = api_backend::StateBackendFor<ReadOnlyBackend<B, DB>, B>>
-		+ Send
-		+ Sync
-		+ 'static,
+	R: Send + Sync + 'static,
 	D: NativeExecutionDispatch + 'static,
-	<R::RuntimeApi as sp_api::ApiExt<B>>::StateBackend: sp_api::StateBackend<BlakeTwo256>,
 	NumberFor<B>: Into<u32> + From<u32> + Unpin,
 	B::Hash: Unpin + std::str::FromStr,
 	B::Header: serde::de::DeserializeOwned,
@@ -362,7 +573,8 @@ where
 	/// and their respective environment variables are not set.
 	pub fn build(mut self) -> Result<impl Archive<B, DB>> {
 		// config logger
-		logger::init(self.config.log.clone())?;
+		let status = StatusTracker::default();
+		logger::init(self.config.log.clone(), status.clone())?;
 		log::debug!("Archive Config: {:?}", self.config);
 
 		// config chain
@@ -392,22 +604,66 @@ where
 
 		// config postgres database
 		const DATABASE_URL: &str = "DATABASE_URL";
-		let pg_url = self
-			.config
-			.database
-			.map(|config| config.url)
+		let db_config = self.config.database.unwrap_or_default();
+		db_config.pool.validate()?;
+		if let Some(retention) = &self.config.retention {
+			retention.validate()?;
+		}
+		let base_url = Some(db_config.url.clone())
+			.filter(|url| !url.is_empty())
 			.unwrap_or_else(|| env::var(DATABASE_URL).expect("missing DATABASE_URL"));
+		let mut pg_url = database::connection_url(&base_url, &db_config)?;
+		let mut read_url = db_config
+			.read_url
+			.as_deref()
+			.filter(|url| !url.is_empty())
+			.map(|url| database::connection_url(url, &db_config))
+			.transpose()?;
+		let relay_link = match self.config.parachain {
+			Some(parachain) => {
+				let relay_url = Some(parachain.relay.url.as_str())
+					.filter(|url| !url.is_empty())
+					.map(|url| database::connection_url(url, &parachain.relay))
+					.transpose()?
+					.unwrap_or_else(|| pg_url.clone());
+				let relay_url = match parachain.relay.schema {
+					Some(schema) => database::schema_url(&relay_url, &schema)?,
+					None => relay_url,
+				};
+				Some(RelayLink::new(parachain.para_id, relay_url))
+			}
+			None => None,
+		};
+		if let Some(schema) = db_config.schema {
+			smol::block_on(database::create_schema(&pg_url, &schema))?;
+			pg_url = database::schema_url(&pg_url, &schema)?;
+			read_url = read_url.map(|url| database::schema_url(&url, &schema)).transpose()?;
+		}
 		smol::block_on(database::migrate(&pg_url))?;
 
 		// config actor system
+		let storage_filter = StorageFilter::new(&self.config.storage)?;
+		let metrics = match &self.chain_name {
+			Some(name) => Metrics::for_chain(name)?,
+			None => Metrics::new()?,
+		};
 		let config = SystemConfig::new(
 			backend,
 			pg_url,
 			client.clone(),
 			self.config.control,
 			self.config.wasm_tracing.map(|t| t.targets),
-		);
-		let sys = System::<_, R, _, _>::new(client, config)?;
+			metrics,
+		)
+		.with_read_url(read_url)
+		.with_metrics_address(self.config.metrics.map(|m| m.address))
+		.with_status(status)
+		.with_sinks(self.sinks)
+		.with_relay_link(relay_link)
+		.with_retention(self.config.retention)
+		.with_storage(storage_filter, self.config.storage.deduplicate_values)
+		.with_pool(db_config.pool);
+		let sys = System::new(client, config)?;
 		Ok(sys)
 	}
 
@@ -0,0 +1,307 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Command line options and commands shared by the binaries.
+//!
+//! Binaries flatten [`CliOpts`] into their own options, which select the chain,
+//! and run every [`Command`] other than [`Command::Run`] with [`run_command`].
+
+use std::{env, fs, path::PathBuf};
+
+use structopt::StructOpt;
+
+use crate::{
+	archive::ArchiveConfig,
+	database::{self, BlockSelection},
+	error::{ArchiveError, Result},
+	export::{self, ExportConfig, ExportTable, Partitioning},
+};
+
+/// Options of every binary, besides those selecting the chain.
+#[derive(Clone, Debug, StructOpt)]
+pub struct CliOpts {
+	/// Sets a custom config file
+	#[structopt(short = "c", long, name = "FILE")]
+	pub config: Option<PathBuf>,
+	#[structopt(subcommand)]
+	pub cmd: Option<Command>,
+}
+
+impl CliOpts {
+	/// Read the config file, if one was given.
+	pub fn config(&self) -> Result<Option<ArchiveConfig>> {
+		match &self.config {
+			Some(path) => Ok(Some(toml::from_str(&fs::read_to_string(path)?)?)),
+			None => Ok(None),
+		}
+	}
+
+	/// The command to run instead of the archive, if any.
+	pub fn command(&self) -> Option<&Command> {
+		self.cmd.as_ref().filter(|cmd| !matches!(cmd, Command::Run))
+	}
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub enum Command {
+	/// Run the archive. The default if no command is given.
+	Run,
+	/// Run the database migrations.
+	Migrate,
+	/// Show the indexing progress recorded in the database.
+	Status,
+	/// Delete a range of blocks, so they are indexed and executed again on the next run.
+	Reindex(ReindexOpts),
+	/// Delete the storage and traces of a runtime version or block range, so its blocks are executed again on the
+	/// next run.
+	Reexecute(ReexecuteOpts),
+	/// Look for missing blocks and blocks which were not executed.
+	Verify,
+	/// List, retry or discard blocks which failed execution too many times.
+	FailedBlocks(FailedBlocks),
+	/// Export blocks, storage and traces of a block range to Parquet files.
+	Export(ExportOpts),
+	/// Delete old data, according to the `[retention]` policies of the config.
+	Prune(PruneOpts),
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct ReindexOpts {
+	/// First block to reindex.
+	#[structopt(long)]
+	pub from: u32,
+	/// Last block to reindex.
+	#[structopt(long)]
+	pub to: u32,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct ReexecuteOpts {
+	/// Runtime version whose blocks are executed again.
+	#[structopt(long, required_unless = "from", conflicts_with_all = &["from", "to"])]
+	pub spec_version: Option<u32>,
+	/// First block to execute again.
+	#[structopt(long, requires = "to")]
+	pub from: Option<u32>,
+	/// Last block to execute again.
+	#[structopt(long, requires = "from")]
+	pub to: Option<u32>,
+}
+
+impl ReexecuteOpts {
+	/// The blocks to execute again.
+	pub fn blocks(&self) -> BlockSelection {
+		match (self.spec_version, self.from, self.to) {
+			(Some(spec), _, _) => BlockSelection::Spec(spec),
+			(None, Some(from), Some(to)) => BlockSelection::Range { from, to },
+			_ => unreachable!("either the spec version or the block range is required"),
+		}
+	}
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct PruneOpts {
+	/// Delete the traces of blocks before this block, instead of applying the retention policies.
+	#[structopt(long)]
+	pub traces_before: Option<u32>,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub enum FailedBlocks {
+	/// List all failed blocks.
+	List,
+	/// Queue failed blocks for execution again.
+	Retry(FailedBlockIds),
+	/// Remove failed blocks without executing them.
+	Discard(FailedBlockIds),
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct FailedBlockIds {
+	/// Ids of the failed blocks, as shown by `list`.
+	#[structopt(required_unless = "all")]
+	pub ids: Vec<i32>,
+	/// Select all failed blocks.
+	#[structopt(long, conflicts_with = "ids")]
+	pub all: bool,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct ExportOpts {
+	/// First block to export.
+	#[structopt(long, default_value = "0")]
+	pub from: u32,
+	/// Last block to export.
+	#[structopt(long)]
+	pub to: u32,
+	/// Directory the Parquet files are written to.
+	#[structopt(short, long, parse(from_os_str))]
+	pub out: PathBuf,
+	/// Partition the files by `spec` version, or by block range with `range:<blocks>`.
+	#[structopt(long, default_value = "spec")]
+	pub partition: Partitioning,
+	/// Tables to export, any of blocks, extrinsics, events, storage and traces. All tables by default.
+	#[structopt(long, use_delimiter = true)]
+	pub tables: Vec<ExportTable>,
+}
+
+/// Run one of the commands which don't start the archive.
+pub fn run_command(cmd: &Command, config: Option<ArchiveConfig>) -> Result<()> {
+	let retention = config.as_ref().and_then(|c| c.retention.clone());
+	let db_config = config.and_then(|c| c.database).unwrap_or_default();
+	let base_url = match db_config.url.as_str() {
+		"" => env::var("DATABASE_URL").map_err(|_| ArchiveError::Command("missing DATABASE_URL"))?,
+		url => url.to_string(),
+	};
+	let base_url = database::connection_url(&base_url, &db_config)?;
+	let url = match &db_config.schema {
+		Some(schema) => database::schema_url(&base_url, schema)?,
+		None => base_url.clone(),
+	};
+	// read-only commands use the read replica, if there is one
+	let read_url = match db_config.read_url.as_deref().filter(|url| !url.is_empty()) {
+		Some(read_url) => {
+			let read_url = database::connection_url(read_url, &db_config)?;
+			match &db_config.schema {
+				Some(schema) => database::schema_url(&read_url, schema)?,
+				None => read_url,
+			}
+		}
+		None => url.clone(),
+	};
+	smol::block_on(async {
+		match cmd {
+			Command::Run => unreachable!("the archive is not run as a command"),
+			Command::Migrate => {
+				if let Some(schema) = &db_config.schema {
+					database::create_schema(&base_url, schema).await?;
+				}
+				database::migrate(&url).await?;
+				println!("Migrations applied");
+			}
+			Command::Status => {
+				let status = database::status(&read_url).await?;
+				println!(
+					"indexed head: {}",
+					status.indexed_head.map(|h| h.to_string()).unwrap_or_else(|| "none".into())
+				);
+				println!("blocks: {}", status.blocks);
+				println!("pending jobs: {}", status.pending_jobs);
+				println!("retrying jobs: {}", status.failed_jobs);
+				println!("failed blocks: {}", status.failed_blocks);
+			}
+			Command::Reindex(opts) => {
+				let deleted = database::reindex(&url, opts.from, opts.to).await?;
+				println!("Deleted {} blocks, they are indexed again on the next run", deleted);
+			}
+			Command::Reexecute(opts) => {
+				let blocks = database::reexecute(&url, &opts.blocks()).await?;
+				println!("Deleted the storage of {} blocks, they are executed again on the next run", blocks);
+			}
+			Command::Verify => {
+				let report = database::verify(&read_url).await?;
+				println!("missing blocks: {}", report.missing_blocks);
+				println!("blocks without storage: {}", report.blocks_without_storage);
+				println!("pending jobs: {}", report.pending_jobs);
+				println!("failed blocks: {}", report.failed_blocks);
+				if !report.is_consistent() {
+					return Err(ArchiveError::Command(
+						"the database is incomplete, run `reindex` or start the archive to fill gaps",
+					));
+				}
+			}
+			Command::Prune(opts) => match (opts.traces_before, &retention) {
+				(Some(block_num), _) => println!("Deleted {} traces", database::prune_traces(&url, block_num).await?),
+				(None, Some(retention)) => {
+					let pruned = database::prune(&url, retention).await?;
+					println!(
+						"Deleted {} storage changes, {} storage values, {} traces and {} partitions of traces",
+						pruned.storage, pruned.values, pruned.traces, pruned.partitions
+					);
+				}
+				(None, None) => {
+					return Err(ArchiveError::Command("no `[retention]` in the config, and no `--traces-before`"))
+				}
+			},
+			Command::FailedBlocks(FailedBlocks::List) => {
+				for block in database::failed_blocks(&url).await? {
+					println!(
+						"{}: block #{} (0x{}), spec {}, {} attempts, failed at {}: {}",
+						block.id,
+						block.block_num,
+						block.hash.iter().map(|b| format!("{:02x}", b)).collect::<String>(),
+						block.spec.map(|s| s.to_string()).unwrap_or_else(|| "unknown".into()),
+						block.attempts,
+						block.failed_at,
+						block.error.as_deref().unwrap_or("unknown error"),
+					);
+				}
+			}
+			Command::FailedBlocks(FailedBlocks::Retry(ids)) => {
+				let ids = failed_block_ids(&url, ids).await?;
+				println!("Queued {} blocks for execution", database::retry_failed_blocks(&url, &ids).await?);
+			}
+			Command::FailedBlocks(FailedBlocks::Discard(ids)) => {
+				let ids = failed_block_ids(&url, ids).await?;
+				println!("Discarded {} blocks", database::discard_failed_blocks(&url, &ids).await?);
+			}
+			Command::Export(opts) => {
+				let summary = export::export(&read_url, &export_config(opts)).await?;
+				println!("Exported {} rows to {} files in {}", summary.rows, summary.files.len(), opts.out.display());
+			}
+		}
+		Ok(())
+	})
+}
+
+async fn failed_block_ids(url: &str, ids: &FailedBlockIds) -> Result<Vec<i32>> {
+	if ids.all {
+		Ok(database::failed_blocks(url).await?.into_iter().map(|b| b.id).collect())
+	} else {
+		Ok(ids.ids.clone())
+	}
+}
+
+fn export_config(opts: &ExportOpts) -> ExportConfig {
+	ExportConfig {
+		dir: opts.out.clone(),
+		from: opts.from,
+		to: opts.to,
+		partitioning: opts.partition,
+		tables: if opts.tables.is_empty() { ExportTable::ALL.to_vec() } else { opts.tables.clone() },
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn should_parse_commands() {
+		let opts = CliOpts::from_iter_safe(&["archive", "-c", "archive.toml"]).unwrap();
+		assert_eq!(opts.config, Some(PathBuf::from("archive.toml")));
+		assert!(opts.command().is_none());
+		assert!(CliOpts::from_iter_safe(&["archive", "run"]).unwrap().command().is_none());
+
+		let opts = CliOpts::from_iter_safe(&["archive", "reexecute", "--from", "1", "--to", "2"]).unwrap();
+		match opts.command() {
+			Some(Command::Reexecute(opts)) => assert_eq!(opts.blocks(), BlockSelection::Range { from: 1, to: 2 }),
+			cmd => panic!("unexpected command {:?}", cmd),
+		}
+		assert!(CliOpts::from_iter_safe(&["archive", "reexecute", "--spec-version", "1", "--from", "1"]).is_err());
+		assert!(CliOpts::from_iter_safe(&["archive", "failed-blocks", "retry"]).is_err());
+	}
+}
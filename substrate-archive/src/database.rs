@@ -18,6 +18,7 @@
 //! Handles inserting of data into the database
 
 mod batch;
+mod copy;
 pub mod listener;
 pub mod models;
 pub mod queries;
@@ -25,15 +26,16 @@ pub mod queries;
 use std::{
 	convert::{TryFrom, TryInto},
 	fmt,
+	path::PathBuf,
 	time::Duration,
 };
 
 use codec::Encode;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sqlx::{
 	pool::PoolConnection,
 	postgres::{PgConnection, PgPool, PgPoolOptions, Postgres},
-	Connection,
+	Connection, Executor,
 };
 
 use sp_runtime::traits::{Block as BlockT, Header as _, NumberFor};
@@ -42,6 +44,8 @@ use self::batch::Batch;
 pub use self::{listener::*, models::*};
 use crate::{
 	error::{ArchiveError, Result},
+	http, partitions,
+	retention::{self, Pruned, RetentionConfig},
 	types::*,
 	wasm_tracing::Traces,
 };
@@ -53,41 +57,400 @@ pub async fn migrate<T: AsRef<str>>(url: T) -> Result<()> {
 	Ok(())
 }
 
+/// Create the schema `schema` in the database at `url`, if it does not exist,
+/// and check that the connections of its [`schema_url`] use it.
+pub async fn create_schema<T: AsRef<str>>(url: T, schema: &str) -> Result<()> {
+	validate_schema(schema)?;
+	let mut conn = PgConnection::connect(url.as_ref()).await?;
+	sqlx::query(&format!("CREATE SCHEMA IF NOT EXISTS {}", schema)).execute(&mut conn).await?;
+	let mut conn = PgConnection::connect(&schema_url(url.as_ref(), schema)?).await?;
+	let (current,): (Option<String>,) = sqlx::query_as("SELECT current_schema()").fetch_one(&mut conn).await?;
+	if current.as_deref() != Some(schema) {
+		return Err(ArchiveError::SchemaIgnored(schema.to_string()));
+	}
+	Ok(())
+}
+
+/// Extend the database `url`, so that its connections use `schema` for all tables.
+/// The schema is set with the `options` connection parameter, which sqlx passes on since `0.5.10`.
+pub fn schema_url(url: &str, schema: &str) -> Result<String> {
+	validate_schema(schema)?;
+	let separator = if url.contains('?') { '&' } else { '?' };
+	Ok(format!("{}{}options=-c%20search_path%3D{}", url, separator, schema))
+}
+
+// Schema names are used in SQL and connection options without quoting.
+fn validate_schema(schema: &str) -> Result<()> {
+	let valid = schema.chars().next().map(|c| c.is_ascii_lowercase() || c == '_').unwrap_or(false)
+		&& schema.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+	if valid {
+		Ok(())
+	} else {
+		Err(ArchiveError::InvalidSchema(schema.to_string()))
+	}
+}
+
+/// Get all blocks whose execution failed more often than the configured `max_retries`.
+pub async fn failed_blocks<T: AsRef<str>>(url: T) -> Result<Vec<FailedBlock>> {
+	let mut conn = PgConnection::connect(url.as_ref()).await?;
+	queries::failed_blocks(&mut conn).await
+}
+
+/// Queue failed blocks for execution again.
+/// Returns the number of blocks queued.
+pub async fn retry_failed_blocks<T: AsRef<str>>(url: T, ids: &[i32]) -> Result<u64> {
+	let mut conn = PgConnection::connect(url.as_ref()).await?;
+	queries::retry_failed_blocks(&mut conn, ids).await
+}
+
+/// Remove failed blocks without executing them.
+/// Returns the number of blocks removed.
+pub async fn discard_failed_blocks<T: AsRef<str>>(url: T, ids: &[i32]) -> Result<u64> {
+	let mut conn = PgConnection::connect(url.as_ref()).await?;
+	queries::discard_failed_blocks(&mut conn, ids).await
+}
+
+/// Get all watched storage key prefixes.
+pub async fn watches<T: AsRef<str>>(url: T) -> Result<Vec<Watch>> {
+	let mut conn = PgConnection::connect(url.as_ref()).await?;
+	queries::watches(&mut conn).await
+}
+
+/// Watch storage keys starting with `prefix`. Every change of a matching key is POSTed to `callback_url`,
+/// which must be a plain `http://` URL.
+/// Returns the id of the watch.
+pub async fn add_watch<T: AsRef<str>>(url: T, prefix: &[u8], callback_url: &str) -> Result<i32> {
+	if !http::is_supported(callback_url) {
+		return Err(ArchiveError::InvalidCallback(callback_url.to_string()));
+	}
+	let mut conn = PgConnection::connect(url.as_ref()).await?;
+	queries::add_watch(&mut conn, prefix, callback_url).await
+}
+
+/// Stop watching a storage key prefix.
+/// Returns the number of watches removed.
+pub async fn remove_watch<T: AsRef<str>>(url: T, id: i32) -> Result<u64> {
+	let mut conn = PgConnection::connect(url.as_ref()).await?;
+	queries::remove_watch(&mut conn, id).await
+}
+
+/// Indexing progress as recorded in the database.
+#[derive(Clone, Debug, Serialize)]
+pub struct DatabaseStatus {
+	/// Highest block in the database.
+	pub indexed_head: Option<u32>,
+	/// Blocks in the database.
+	pub blocks: u64,
+	/// Blocks waiting to be executed.
+	pub pending_jobs: u64,
+	/// Blocks which failed execution at least once and are waiting to be retried.
+	pub failed_jobs: u64,
+	/// Blocks which failed execution too many times, and were moved to `failed_blocks`.
+	pub failed_blocks: u64,
+}
+
+/// Gaps and unexecuted blocks found in the database.
+#[derive(Clone, Debug, Serialize)]
+pub struct VerifyReport {
+	/// Block numbers up to the highest block which are missing.
+	pub missing_blocks: u64,
+	/// Blocks without storage. Includes blocks waiting to be executed and failed blocks.
+	pub blocks_without_storage: u64,
+	/// Blocks waiting to be executed.
+	pub pending_jobs: u64,
+	/// Blocks which failed execution too many times.
+	pub failed_blocks: u64,
+}
+
+impl VerifyReport {
+	/// No block is missing, and every block without storage is either queued or failed.
+	pub fn is_consistent(&self) -> bool {
+		self.missing_blocks == 0 && self.blocks_without_storage <= self.pending_jobs + self.failed_blocks
+	}
+}
+
+/// Get the indexing progress recorded in the database.
+pub async fn status<T: AsRef<str>>(url: T) -> Result<DatabaseStatus> {
+	let mut conn = PgConnection::connect(url.as_ref()).await?;
+	Ok(DatabaseStatus {
+		indexed_head: queries::max_block(&mut conn).await?,
+		blocks: queries::count_blocks(&mut conn).await?,
+		pending_jobs: queries::queue_depth(&mut conn).await?,
+		failed_jobs: queries::failed_jobs(&mut conn).await?,
+		failed_blocks: queries::count_failed_blocks(&mut conn).await?,
+	})
+}
+
+/// Look for missing blocks and blocks which were not executed.
+/// Scans the `blocks` table, which may take a while on large databases.
+pub async fn verify<T: AsRef<str>>(url: T) -> Result<VerifyReport> {
+	let mut conn = PgConnection::connect(url.as_ref()).await?;
+	Ok(VerifyReport {
+		missing_blocks: queries::count_missing_blocks(&mut conn).await?,
+		blocks_without_storage: queries::count_blocks_without_storage(&mut conn).await?,
+		pending_jobs: queries::queue_depth(&mut conn).await?,
+		failed_blocks: queries::count_failed_blocks(&mut conn).await?,
+	})
+}
+
+/// Delete the blocks `from..=to` with their storage and traces, and the storage values only they referenced.
+/// They are read from the chain database and executed again, the next time the archive is started.
+/// Returns the number of blocks deleted.
+pub async fn reindex<T: AsRef<str>>(url: T, from: u32, to: u32) -> Result<u64> {
+	let mut conn = PgConnection::connect(url.as_ref()).await?;
+	queries::delete_blocks(&mut conn, from, to).await
+}
+
+/// Blocks whose execution results are replaced.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockSelection {
+	/// All blocks of a runtime version.
+	Spec(u32),
+	/// The blocks `from..=to`.
+	Range { from: u32, to: u32 },
+}
+
+impl BlockSelection {
+	/// The runtime version, first and last block number the queries select blocks by.
+	pub(crate) fn bounds(&self) -> Result<(Option<i32>, i32, i32)> {
+		match *self {
+			BlockSelection::Spec(spec) => Ok((Some(i32::try_from(spec)?), 0, i32::MAX)),
+			BlockSelection::Range { from, to } => {
+				Ok((None, i32::try_from(from).unwrap_or(i32::MAX), i32::try_from(to).unwrap_or(i32::MAX)))
+			}
+		}
+	}
+}
+
+/// Delete the storage and traces of the selected blocks, and the storage values only they referenced.
+/// The blocks are executed again, the next time the archive is started.
+/// To execute them again while the archive is running, use [`Archive::reexecute`](crate::Archive::reexecute).
+/// Returns the number of blocks affected.
+pub async fn reexecute<T: AsRef<str>>(url: T, blocks: &BlockSelection) -> Result<u64> {
+	let mut conn = PgConnection::connect(url.as_ref()).await?;
+	queries::delete_execution_results(&mut conn, blocks).await
+}
+
+/// Apply the retention policies of `config` once, instead of periodically while the archive is running.
+/// Returns the number of storage changes, storage values and traces deleted.
+pub async fn prune<T: AsRef<str>>(url: T, config: &RetentionConfig) -> Result<Pruned> {
+	let mut conn = PgConnection::connect(url.as_ref()).await?;
+	retention::apply(&mut conn, config).await
+}
+
+/// Delete the traces of all blocks before `block_num`, dropping the partitions which only hold such traces.
+/// Returns the number of traces deleted, not counting those of dropped partitions.
+pub async fn prune_traces<T: AsRef<str>>(url: T, block_num: u32) -> Result<u64> {
+	let mut conn = PgConnection::connect(url.as_ref()).await?;
+	partitions::drop_before(&mut conn, "state_traces", block_num).await?;
+	queries::delete_traces_before(&mut conn, block_num).await
+}
+
 #[derive(Clone, Debug, Default, Deserialize)]
 pub struct DatabaseConfig {
 	/// PostgreSQL url.
+	#[serde(default)]
 	pub url: String,
+	/// Schema the tables of the archive are kept in, so that archives of different chains can share a database.
+	/// Must consist of lowercase letters, digits and underscores.
+	pub schema: Option<String>,
+	/// Name the connections of the archive report to the server.
+	pub application_name: Option<String>,
+	/// Whether, and how strictly, connections use TLS.
+	/// Defaults to the `sslmode` of the url, or `prefer`.
+	pub ssl_mode: Option<SslMode>,
+	/// Certificate authorities the certificate of the server is verified with, a PEM file.
+	pub ssl_root_cert: Option<PathBuf>,
+	/// Read replica of the database at `url`.
+	/// Read-heavy queries, like finding missing blocks and restoring missing storage, are routed to it,
+	/// while inserts and the job queue stay on `url`.
+	pub read_url: Option<String>,
+	/// Connection pool of the archive.
+	#[serde(flatten)]
+	pub pool: PoolConfig,
 }
 
 impl fmt::Display for DatabaseConfig {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		write!(f, "{}", self.url)
+		match &self.schema {
+			Some(schema) => write!(f, "{} (schema {})", self.url, schema),
+			None => write!(f, "{}", self.url),
+		}
+	}
+}
+
+/// TLS modes of PostgreSQL connections, as in the `sslmode` of libpq.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SslMode {
+	Disable,
+	Allow,
+	Prefer,
+	Require,
+	VerifyCa,
+	VerifyFull,
+}
+
+impl SslMode {
+	fn as_str(&self) -> &'static str {
+		match self {
+			SslMode::Disable => "disable",
+			SslMode::Allow => "allow",
+			SslMode::Prefer => "prefer",
+			SslMode::Require => "require",
+			SslMode::VerifyCa => "verify-ca",
+			SslMode::VerifyFull => "verify-full",
+		}
+	}
+}
+
+/// Extend the database `url` with the connection options of `config`: TLS and application name.
+/// Connections to the url use these options wherever they are made, including migrations and listeners.
+pub fn connection_url(url: &str, config: &DatabaseConfig) -> Result<String> {
+	let mut params = Vec::new();
+	if let Some(mode) = config.ssl_mode {
+		params.push(format!("sslmode={}", mode.as_str()));
+	}
+	if let Some(path) = &config.ssl_root_cert {
+		params.push(format!("sslrootcert={}", encode_param(&path.to_string_lossy())));
+	}
+	if let Some(name) = &config.application_name {
+		params.push(format!("application_name={}", encode_param(name)));
+	}
+	if params.is_empty() {
+		return Ok(url.to_string());
+	}
+	let separator = if url.contains('?') { '&' } else { '?' };
+	Ok(format!("{}{}{}", url, separator, params.join("&")))
+}
+
+// Percent-encode everything but unreserved characters of a url query value.
+fn encode_param(value: &str) -> String {
+	value
+		.bytes()
+		.map(|b| match b {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => (b as char).to_string(),
+			_ => format!("%{:02X}", b),
+		})
+		.collect()
+}
+
+/// Connection pool of the archive, shared by the database actors and the job queue.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PoolConfig {
+	/// Connections kept open.
+	#[serde(default = "default_min_connections")]
+	pub min_connections: u32,
+	/// Connections opened at most, e.g. to stay under the connection limit of the database user.
+	#[serde(default = "default_max_connections")]
+	pub max_connections: u32,
+	/// Seconds to wait for a connection, before giving up.
+	#[serde(default = "default_connect_timeout")]
+	pub connect_timeout: f64,
+	/// Seconds a connection is kept idle, before it is closed.
+	#[serde(default = "default_idle_timeout")]
+	pub idle_timeout: f64,
+	/// Seconds a connection is used at most, before it is closed.
+	pub max_lifetime: Option<f64>,
+	/// Seconds a statement may run, before the server cancels it.
+	/// Unlimited if not set. Only applies to the pool, not to migrations.
+	pub statement_timeout: Option<f64>,
+}
+
+impl Default for PoolConfig {
+	fn default() -> Self {
+		Self {
+			min_connections: default_min_connections(),
+			max_connections: default_max_connections(),
+			connect_timeout: default_connect_timeout(),
+			idle_timeout: default_idle_timeout(),
+			max_lifetime: None,
+			statement_timeout: None,
+		}
+	}
+}
+
+impl PoolConfig {
+	/// Check that the timeouts are durations, so that connecting does not fail on them.
+	pub fn validate(&self) -> Result<()> {
+		seconds("connect_timeout", self.connect_timeout)?;
+		seconds("idle_timeout", self.idle_timeout)?;
+		self.max_lifetime.map(|secs| seconds("max_lifetime", secs)).transpose()?;
+		self.statement_timeout.map(|secs| seconds("statement_timeout", secs)).transpose()?;
+		Ok(())
+	}
+}
+
+/// The duration of `secs` seconds of the option `name`, if they are finite and not negative.
+fn seconds(name: &'static str, secs: f64) -> Result<Duration> {
+	if secs.is_finite() && secs >= 0.0 && secs <= u64::MAX as f64 {
+		Ok(Duration::from_secs_f64(secs))
+	} else {
+		Err(ArchiveError::InvalidDuration(name, secs))
 	}
 }
 
+fn default_min_connections() -> u32 {
+	4
+}
+
+fn default_max_connections() -> u32 {
+	28
+}
+
+fn default_connect_timeout() -> f64 {
+	30.0
+}
+
+fn default_idle_timeout() -> f64 {
+	3.6
+}
+
 #[derive(Clone)]
 pub struct Database {
 	/// pool of database connections
 	pool: PgPool,
+	/// pool of connections to a read replica, if reads are routed to one
+	read_pool: Option<PgPool>,
 	url: String,
 }
 
 impl Database {
-	/// Connect to the database
-	pub async fn new(url: String) -> Result<Self> {
-		let pool = PgPoolOptions::new()
-			.min_connections(4)
-			.max_connections(28)
-			.idle_timeout(Duration::from_millis(3600)) // kill connections after 3.6 seconds of idle
-			.connect(url.as_str())
-			.await?;
-		Ok(Self { pool, url })
+	/// Connect to the database, with pools configured by `config`.
+	/// Read-heavy queries use connections to `read_url` if given, everything else uses `url`.
+	pub async fn new(url: String, read_url: Option<String>, config: &PoolConfig) -> Result<Self> {
+		let pool = Self::pool_options(config)?.connect(url.as_str()).await?;
+		let read_pool = match read_url {
+			Some(read_url) => Some(Self::pool_options(config)?.connect(read_url.as_str()).await?),
+			None => None,
+		};
+		Ok(Self { pool, read_pool, url })
+	}
+
+	fn pool_options(config: &PoolConfig) -> Result<PgPoolOptions> {
+		let mut options = PgPoolOptions::new()
+			.min_connections(config.min_connections)
+			.max_connections(config.max_connections)
+			.connect_timeout(seconds("connect_timeout", config.connect_timeout)?)
+			.idle_timeout(seconds("idle_timeout", config.idle_timeout)?);
+		if let Some(lifetime) = config.max_lifetime {
+			options = options.max_lifetime(seconds("max_lifetime", lifetime)?);
+		}
+		if let Some(timeout) = config.statement_timeout {
+			let set_timeout = format!("SET statement_timeout = {}", seconds("statement_timeout", timeout)?.as_millis());
+			options = options.after_connect(move |conn| {
+				let set_timeout = set_timeout.clone();
+				Box::pin(async move { conn.execute(set_timeout.as_str()).await.map(|_| ()) })
+			});
+		}
+		Ok(options)
 	}
 
 	/// Start the database with a pre-defined pool
 	#[allow(unused)]
 	pub fn with_pool(url: String, pool: PgPool) -> Self {
-		Self { pool, url }
+		Self { pool, read_pool: None, url }
 	}
 
 	#[allow(unused)]
@@ -104,6 +467,17 @@ impl Database {
 	pub fn pool(&self) -> &sqlx::PgPool {
 		&self.pool
 	}
+
+	/// Get a connection for read-heavy queries, to the read replica if there is one.
+	/// Reads may lag behind writes.
+	pub async fn read_conn(&self) -> Result<DbConn> {
+		self.read_pool().acquire().await.map_err(Into::into)
+	}
+
+	/// The pool of the read replica, or of the primary if there is none.
+	pub fn read_pool(&self) -> &sqlx::PgPool {
+		self.read_pool.as_ref().unwrap_or(&self.pool)
+	}
 }
 
 pub type DbReturn = Result<u64>;
@@ -164,6 +538,9 @@ where
 	NumberFor<B>: Into<u32>,
 {
 	async fn insert(mut self, conn: &mut DbConn) -> DbReturn {
+		if self.inner.len() >= copy::COPY_THRESHOLD {
+			return copy::blocks(&self.inner, conn).await;
+		}
 		let mut batch = Batch::new(
 			"blocks",
 			r#"
@@ -216,12 +593,13 @@ impl<B: BlockT> Insert for StorageModel<B> {
 		sqlx::query(
 			r#"
                 INSERT INTO storage (
-                    block_num, hash, is_full, key, storage
-                ) VALUES ($1, $2, $3, $4, $5)
-                ON CONFLICT (hash, key, md5(storage)) DO UPDATE SET
+                    block_num, hash, is_full, key, storage, value_hash
+                ) VALUES ($1, $2, $3, $4, $5, $6)
+                ON CONFLICT (hash, key, block_num) DO UPDATE SET
                     hash = EXCLUDED.hash,
                     key = EXCLUDED.key,
                     storage = EXCLUDED.storage,
+                    value_hash = EXCLUDED.value_hash,
                     is_full = EXCLUDED.is_full
             "#,
 		)
@@ -230,6 +608,7 @@ impl<B: BlockT> Insert for StorageModel<B> {
 		.bind(self.is_full())
 		.bind(self.key().0.as_slice())
 		.bind(self.data().map(|d| d.0.as_slice()))
+		.bind(self.value_hash().map(|h| h.to_vec()))
 		.execute(conn)
 		.await
 		.map(|d| d.rows_affected())
@@ -240,41 +619,101 @@ impl<B: BlockT> Insert for StorageModel<B> {
 #[async_trait::async_trait]
 impl<B: BlockT> Insert for Vec<StorageModel<B>> {
 	async fn insert(mut self, conn: &mut DbConn) -> DbReturn {
-		let mut batch = Batch::new(
-			"storage",
-			r#"
-            INSERT INTO "storage" (
-                block_num, hash, is_full, key, storage
-            ) VALUES
-            "#,
-			r#"
-            ON CONFLICT (hash, key, md5(storage)) DO UPDATE SET
-                hash = EXCLUDED.hash,
-                key = EXCLUDED.key,
-                storage = EXCLUDED.storage,
-                is_full = EXCLUDED.is_full
-            "#,
-		);
+		insert_storage(&self, true, conn).await
+	}
+}
 
-		for s in self {
-			batch.reserve(5)?;
-			if batch.current_num_arguments() > 0 {
-				batch.append(",");
-			}
-			batch.append("(");
-			batch.bind(s.block_num())?;
-			batch.append(",");
-			batch.bind(s.hash().as_ref())?;
-			batch.append(",");
-			batch.bind(s.is_full())?;
+#[async_trait::async_trait]
+impl<B: BlockT> Insert for DeduplicatedStorage<B> {
+	async fn insert(mut self, conn: &mut DbConn) -> DbReturn {
+		let mut tx = conn.begin().await?;
+		queries::share_storage_values(&mut tx).await?;
+		insert_storage_values(&self.0, &mut tx).await?;
+		let rows = insert_storage(&self.0, false, &mut tx).await?;
+		tx.commit().await?;
+		Ok(rows)
+	}
+}
+
+/// Insert storage changes, either with their values `inline`,
+/// or referencing values in `storage_values` by their hash only.
+async fn insert_storage<B: BlockT>(storage: &[StorageModel<B>], inline: bool, conn: &mut PgConnection) -> DbReturn {
+	if storage.len() >= copy::COPY_THRESHOLD {
+		return copy::storage(storage, inline, conn).await;
+	}
+	let mut batch = Batch::new(
+		"storage",
+		r#"
+        INSERT INTO "storage" (
+            block_num, hash, is_full, key, storage, value_hash
+        ) VALUES
+        "#,
+		r#"
+        ON CONFLICT (hash, key, block_num) DO UPDATE SET
+            hash = EXCLUDED.hash,
+            key = EXCLUDED.key,
+            storage = EXCLUDED.storage,
+            value_hash = EXCLUDED.value_hash,
+            is_full = EXCLUDED.is_full
+        "#,
+	);
+
+	for s in storage {
+		batch.reserve(6)?;
+		if batch.current_num_arguments() > 0 {
 			batch.append(",");
-			batch.bind(s.key().0.as_slice())?;
+		}
+		batch.append("(");
+		batch.bind(s.block_num())?;
+		batch.append(",");
+		batch.bind(s.hash().as_ref())?;
+		batch.append(",");
+		batch.bind(s.is_full())?;
+		batch.append(",");
+		batch.bind(s.key().0.as_slice())?;
+		batch.append(",");
+		batch.bind(s.data().filter(|_| inline).map(|d| d.0.as_slice()))?;
+		batch.append(",");
+		batch.bind(s.value_hash().map(|h| h.to_vec()))?;
+		batch.append(")");
+	}
+	Ok(batch.execute(conn).await?)
+}
+
+/// Insert the values of storage changes into `storage_values`, once for every distinct value.
+async fn insert_storage_values<B: BlockT>(storage: &[StorageModel<B>], conn: &mut PgConnection) -> DbReturn {
+	let mut batch = Batch::new(
+		"storage_values",
+		r#"
+        INSERT INTO "storage_values" (
+            hash, value
+        ) VALUES
+        "#,
+		r#"
+        ON CONFLICT DO NOTHING
+        "#,
+	);
+
+	let mut inserted = hashbrown::HashSet::new();
+	for s in storage {
+		let (hash, data) = match (s.value_hash(), s.data()) {
+			(Some(hash), Some(data)) => (hash, data),
+			_ => continue,
+		};
+		if !inserted.insert(hash) {
+			continue;
+		}
+		batch.reserve(2)?;
+		if batch.current_num_arguments() > 0 {
 			batch.append(",");
-			batch.bind(s.data().map(|d| d.0.as_slice()))?;
-			batch.append(")");
 		}
-		Ok(batch.execute(conn).await?)
+		batch.append("(");
+		batch.bind(hash.to_vec())?;
+		batch.append(",");
+		batch.bind(data.0.as_slice())?;
+		batch.append(")");
 	}
+	Ok(batch.execute(conn).await?)
 }
 
 #[async_trait::async_trait]
@@ -392,3 +831,168 @@ impl Insert for Traces {
 fn time_to_std(time: chrono::Duration) -> Result<Duration> {
 	time.to_std().map_err(|_| ArchiveError::TimestampOutOfRange)
 }
+
+#[cfg(test)]
+mod tests {
+	use sp_core::H256;
+	use sp_storage::{StorageData, StorageKey};
+
+	use super::*;
+	use crate::generic::OpaqueBlock;
+
+	const BLOCK_HASH: [u8; 32] = [0x42; 32];
+
+	/// Changes of block 0 to `len` keys, with two distinct values and a deletion.
+	fn changes(len: usize) -> Vec<StorageModel<OpaqueBlock>> {
+		(0..len)
+			.map(|i| {
+				let value = match i % 3 {
+					0 => Some(StorageData(vec![0xaa; 64])),
+					1 => Some(StorageData(vec![0xbb; 64])),
+					_ => None,
+				};
+				StorageModel::new(H256(BLOCK_HASH), 0, false, StorageKey((i as u32).to_be_bytes().to_vec()), value)
+			})
+			.collect()
+	}
+
+	async fn insert_block(conn: &mut PgConnection) {
+		sqlx::query(
+			"INSERT INTO blocks (parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec)
+            VALUES ($1, $1, 0, $1, $1, $1, $1, 0)",
+		)
+		.bind(&BLOCK_HASH[..])
+		.execute(conn)
+		.await
+		.unwrap();
+	}
+
+	async fn stored_values(conn: &mut PgConnection) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+		sqlx::query_as(
+			"SELECT storage.key, COALESCE(storage.storage, storage_values.value) FROM storage
+            LEFT JOIN storage_values ON storage.storage IS NULL AND storage_values.hash = storage.value_hash
+            WHERE storage.hash = $1 ORDER BY storage.key",
+		)
+		.bind(&BLOCK_HASH[..])
+		.fetch_all(conn)
+		.await
+		.unwrap()
+	}
+
+	async fn count(conn: &mut PgConnection, query: &str) -> i64 {
+		sqlx::query_as::<_, (i64,)>(query).fetch_one(conn).await.unwrap().0
+	}
+
+	#[test]
+	fn should_reject_invalid_durations() {
+		assert!(PoolConfig::default().validate().is_ok());
+		for secs in [-1.0, f64::NAN, f64::INFINITY, 1e30].iter().copied() {
+			let config = PoolConfig { statement_timeout: Some(secs), ..Default::default() };
+			assert!(matches!(config.validate(), Err(ArchiveError::InvalidDuration("statement_timeout", _))));
+			let config = PoolConfig { idle_timeout: secs, ..Default::default() };
+			assert!(Database::pool_options(&config).is_err());
+		}
+	}
+
+	#[test]
+	fn should_pass_root_certificates() {
+		let config = DatabaseConfig { ssl_root_cert: Some("/ca.pem".into()), ..Default::default() };
+		assert_eq!(
+			connection_url("postgres://localhost/archive", &config).unwrap(),
+			"postgres://localhost/archive?sslrootcert=/ca.pem"
+		);
+	}
+
+	#[test]
+	fn should_connect_to_schema() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			create_schema(&*crate::DATABASE_URL, "archive_test").await.unwrap();
+			let url = schema_url(&crate::DATABASE_URL, "archive_test").unwrap();
+			let mut conn = PgConnection::connect(&url).await.unwrap();
+			let (schema,): (String,) = sqlx::query_as("SELECT current_schema()").fetch_one(&mut conn).await.unwrap();
+			assert_eq!(schema, "archive_test");
+			sqlx::query("DROP SCHEMA archive_test").execute(&mut conn).await.unwrap();
+		});
+	}
+
+	#[test]
+	fn should_keep_deduplicated_values_once() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = crate::PG_POOL.acquire().await.unwrap();
+			insert_block(&mut conn).await;
+			// batched inserts, and copies of large batches
+			for len in [3, copy::COPY_THRESHOLD + 1].iter().copied() {
+				let changes = changes(len);
+				assert_eq!(DeduplicatedStorage(changes.clone()).insert(&mut conn).await.unwrap(), len as u64);
+				// inserting a block again, e.g. when it is executed again, does not duplicate deletions
+				DeduplicatedStorage(changes.clone()).insert(&mut conn).await.unwrap();
+				assert_eq!(count(&mut conn, "SELECT COUNT(*) FROM storage").await, len as i64);
+
+				assert_eq!(count(&mut conn, "SELECT COUNT(*) FROM storage_values").await, 2);
+				assert_eq!(count(&mut conn, "SELECT COUNT(*) FROM storage WHERE storage IS NOT NULL").await, 0);
+				let stored = stored_values(&mut conn).await;
+				assert_eq!(stored.len(), len);
+				for (change, (key, value)) in changes.iter().zip(stored) {
+					assert_eq!(change.key().0, key);
+					assert_eq!(change.data().map(|d| d.0.clone()), value);
+				}
+				sqlx::query("DELETE FROM storage").execute(&mut conn).await.unwrap();
+			}
+		});
+	}
+
+	#[test]
+	fn should_delete_unreferenced_values() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = crate::PG_POOL.acquire().await.unwrap();
+			insert_block(&mut conn).await;
+			DeduplicatedStorage(changes(6)).insert(&mut conn).await.unwrap();
+			// the first changes are inserted again with their values inline, which don't reference `storage_values`
+			changes(3).insert(&mut conn).await.unwrap();
+			assert_eq!(count(&mut conn, "SELECT COUNT(*) FROM storage").await, 6);
+
+			assert_eq!(retention::delete_unreferenced_values(&mut conn, 1).await.unwrap(), 0);
+			sqlx::query("DELETE FROM storage WHERE storage IS NULL AND value_hash = $1")
+				.bind(&sp_core::hashing::blake2_256(&[0xaa; 64])[..])
+				.execute(&mut conn)
+				.await
+				.unwrap();
+			assert_eq!(retention::delete_unreferenced_values(&mut conn, 1).await.unwrap(), 1);
+
+			let values =
+				sqlx::query_as::<_, (Vec<u8>,)>("SELECT value FROM storage_values").fetch_all(&mut conn).await.unwrap();
+			assert_eq!(values, vec![(vec![0xbb; 64],)]);
+		});
+	}
+
+	#[test]
+	fn should_delete_values_of_reexecuted_blocks() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = crate::PG_POOL.acquire().await.unwrap();
+			insert_block(&mut conn).await;
+			DeduplicatedStorage(changes(6)).insert(&mut conn).await.unwrap();
+			// a value of changes which were pruned, only the retention policies delete it
+			sqlx::query("INSERT INTO storage_values (hash, value) VALUES ($1, $2)")
+				.bind(&[0xcc; 32][..])
+				.bind(&[0xcc; 64][..])
+				.execute(&mut conn)
+				.await
+				.unwrap();
+
+			// the block of the guard and the block of the changes
+			assert_eq!(reexecute(&*crate::DATABASE_URL, &BlockSelection::Range { from: 0, to: 0 }).await.unwrap(), 2);
+			assert_eq!(count(&mut conn, "SELECT COUNT(*) FROM storage").await, 0);
+			let values =
+				sqlx::query_as::<_, (Vec<u8>,)>("SELECT value FROM storage_values").fetch_all(&mut conn).await.unwrap();
+			assert_eq!(values, vec![(vec![0xcc; 64],)]);
+		});
+	}
+}
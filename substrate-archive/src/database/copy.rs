@@ -0,0 +1,203 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Bulk loading with `COPY ... FROM STDIN` in the binary format.
+//! Rows are copied into a temporary staging table, and merged into their table with `INSERT ... SELECT`,
+//! keeping the `ON CONFLICT` semantics of the multi-row inserts of [`Batch`](super::batch::Batch)
+//! without parsing and binding one parameter for every value.
+
+use std::convert::TryFrom;
+
+use codec::Encode;
+use sqlx::{postgres::PgConnection, Connection};
+
+use sp_runtime::traits::{Block as BlockT, Header as _, NumberFor};
+
+use crate::{database::models::StorageModel, error::Result, types::Block};
+
+/// Batches of at least this many rows are copied rather than inserted.
+pub(crate) const COPY_THRESHOLD: usize = 1_000;
+/// Size of the `CopyData` messages the encoded rows are sent in.
+const COPY_CHUNK: usize = 1024 * 1024;
+
+/// Rows encoded in the binary `COPY` format.
+pub(crate) struct BinaryCopy {
+	buf: Vec<u8>,
+}
+
+impl BinaryCopy {
+	pub(crate) fn new() -> Self {
+		let mut buf = Vec::with_capacity(COPY_CHUNK);
+		buf.extend_from_slice(b"PGCOPY\n\xff\r\n\0");
+		// flags and length of the header extension
+		buf.extend_from_slice(&0i32.to_be_bytes());
+		buf.extend_from_slice(&0i32.to_be_bytes());
+		Self { buf }
+	}
+
+	/// Start a row of `fields` fields.
+	pub(crate) fn row(&mut self, fields: i16) {
+		self.buf.extend_from_slice(&fields.to_be_bytes());
+	}
+
+	pub(crate) fn int4(&mut self, value: i32) {
+		self.buf.extend_from_slice(&4i32.to_be_bytes());
+		self.buf.extend_from_slice(&value.to_be_bytes());
+	}
+
+	pub(crate) fn bool(&mut self, value: bool) {
+		self.buf.extend_from_slice(&1i32.to_be_bytes());
+		self.buf.push(value as u8);
+	}
+
+	pub(crate) fn bytea(&mut self, value: Option<&[u8]>) -> Result<()> {
+		match value {
+			Some(value) => {
+				self.buf.extend_from_slice(&i32::try_from(value.len())?.to_be_bytes());
+				self.buf.extend_from_slice(value);
+			}
+			None => self.buf.extend_from_slice(&(-1i32).to_be_bytes()),
+		}
+		Ok(())
+	}
+
+	/// Append the trailer, and get the encoded rows.
+	pub(crate) fn finish(mut self) -> Vec<u8> {
+		self.buf.extend_from_slice(&(-1i16).to_be_bytes());
+		self.buf
+	}
+}
+
+/// Copy the encoded rows into the staging table created by `create` with `copy`,
+/// and merge them into their table with `merge`.
+///
+/// # Returns the number of rows merged
+async fn copy_and_merge(conn: &mut PgConnection, create: &str, copy: &str, merge: &str, rows: Vec<u8>) -> Result<u64> {
+	let mut tx = conn.begin().await?;
+	// the staging table lives as long as the connection, its rows as long as the transaction
+	sqlx::query(create).execute(&mut tx).await?;
+	let mut copy_in = tx.copy_in_raw(copy).await?;
+	for chunk in rows.chunks(COPY_CHUNK) {
+		copy_in.send(chunk).await?;
+	}
+	copy_in.finish().await?;
+	let merged = sqlx::query(merge).execute(&mut tx).await?.rows_affected();
+	tx.commit().await?;
+	Ok(merged)
+}
+
+/// Copy blocks into `blocks`, skipping blocks which already exist.
+pub(crate) async fn blocks<B>(blocks: &[Block<B>], conn: &mut PgConnection) -> Result<u64>
+where
+	B: BlockT,
+	NumberFor<B>: Into<u32>,
+{
+	let mut rows = BinaryCopy::new();
+	for b in blocks {
+		let header = b.inner.block.header();
+		let block_num: u32 = (*header.number()).into();
+		rows.row(8);
+		rows.bytea(Some(header.parent_hash().as_ref()))?;
+		rows.bytea(Some(header.hash().as_ref()))?;
+		rows.int4(i32::try_from(block_num)?);
+		rows.bytea(Some(header.state_root().as_ref()))?;
+		rows.bytea(Some(header.extrinsics_root().as_ref()))?;
+		rows.bytea(Some(header.digest().encode().as_slice()))?;
+		rows.bytea(Some(b.inner.block.extrinsics().encode().as_slice()))?;
+		rows.int4(i32::try_from(b.spec)?);
+	}
+	copy_and_merge(
+		conn,
+		r#"
+        CREATE TEMPORARY TABLE IF NOT EXISTS blocks_staging (
+            parent_hash bytea, hash bytea, block_num int, state_root bytea, extrinsics_root bytea, digest bytea,
+            ext bytea, spec int
+        ) ON COMMIT DELETE ROWS
+        "#,
+		"COPY blocks_staging FROM STDIN WITH (FORMAT binary)",
+		r#"
+        INSERT INTO blocks (parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec)
+        SELECT * FROM blocks_staging
+        ON CONFLICT DO NOTHING
+        "#,
+		rows.finish(),
+	)
+	.await
+}
+
+/// Copy storage changes into `storage`, replacing changes which already exist.
+/// Values are copied if `inline`, otherwise only their hash is.
+pub(crate) async fn storage<B: BlockT>(
+	storage: &[StorageModel<B>],
+	inline: bool,
+	conn: &mut PgConnection,
+) -> Result<u64> {
+	let mut rows = BinaryCopy::new();
+	for s in storage {
+		rows.row(6);
+		rows.int4(i32::try_from(s.block_num())?);
+		rows.bytea(Some(s.hash().as_ref()))?;
+		rows.bool(s.is_full());
+		rows.bytea(Some(s.key().0.as_slice()))?;
+		rows.bytea(s.data().filter(|_| inline).map(|d| d.0.as_slice()))?;
+		rows.bytea(s.value_hash().as_ref().map(|h| &h[..]))?;
+	}
+	// a change may be in the batch twice, but `ON CONFLICT DO UPDATE` may only update each row once
+	copy_and_merge(
+		conn,
+		r#"
+        CREATE TEMPORARY TABLE IF NOT EXISTS storage_staging (
+            block_num int, hash bytea, is_full boolean, key bytea, storage bytea, value_hash bytea
+        ) ON COMMIT DELETE ROWS
+        "#,
+		"COPY storage_staging FROM STDIN WITH (FORMAT binary)",
+		r#"
+        INSERT INTO storage (block_num, hash, is_full, key, storage, value_hash)
+        SELECT DISTINCT ON (hash, key, block_num) * FROM storage_staging
+        ON CONFLICT (hash, key, block_num) DO UPDATE SET
+            hash = EXCLUDED.hash,
+            key = EXCLUDED.key,
+            storage = EXCLUDED.storage,
+            value_hash = EXCLUDED.value_hash,
+            is_full = EXCLUDED.is_full
+        "#,
+		rows.finish(),
+	)
+	.await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn should_encode_binary_copy() {
+		let mut rows = BinaryCopy::new();
+		rows.row(3);
+		rows.int4(7);
+		rows.bool(true);
+		rows.bytea(None).unwrap();
+		let rows = rows.finish();
+
+		assert_eq!(&rows[..11], b"PGCOPY\n\xff\r\n\0");
+		let row = &rows[19..];
+		assert_eq!(&row[..2], &3i16.to_be_bytes());
+		assert_eq!(&row[2..10], &[0, 0, 0, 4, 0, 0, 0, 7]);
+		assert_eq!(&row[10..15], &[0, 0, 0, 1, 1]);
+		assert_eq!(&row[15..19], &(-1i32).to_be_bytes());
+		assert_eq!(&row[19..], &(-1i16).to_be_bytes());
+	}
+}
@@ -86,6 +86,17 @@ impl From<&Channel> for String {
 	}
 }
 
+impl Channel {
+	/// Name of the channel for tables in `schema`, matching `table_update_trigger_fn`.
+	fn name_in(&self, schema: &str) -> String {
+		if schema == "public" {
+			String::from(self)
+		} else {
+			format!("{}.{}", schema, String::from(self))
+		}
+	}
+}
+
 #[derive(Serialize, Deserialize)]
 struct ListenEvent {
 	table: String,
@@ -119,10 +130,12 @@ where
 	pub async fn spawn(self) -> Result<Listener> {
 		let (tx, rx) = flume::bounded(1);
 
+		let mut conn = PgConnection::connect(&self.pg_url).await?;
+		// notifications are namespaced by the schema the archive's tables are in
+		let schema: String = sqlx::query_scalar("SELECT current_schema()").fetch_one(&mut conn).await?;
 		let mut listener = PgListener::connect(&self.pg_url).await?;
-		let channels = self.channels.iter().map(String::from).collect::<Vec<String>>();
+		let channels = self.channels.iter().map(|c| c.name_in(&schema)).collect::<Vec<String>>();
 		listener.listen_all(channels.iter().map(|s| s.as_ref())).await?;
-		let mut conn = PgConnection::connect(&self.pg_url).await.unwrap();
 
 		let fut = async move {
 			let mut listener = listener.into_stream();
@@ -263,6 +276,12 @@ mod tests {
 		});
 	}
 
+	#[test]
+	fn should_namespace_channels() {
+		assert_eq!(Channel::Blocks.name_in("public"), "blocks_update");
+		assert_eq!(Channel::Blocks.name_in("kusama"), "kusama.blocks_update");
+	}
+
 	#[test]
 	fn should_deserialize_into_block() {
 		let json = serde_json::json!({
@@ -24,6 +24,7 @@ use codec::{Decode, Encode, Error as DecodeError};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
+use sp_core::hashing::blake2_256;
 use sp_runtime::{
 	generic::SignedBlock,
 	traits::{Block as BlockT, Header as HeaderT},
@@ -62,6 +63,66 @@ impl BlockModel {
 	}
 }
 
+/// A block whose execution failed more often than the configured `max_retries`.
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct FailedBlock {
+	pub id: i32,
+	pub block_num: i32,
+	pub hash: Vec<u8>,
+	pub spec: Option<i32>,
+	/// Error of the last attempt, if it was recorded before the archive restarted.
+	pub error: Option<String>,
+	pub attempts: i32,
+	pub failed_at: chrono::NaiveDateTime,
+}
+
+/// A storage key prefix whose changes are POSTed to a callback URL.
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct Watch {
+	pub id: i32,
+	pub prefix: Vec<u8>,
+	pub callback_url: String,
+	pub created_at: chrono::NaiveDateTime,
+}
+
+/// A message queued for a `PublisherSink`, in the order it was written.
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct PublisherEvent {
+	pub id: i64,
+	pub topic: String,
+	pub schema: String,
+	pub content_type: String,
+	pub block_num: i32,
+	pub payload: Vec<u8>,
+}
+
+/// A relay chain block in which a parablock was backed or included.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelayInclusion {
+	pub para_hash: Vec<u8>,
+	pub relay_block_num: i32,
+	pub relay_hash: Vec<u8>,
+	pub status: InclusionStatus,
+}
+
+/// How far a parablock got in a relay chain block.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum InclusionStatus {
+	/// The candidate was backed, and is pending availability.
+	Backed,
+	/// The candidate became available, and its head was included as the parachain head.
+	Included,
+}
+
+impl std::fmt::Display for InclusionStatus {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Backed => write!(f, "backed"),
+			Self::Included => write!(f, "included"),
+		}
+	}
+}
+
 /// Helper struct for decoding block modeling data into block type.
 pub struct BlockModelDecoder<B: BlockT> {
 	_marker: PhantomData<B>,
@@ -120,8 +181,16 @@ impl<Block: BlockT> StorageModel<Block> {
 	pub fn data(&self) -> Option<&StorageData> {
 		self.data.as_ref()
 	}
+
+	/// Blake2-256 hash of the value, keying it in `storage_values`.
+	pub fn value_hash(&self) -> Option<[u8; 32]> {
+		self.data.as_ref().map(|d| blake2_256(&d.0))
+	}
 }
 
+/// Storage changes whose values are kept once in `storage_values`, instead of inline.
+pub struct DeduplicatedStorage<Block: BlockT>(pub Vec<StorageModel<Block>>);
+
 impl<Block: BlockT> From<Storage<Block>> for Vec<StorageModel<Block>> {
 	fn from(original: Storage<Block>) -> Vec<StorageModel<Block>> {
 		let hash = *original.hash();
@@ -20,11 +20,17 @@ use std::convert::TryFrom;
 
 use hashbrown::HashSet;
 use serde::{de::DeserializeOwned, Deserialize};
-use sqlx::PgConnection;
+use sqlx::{Connection, PgConnection};
 
-use sp_runtime::traits::Block as BlockT;
+use sp_runtime::traits::{Block as BlockT, Header as _, NumberFor};
 
-use crate::{database::models::BlockModel, error::Result};
+use crate::{
+	database::{
+		models::{BlockModel, FailedBlock, PublisherEvent, RelayInclusion, Watch},
+		BlockSelection,
+	},
+	error::Result,
+};
 
 /// Return type of queries that `SELECT version`
 struct Version {
@@ -41,6 +47,27 @@ struct Max {
 	max: Option<i32>,
 }
 
+/// Return type of queries that `SELECT id, executed_at FROM blocks`, of executed blocks
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ExecutedBlock {
+	pub id: i32,
+	pub executed_at: chrono::NaiveDateTime,
+}
+
+/// Return type of queries that `SELECT id, block_num, hash, key, storage FROM storage`
+pub(crate) struct StorageRow {
+	pub id: i32,
+	pub block_num: i32,
+	pub hash: Vec<u8>,
+	pub key: Vec<u8>,
+	pub storage: Option<Vec<u8>>,
+}
+
+/// Return type of queries that `SELECT COUNT(*)`
+struct Count {
+	count: Option<i64>,
+}
+
 /// Return type of queries that `SELECT EXISTS`
 struct DoesExist {
 	exists: Option<bool>,
@@ -51,8 +78,22 @@ struct BlockNum {
 	block_num: i32,
 }
 
-// Return type of queries that `SELECT data`
-struct Bytes {
+// Return type of queries that `SELECT id, data, retries` from the background task queue
+struct QueuedJob {
+	id: i64,
+	data: Vec<u8>,
+	retries: i32,
+}
+
+// The block of a serialized `execute_block` job
+#[derive(Deserialize)]
+struct JobIn<B: BlockT> {
+	block: B,
+}
+
+// Return type of queries that `SELECT id, data` from the background task queue
+struct JobData {
+	id: i64,
 	data: Vec<u8>,
 }
 
@@ -88,32 +129,89 @@ pub(crate) async fn missing_blocks_min_max(
 	.collect())
 }
 
+/// Name of the `archive_state` entry holding the highest block number up to which every block is indexed.
+const INDEXER_CURSOR: &str = "indexer_cursor";
+
+/// Get the highest block number up to which every block is indexed, if it was recorded.
+pub(crate) async fn indexer_cursor(conn: &mut PgConnection) -> Result<Option<u32>> {
+	let row =
+		sqlx::query!("SELECT value FROM archive_state WHERE key = $1", INDEXER_CURSOR).fetch_optional(conn).await?;
+	Ok(row.map(|r| r.value as u32))
+}
+
+/// Record that every block up to `block_num` is indexed.
+/// The cursor never moves backwards, except when blocks are deleted.
+pub(crate) async fn set_indexer_cursor(conn: &mut PgConnection, block_num: u32) -> Result<()> {
+	sqlx::query!(
+		"INSERT INTO archive_state (key, value) VALUES ($1, $2)
+        ON CONFLICT (key) DO UPDATE SET value = GREATEST(archive_state.value, EXCLUDED.value), updated_at = now()",
+		INDEXER_CURSOR,
+		i32::try_from(block_num)?
+	)
+	.execute(conn)
+	.await?;
+	Ok(())
+}
+
+/// Move the indexer cursor up to the block before the first block missing after it.
+/// Nothing is looked at unless the block after the cursor exists, and then only the blocks up to the next gap,
+/// so each block is passed over once.
+pub(crate) async fn advance_indexer_cursor(conn: &mut PgConnection) -> Result<()> {
+	sqlx::query!(
+		"WITH indexed AS (SELECT COALESCE((SELECT value + 1 FROM archive_state WHERE key = $1), 0) AS next)
+        INSERT INTO archive_state (key, value)
+        SELECT $1, blocks.block_num FROM blocks, indexed
+        WHERE EXISTS (SELECT 1 FROM blocks WHERE block_num = indexed.next)
+        AND blocks.block_num >= indexed.next
+        AND NOT EXISTS (SELECT 1 FROM blocks AS next WHERE next.block_num = blocks.block_num + 1)
+        ORDER BY blocks.block_num
+        LIMIT 1
+        ON CONFLICT (key) DO UPDATE SET value = GREATEST(archive_state.value, EXCLUDED.value), updated_at = now()",
+		INDEXER_CURSOR
+	)
+	.execute(conn)
+	.await?;
+	Ok(())
+}
+
 /// Get the maximum block number from the relational database
 pub(crate) async fn max_block(conn: &mut PgConnection) -> Result<Option<u32>> {
 	let max = sqlx::query_as!(Max, "SELECT MAX(block_num) FROM blocks").fetch_one(conn).await?;
 	Ok(max.max.map(|v| v as u32))
 }
 
-/// Will get blocks such that they exist in the `blocks` table but they
-/// do not exist in the `storage` table
-/// blocks are ordered by spec version
+/// Get up to `limit` blocks which were not executed yet, with an id greater than `after`, ordered by id.
+/// Neither the genesis block, nor blocks which failed execution too often are returned.
 ///
 /// # Returns full blocks
-pub(crate) async fn blocks_storage_intersection(conn: &mut sqlx::PgConnection) -> Result<Vec<BlockModel>> {
+pub(crate) async fn unexecuted_blocks(conn: &mut PgConnection, after: i32, limit: i64) -> Result<Vec<BlockModel>> {
 	#[allow(clippy::toplevel_ref_arg)]
 	sqlx::query_as!(
 		BlockModel,
-		"SELECT *
+		"SELECT id, parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec
         FROM blocks
-        WHERE NOT EXISTS (SELECT * FROM storage WHERE storage.block_num = blocks.block_num)
-        AND blocks.block_num != 0
-        ORDER BY blocks.spec",
+        WHERE executed_at IS NULL AND id > $1 AND block_num != 0
+        AND NOT EXISTS (SELECT 1 FROM failed_blocks WHERE failed_blocks.hash = blocks.hash)
+        ORDER BY id
+        LIMIT $2",
+		after,
+		limit
 	)
 	.fetch_all(conn)
 	.await
 	.map_err(Into::into)
 }
 
+/// Record that the blocks with `hashes` were executed, once their storage is written.
+pub(crate) async fn mark_executed(conn: &mut PgConnection, hashes: &[Vec<u8>]) -> Result<u64> {
+	let marked =
+		sqlx::query!("UPDATE blocks SET executed_at = now() WHERE hash = ANY($1) AND executed_at IS NULL", hashes)
+			.execute(conn)
+			.await?
+			.rows_affected();
+	Ok(marked)
+}
+
 /// Get a block by id from the relational database
 pub(crate) async fn get_full_block_by_id(conn: &mut sqlx::PgConnection, id: i32) -> Result<BlockModel> {
 	#[allow(clippy::toplevel_ref_arg)]
@@ -178,22 +276,968 @@ pub(crate) async fn get_versions(conn: &mut PgConnection) -> Result<Vec<u32>> {
 		.collect())
 }
 
-/// Get all the blocks queued for execution in the background task queue.
-pub(crate) async fn get_all_blocks<B: BlockT + DeserializeOwned>(
+/// Get the number of blocks queued for execution, pending or in the background task queue.
+pub(crate) async fn queue_depth(conn: &mut PgConnection) -> Result<u64> {
+	let count = sqlx::query_as!(
+		Count,
+		"SELECT (SELECT COUNT(*) FROM pending_tasks WHERE job_type = 'execute_block')
+            + (SELECT COUNT(*) FROM _background_tasks WHERE job_type = 'execute_block') AS count"
+	)
+	.fetch_one(conn)
+	.await?;
+	Ok(count.count.unwrap_or(0) as u64)
+}
+
+/// Get the number of queued blocks which failed execution at least once.
+pub(crate) async fn failed_jobs(conn: &mut PgConnection) -> Result<u64> {
+	let count = sqlx::query_as!(
+		Count,
+		"SELECT COUNT(*) FROM _background_tasks WHERE job_type = 'execute_block' AND retries > 0"
+	)
+	.fetch_one(conn)
+	.await?;
+	Ok(count.count.unwrap_or(0) as u64)
+}
+
+/// Get the number of blocks in the database.
+pub(crate) async fn count_blocks(conn: &mut PgConnection) -> Result<u64> {
+	let count = sqlx::query_as!(Count, "SELECT COUNT(*) FROM blocks").fetch_one(conn).await?;
+	Ok(count.count.unwrap_or(0) as u64)
+}
+
+/// Get the number of blocks which were moved to `failed_blocks`.
+pub(crate) async fn count_failed_blocks(conn: &mut PgConnection) -> Result<u64> {
+	let count = sqlx::query_as!(Count, "SELECT COUNT(*) FROM failed_blocks").fetch_one(conn).await?;
+	Ok(count.count.unwrap_or(0) as u64)
+}
+
+/// Get the number of block numbers up to the highest block which are missing from the database.
+pub(crate) async fn count_missing_blocks(conn: &mut PgConnection) -> Result<u64> {
+	let count = sqlx::query_as!(
+		Count,
+		"SELECT COUNT(*)
+        FROM (SELECT MAX(block_num) AS max_num FROM blocks) max,
+            GENERATE_SERIES(0, max_num) AS missing_num
+        WHERE NOT EXISTS (SELECT id FROM blocks WHERE block_num = missing_num)"
+	)
+	.fetch_one(conn)
+	.await?;
+	Ok(count.count.unwrap_or(0) as u64)
+}
+
+/// Get the number of blocks, other than genesis, without any storage.
+pub(crate) async fn count_blocks_without_storage(conn: &mut PgConnection) -> Result<u64> {
+	let count = sqlx::query_as!(
+		Count,
+		"SELECT COUNT(*) FROM blocks
+        WHERE NOT EXISTS (SELECT * FROM storage WHERE storage.block_num = blocks.block_num)
+        AND blocks.block_num != 0"
+	)
+	.fetch_one(conn)
+	.await?;
+	Ok(count.count.unwrap_or(0) as u64)
+}
+
+/// Delete the blocks `from..=to`, with their storage and traces, and the storage values only they referenced,
+/// and move the indexer cursor before them.
+///
+/// # Returns the number of blocks deleted
+pub(crate) async fn delete_blocks(conn: &mut PgConnection, from: u32, to: u32) -> Result<u64> {
+	let from = i32::try_from(from)?;
+	let to = i32::try_from(to).unwrap_or(i32::MAX);
+	let mut tx = conn.begin().await?;
+	// storage is deleted with the blocks anyway, but deleting it first returns the values it referenced
+	let values = sqlx::query!(
+		r#"WITH deleted AS (
+            DELETE FROM storage WHERE block_num >= $1 AND block_num <= $2
+            RETURNING storage IS NULL AS deduplicated, value_hash
+        )
+        SELECT DISTINCT value_hash AS "value_hash!" FROM deleted WHERE deduplicated AND value_hash IS NOT NULL"#,
+		from,
+		to
+	)
+	.fetch_all(&mut tx)
+	.await?;
+	delete_values(&mut tx, &values.into_iter().map(|v| v.value_hash).collect::<Vec<_>>()).await?;
+	let deleted = sqlx::query!("DELETE FROM blocks WHERE block_num >= $1 AND block_num <= $2", from, to)
+		.execute(&mut tx)
+		.await?
+		.rows_affected();
+	if from == 0 {
+		sqlx::query!("DELETE FROM archive_state WHERE key = $1", INDEXER_CURSOR).execute(&mut tx).await?;
+	} else {
+		sqlx::query!(
+			"UPDATE archive_state SET value = $2, updated_at = now() WHERE key = $1 AND value > $2",
+			INDEXER_CURSOR,
+			from - 1
+		)
+		.execute(&mut tx)
+		.await?;
+	}
+	tx.commit().await?;
+	Ok(deleted)
+}
+
+/// Delete the storage and traces of the selected blocks, and the storage values only they referenced,
+/// and mark them as not executed.
+///
+/// # Returns the number of blocks whose storage was deleted
+pub(crate) async fn delete_execution_results(conn: &mut PgConnection, blocks: &BlockSelection) -> Result<u64> {
+	let (spec, from, to) = blocks.bounds()?;
+	let mut tx = conn.begin().await?;
+	let blocks = sqlx::query!(
+		"UPDATE blocks SET executed_at = NULL WHERE ($1::int IS NULL OR spec = $1) AND block_num BETWEEN $2 AND $3",
+		spec,
+		from,
+		to
+	)
+	.execute(&mut tx)
+	.await?
+	.rows_affected();
+	let values = sqlx::query!(
+		r#"WITH deleted AS (
+            DELETE FROM storage WHERE hash IN
+            (SELECT hash FROM blocks WHERE ($1::int IS NULL OR spec = $1) AND block_num BETWEEN $2 AND $3)
+            RETURNING storage IS NULL AS deduplicated, value_hash
+        )
+        SELECT DISTINCT value_hash AS "value_hash!" FROM deleted WHERE deduplicated AND value_hash IS NOT NULL"#,
+		spec,
+		from,
+		to
+	)
+	.fetch_all(&mut tx)
+	.await?;
+	delete_values(&mut tx, &values.into_iter().map(|v| v.value_hash).collect::<Vec<_>>()).await?;
+	sqlx::query!(
+		"DELETE FROM state_traces WHERE hash IN
+        (SELECT hash FROM blocks WHERE ($1::int IS NULL OR spec = $1) AND block_num BETWEEN $2 AND $3)",
+		spec,
+		from,
+		to
+	)
+	.execute(&mut tx)
+	.await?;
+	tx.commit().await?;
+	Ok(blocks)
+}
+
+/// Get up to `limit` of the selected blocks with an id greater than `after`, ordered by id.
+/// The genesis block is never selected, since it is not executed.
+pub(crate) async fn selected_blocks(
 	conn: &mut PgConnection,
-) -> Result<impl Iterator<Item = Result<B>>> {
+	blocks: &BlockSelection,
+	after: i32,
+	limit: i64,
+) -> Result<Vec<BlockModel>> {
+	let (spec, from, to) = blocks.bounds()?;
 	#[allow(clippy::toplevel_ref_arg)]
-	let blocks = sqlx::query_as!(Bytes, "SELECT data FROM _background_tasks WHERE job_type = 'execute_block'",)
+	sqlx::query_as!(
+		BlockModel,
+		"SELECT id, parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec
+        FROM blocks
+        WHERE ($1::int IS NULL OR spec = $1) AND block_num BETWEEN $2 AND $3 AND block_num != 0 AND id > $4
+        ORDER BY id
+        LIMIT $5",
+		spec,
+		from,
+		to,
+		after,
+		limit
+	)
+	.fetch_all(conn)
+	.await
+	.map_err(Into::into)
+}
+
+/// Count the selected blocks which were not executed yet.
+pub(crate) async fn count_unexecuted(conn: &mut PgConnection, blocks: &BlockSelection) -> Result<u64> {
+	let (spec, from, to) = blocks.bounds()?;
+	let unexecuted = sqlx::query_as!(
+		Count,
+		"SELECT COUNT(*) FROM blocks
+        WHERE ($1::int IS NULL OR spec = $1) AND block_num BETWEEN $2 AND $3 AND block_num != 0
+        AND executed_at IS NULL",
+		spec,
+		from,
+		to
+	)
+	.fetch_one(conn)
+	.await?;
+	Ok(unexecuted.count.unwrap_or(0) as u64)
+}
+
+/// Delete the traces of blocks before `block_num`.
+///
+/// # Returns the number of traces deleted
+pub(crate) async fn delete_traces_before(conn: &mut PgConnection, block_num: u32) -> Result<u64> {
+	let block_num = i32::try_from(block_num).unwrap_or(i32::MAX);
+	let deleted =
+		sqlx::query!("DELETE FROM state_traces WHERE block_num < $1", block_num).execute(conn).await?.rows_affected();
+	Ok(deleted)
+}
+
+/// Delete up to `limit` traces of blocks before `block_num`.
+///
+/// # Returns the number of traces deleted
+pub(crate) async fn delete_traces_batch(conn: &mut PgConnection, block_num: u32, limit: i64) -> Result<u64> {
+	let block_num = i32::try_from(block_num).unwrap_or(i32::MAX);
+	let deleted = sqlx::query!(
+		"DELETE FROM state_traces WHERE id IN (SELECT id FROM state_traces WHERE block_num < $1 LIMIT $2)",
+		block_num,
+		limit
+	)
+	.execute(conn)
+	.await?
+	.rows_affected();
+	Ok(deleted)
+}
+
+/// Delete up to `limit` storage changes which were superseded by another change of the same key,
+/// at or before `block_num`. Keys starting with one of the `keep` prefixes are never deleted.
+/// The state at `block_num` and every later block can still be read afterwards.
+///
+/// # Returns the number of storage changes deleted
+pub(crate) async fn delete_superseded_storage(
+	conn: &mut PgConnection,
+	block_num: u32,
+	keep: &[Vec<u8>],
+	limit: i64,
+) -> Result<u64> {
+	let block_num = i32::try_from(block_num).unwrap_or(i32::MAX);
+	let deleted = sqlx::query!(
+		"DELETE FROM storage WHERE id IN (
+            SELECT id FROM storage s
+            WHERE s.block_num < $1
+            AND NOT EXISTS (SELECT 1 FROM UNNEST($2::bytea[]) AS keep(prefix)
+                WHERE substring(s.key FROM 1 FOR length(keep.prefix)) = keep.prefix)
+            AND EXISTS (SELECT 1 FROM storage n WHERE n.key = s.key AND n.block_num > s.block_num AND n.block_num <= $1)
+            LIMIT $3
+        )",
+		block_num,
+		keep,
+		limit
+	)
+	.execute(conn)
+	.await?
+	.rows_affected();
+	Ok(deleted)
+}
+
+/// Move the job enqueued last on this connection from the background task queue to `pending_tasks`, with the
+/// hash of the block it executes, so that queued blocks can be looked up without decoding the queue.
+/// Must be called in the transaction the job was enqueued in, before the job is fetched from the queue.
+pub(crate) async fn defer_last_job(conn: &mut PgConnection, hash: &[u8], priority: i32) -> Result<()> {
+	sqlx::query!(
+		"WITH job AS (
+            DELETE FROM _background_tasks WHERE id = currval(pg_get_serial_sequence('_background_tasks', 'id'))
+            RETURNING job_type, is_async, data
+        )
+        INSERT INTO pending_tasks (priority, job_type, is_async, data, block_hash)
+        SELECT $2, job_type, is_async, data, $1 FROM job",
+		hash,
+		priority
+	)
+	.execute(conn)
+	.await?;
+	Ok(())
+}
+
+/// Move pending jobs to the background task queue, the highest priority first, until it holds `queued` jobs
+/// which were not retried yet.
+///
+/// # Returns the number of jobs moved
+pub(crate) async fn release_pending_jobs(conn: &mut PgConnection, queued: i64) -> Result<u64> {
+	let released = sqlx::query!(
+		"WITH released AS (
+            DELETE FROM pending_tasks WHERE id IN (
+                SELECT id FROM pending_tasks ORDER BY priority DESC, id
+                LIMIT GREATEST($1 - (SELECT COUNT(*) FROM _background_tasks WHERE retries = 0), 0)
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, priority, job_type, is_async, data, block_hash
+        )
+        INSERT INTO _background_tasks (job_type, is_async, data, block_hash)
+        SELECT job_type, is_async, data, block_hash FROM released ORDER BY priority DESC, id",
+		queued
+	)
+	.execute(conn)
+	.await?
+	.rows_affected();
+	Ok(released)
+}
+
+/// Record the block hashes of `execute_block` jobs which were queued before hashes were recorded.
+///
+/// # Returns the number of jobs tagged
+pub(crate) async fn tag_queued_jobs<B: BlockT + DeserializeOwned>(conn: &mut PgConnection) -> Result<u64> {
+	let jobs = sqlx::query_as!(
+		JobData,
+		"SELECT id, data FROM _background_tasks WHERE job_type = 'execute_block' AND block_hash IS NULL"
+	)
+	.fetch_all(&mut *conn)
+	.await?;
+	if jobs.is_empty() {
+		return Ok(0);
+	}
+	let mut ids = Vec::with_capacity(jobs.len());
+	let mut hashes = Vec::with_capacity(jobs.len());
+	for job in jobs {
+		let JobIn::<B> { block } = rmp_serde::from_read(job.data.as_slice())?;
+		ids.push(job.id);
+		hashes.push(block.hash().as_ref().to_vec());
+	}
+	let tagged = sqlx::query!(
+		"UPDATE _background_tasks SET block_hash = tagged.hash
+        FROM UNNEST($1::bigint[], $2::bytea[]) AS tagged(id, hash)
+        WHERE _background_tasks.id = tagged.id",
+		&ids,
+		&hashes
+	)
+	.execute(conn)
+	.await?
+	.rows_affected();
+	Ok(tagged)
+}
+
+/// Get the hashes out of `hashes` of the blocks which are queued for execution, pending or in the background
+/// task queue.
+pub(crate) async fn queued_blocks(conn: &mut PgConnection, hashes: &[Vec<u8>]) -> Result<HashSet<Vec<u8>>> {
+	let rows = sqlx::query!(
+		r#"SELECT block_hash AS "block_hash!" FROM pending_tasks WHERE block_hash = ANY($1)
+        UNION ALL
+        SELECT block_hash FROM _background_tasks WHERE block_hash = ANY($1)"#,
+		hashes
+	)
+	.fetch_all(conn)
+	.await?;
+	Ok(rows.into_iter().map(|r| r.block_hash).collect())
+}
+
+/// Move `execute_block` jobs which failed at least `max_retries` times out of the background task queue
+/// and into `failed_blocks`. `error` looks up the last error of a block by its hash.
+///
+/// # Returns the number of blocks moved
+pub(crate) async fn dead_letter_jobs<B>(
+	conn: &mut PgConnection,
+	max_retries: u32,
+	error: impl Fn(&[u8]) -> Option<String>,
+) -> Result<u64>
+where
+	B: BlockT + DeserializeOwned,
+	NumberFor<B>: Into<u32>,
+{
+	let max_retries = i32::try_from(max_retries).unwrap_or(i32::MAX);
+	let mut tx = conn.begin().await?;
+	// skip jobs which are locked, because they are being executed right now
+	let jobs = sqlx::query_as!(
+		QueuedJob,
+		"SELECT id, data, retries FROM _background_tasks
+        WHERE job_type = 'execute_block' AND retries >= $1
+        FOR UPDATE SKIP LOCKED",
+		max_retries
+	)
+	.fetch_all(&mut tx)
+	.await?;
+
+	for job in jobs.iter() {
+		let JobIn::<B> { block } = rmp_serde::from_read(job.data.as_slice())?;
+		let hash = block.hash();
+		let block_num: u32 = (*block.header().number()).into();
+		sqlx::query!(
+			"INSERT INTO failed_blocks (block_num, hash, spec, error, attempts, data)
+        VALUES ($1, $2, (SELECT spec FROM blocks WHERE hash = $2), $3, $4, $5)
+        ON CONFLICT (hash) DO UPDATE SET
+            error = EXCLUDED.error,
+            attempts = failed_blocks.attempts + EXCLUDED.attempts,
+            data = EXCLUDED.data,
+            failed_at = EXCLUDED.failed_at",
+			block_num as i32,
+			hash.as_ref(),
+			error(hash.as_ref()),
+			job.retries,
+			job.data
+		)
+		.execute(&mut tx)
+		.await?;
+		sqlx::query!("DELETE FROM _background_tasks WHERE id = $1", job.id).execute(&mut tx).await?;
+	}
+	tx.commit().await?;
+	Ok(jobs.len() as u64)
+}
+
+/// Get all blocks whose execution failed too many times, ordered by block number.
+pub(crate) async fn failed_blocks(conn: &mut PgConnection) -> Result<Vec<FailedBlock>> {
+	sqlx::query_as!(
+		FailedBlock,
+		"SELECT id, block_num, hash, spec, error, attempts, failed_at FROM failed_blocks ORDER BY block_num"
+	)
+	.fetch_all(conn)
+	.await
+	.map_err(Into::into)
+}
+
+/// Queue the failed blocks with the given ids for execution again.
+///
+/// # Returns the number of blocks queued
+pub(crate) async fn retry_failed_blocks(conn: &mut PgConnection, ids: &[i32]) -> Result<u64> {
+	let mut tx = conn.begin().await?;
+	sqlx::query!(
+		"INSERT INTO _background_tasks (job_type, is_async, data, block_hash)
+        SELECT 'execute_block', false, data, hash FROM failed_blocks WHERE id = ANY($1)",
+		ids
+	)
+	.execute(&mut tx)
+	.await?;
+	let retried =
+		sqlx::query!("DELETE FROM failed_blocks WHERE id = ANY($1)", ids).execute(&mut tx).await?.rows_affected();
+	tx.commit().await?;
+	Ok(retried)
+}
+
+/// Remove the failed blocks with the given ids, without executing them.
+///
+/// # Returns the number of blocks removed
+pub(crate) async fn discard_failed_blocks(conn: &mut PgConnection, ids: &[i32]) -> Result<u64> {
+	let discarded =
+		sqlx::query!("DELETE FROM failed_blocks WHERE id = ANY($1)", ids).execute(conn).await?.rows_affected();
+	Ok(discarded)
+}
+
+/// Queue messages of a publisher.
+pub(crate) async fn append_publisher_events(
+	conn: &mut PgConnection,
+	publisher: &str,
+	events: &[PublisherEvent],
+) -> Result<u64> {
+	let topics = events.iter().map(|e| e.topic.clone()).collect::<Vec<_>>();
+	let schemas = events.iter().map(|e| e.schema.clone()).collect::<Vec<_>>();
+	let content_types = events.iter().map(|e| e.content_type.clone()).collect::<Vec<_>>();
+	let block_nums = events.iter().map(|e| e.block_num).collect::<Vec<_>>();
+	let payloads = events.iter().map(|e| e.payload.clone()).collect::<Vec<_>>();
+	let appended = sqlx::query!(
+		"INSERT INTO publisher_events (publisher, topic, schema, content_type, block_num, payload)
+        SELECT $1, * FROM UNNEST($2::text[], $3::text[], $4::text[], $5::int[], $6::bytea[])",
+		publisher,
+		&topics,
+		&schemas,
+		&content_types,
+		&block_nums,
+		&payloads
+	)
+	.execute(conn)
+	.await?
+	.rows_affected();
+	Ok(appended)
+}
+
+/// Get the oldest `limit` messages a publisher has not delivered yet.
+pub(crate) async fn pending_publisher_events(
+	conn: &mut PgConnection,
+	publisher: &str,
+	limit: i64,
+) -> Result<Vec<PublisherEvent>> {
+	sqlx::query_as!(
+		PublisherEvent,
+		"SELECT id, topic, schema, content_type, block_num, payload FROM publisher_events
+        WHERE publisher = $1 ORDER BY id LIMIT $2",
+		publisher,
+		limit
+	)
+	.fetch_all(conn)
+	.await
+	.map_err(Into::into)
+}
+
+/// Remove delivered messages of a publisher, and move its cursor to the last of them.
+pub(crate) async fn ack_publisher_events(
+	conn: &mut PgConnection,
+	publisher: &str,
+	events: &[PublisherEvent],
+) -> Result<()> {
+	let last = match events.last() {
+		Some(last) => last,
+		None => return Ok(()),
+	};
+	let ids = events.iter().map(|e| e.id).collect::<Vec<_>>();
+	let mut tx = conn.begin().await?;
+	sqlx::query!("DELETE FROM publisher_events WHERE publisher = $1 AND id = ANY($2)", publisher, &ids)
+		.execute(&mut tx)
+		.await?;
+	sqlx::query!(
+		"INSERT INTO publisher_cursors (publisher, last_event, block_num)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (publisher) DO UPDATE SET
+            last_event = EXCLUDED.last_event,
+            block_num = EXCLUDED.block_num,
+            updated_at = CURRENT_TIMESTAMP",
+		publisher,
+		last.id,
+		last.block_num
+	)
+	.execute(&mut tx)
+	.await?;
+	tx.commit().await?;
+	Ok(())
+}
+
+/// Get all watched storage key prefixes.
+pub(crate) async fn watches(conn: &mut PgConnection) -> Result<Vec<Watch>> {
+	sqlx::query_as!(Watch, "SELECT id, prefix, callback_url, created_at FROM watches ORDER BY id")
 		.fetch_all(conn)
+		.await
+		.map_err(Into::into)
+}
+
+/// Watch a storage key prefix.
+///
+/// # Returns the id of the watch
+pub(crate) async fn add_watch(conn: &mut PgConnection, prefix: &[u8], callback_url: &str) -> Result<i32> {
+	let row =
+		sqlx::query!("INSERT INTO watches (prefix, callback_url) VALUES ($1, $2) RETURNING id", prefix, callback_url)
+			.fetch_one(conn)
+			.await?;
+	Ok(row.id)
+}
+
+/// Stop watching a storage key prefix.
+///
+/// # Returns the number of watches removed
+pub(crate) async fn remove_watch(conn: &mut PgConnection, id: i32) -> Result<u64> {
+	let removed = sqlx::query!("DELETE FROM watches WHERE id = $1", id).execute(conn).await?.rows_affected();
+	Ok(removed)
+}
+
+/// Get the value of each of `keys` before the block of the same index in `block_nums`, from the latest change of
+/// the key in an earlier block. The value is `None` if there is no such change, or if it removed the key.
+pub(crate) async fn previous_values(
+	conn: &mut PgConnection,
+	keys: &[Vec<u8>],
+	block_nums: &[i32],
+) -> Result<Vec<(Vec<u8>, Option<Vec<u8>>)>> {
+	let rows = sqlx::query!(
+		r#"SELECT changed.key AS "key!", latest.value
+        FROM UNNEST($1::bytea[], $2::int[]) WITH ORDINALITY AS changed(key, block_num, n)
+        LEFT JOIN LATERAL (
+            SELECT COALESCE(storage.storage, storage_values.value) AS value FROM storage
+            LEFT JOIN storage_values ON storage.storage IS NULL AND storage_values.hash = storage.value_hash
+            WHERE storage.key = changed.key AND storage.block_num < changed.block_num
+            ORDER BY storage.block_num DESC
+            LIMIT 1
+        ) latest ON true
+        ORDER BY changed.n"#,
+		keys,
+		block_nums
+	)
+	.fetch_all(conn)
+	.await?;
+	Ok(rows.into_iter().map(|r| (r.key, r.value)).collect())
+}
+
+/// Get up to `limit` blocks executed after `after`, in the order they were executed.
+/// Blocks executed less than `lag_secs` ago are left out, their storage may not be committed yet.
+pub(crate) async fn executed_blocks(
+	conn: &mut PgConnection,
+	after: Option<&ExecutedBlock>,
+	lag_secs: f64,
+	limit: i64,
+) -> Result<Vec<ExecutedBlock>> {
+	sqlx::query_as!(
+		ExecutedBlock,
+		r#"SELECT id, executed_at AS "executed_at!" FROM blocks
+        WHERE executed_at <= LOCALTIMESTAMP - make_interval(secs => $3)
+        AND ($1::timestamp IS NULL OR (executed_at, id) > ($1, $2))
+        ORDER BY executed_at, id LIMIT $4"#,
+		after.map(|b| b.executed_at),
+		after.map(|b| b.id).unwrap_or(0),
+		lag_secs,
+		limit
+	)
+	.fetch_all(conn)
+	.await
+	.map_err(Into::into)
+}
+
+/// Get the changes of `keys` in the storage of the blocks with ids `block_ids`.
+pub(crate) async fn storage_changes(
+	conn: &mut PgConnection,
+	block_ids: &[i32],
+	keys: &[Vec<u8>],
+) -> Result<Vec<StorageRow>> {
+	sqlx::query_as!(
+		StorageRow,
+		"SELECT storage.id, storage.block_num, storage.hash, key,
+            COALESCE(storage.storage, storage_values.value) AS storage
+        FROM blocks
+        INNER JOIN storage ON storage.block_num = blocks.block_num AND storage.hash = blocks.hash
+        LEFT JOIN storage_values ON storage.storage IS NULL AND storage_values.hash = storage.value_hash
+        WHERE blocks.id = ANY($1) AND key = ANY($2) ORDER BY storage.id",
+		block_ids,
+		keys
+	)
+	.fetch_all(conn)
+	.await
+	.map_err(Into::into)
+}
+
+/// Get the last relay chain block scanned for a parachain.
+pub(crate) async fn relay_cursor(conn: &mut PgConnection, para_id: i32) -> Result<Option<ExecutedBlock>> {
+	sqlx::query_as!(ExecutedBlock, "SELECT block_id AS id, executed_at FROM relay_cursors WHERE para_id = $1", para_id)
+		.fetch_optional(conn)
+		.await
+		.map_err(Into::into)
+}
+
+/// Record the relay chain blocks parablocks were backed or included in,
+/// and move the cursor of the parachain to the relay chain block `scanned`.
+pub(crate) async fn add_relay_inclusions(
+	conn: &mut PgConnection,
+	para_id: i32,
+	inclusions: &[RelayInclusion],
+	scanned: &ExecutedBlock,
+) -> Result<()> {
+	let para_hashes = inclusions.iter().map(|i| i.para_hash.clone()).collect::<Vec<_>>();
+	let relay_block_nums = inclusions.iter().map(|i| i.relay_block_num).collect::<Vec<_>>();
+	let relay_hashes = inclusions.iter().map(|i| i.relay_hash.clone()).collect::<Vec<_>>();
+	let statuses = inclusions.iter().map(|i| i.status.to_string()).collect::<Vec<_>>();
+	let mut tx = conn.begin().await?;
+	sqlx::query!(
+		"INSERT INTO relay_inclusions (para_id, para_hash, relay_block_num, relay_hash, status)
+        SELECT $1, * FROM UNNEST($2::bytea[], $3::int[], $4::bytea[], $5::text[])
+        ON CONFLICT DO NOTHING",
+		para_id,
+		&para_hashes,
+		&relay_block_nums,
+		&relay_hashes,
+		&statuses
+	)
+	.execute(&mut tx)
+	.await?;
+	sqlx::query!(
+		"INSERT INTO relay_cursors (para_id, block_id, executed_at) VALUES ($1, $2, $3)
+        ON CONFLICT (para_id) DO UPDATE SET block_id = EXCLUDED.block_id, executed_at = EXCLUDED.executed_at",
+		para_id,
+		scanned.id,
+		scanned.executed_at
+	)
+	.execute(&mut tx)
+	.await?;
+	tx.commit().await?;
+	Ok(())
+}
+
+/// Wait for, and hold until the end of the transaction, the lock of creating partitions.
+pub(crate) async fn lock_partitions(conn: &mut PgConnection) -> Result<()> {
+	// not checked, the macros don't support the `void` it returns
+	sqlx::query("SELECT pg_advisory_xact_lock(hashtext('partitions'))").execute(conn).await?;
+	Ok(())
+}
+
+/// Wait for, and hold until the end of the transaction, a shared lock of `storage_values`,
+/// so that no value is deleted while storage changes referencing it are being inserted.
+pub(crate) async fn share_storage_values(conn: &mut PgConnection) -> Result<()> {
+	// not checked, the macros don't support the `void` it returns
+	sqlx::query("SELECT pg_advisory_xact_lock_shared(hashtext('storage_values'))").execute(conn).await?;
+	Ok(())
+}
+
+/// Delete the values of `hashes` from `storage_values`, unless a storage change still references them.
+/// Waits until no storage changes referencing values are being inserted, and must be called in a transaction,
+/// which holds the lock of `storage_values` until it ends.
+///
+/// # Returns the number of values deleted
+async fn delete_values(conn: &mut PgConnection, hashes: &[Vec<u8>]) -> Result<u64> {
+	if hashes.is_empty() {
+		return Ok(0);
+	}
+	// not checked, the macros don't support the `void` it returns
+	sqlx::query("SELECT pg_advisory_xact_lock(hashtext('storage_values'))").execute(&mut *conn).await?;
+	let deleted = sqlx::query!(
+		"DELETE FROM storage_values v WHERE v.hash = ANY($1)
+        AND NOT EXISTS (SELECT 1 FROM storage s WHERE s.storage IS NULL AND s.value_hash = v.hash)",
+		hashes
+	)
+	.execute(conn)
+	.await?
+	.rows_affected();
+	Ok(deleted)
+}
+
+/// Delete the values no storage change references any more, among the first `limit` values of `storage_values`
+/// whose hash is greater than `after`.
+/// Waits until no storage changes referencing values are being inserted.
+///
+/// # Returns the greatest hash scanned, `None` once all values were, and the number of values deleted
+pub(crate) async fn delete_unreferenced_values(
+	conn: &mut PgConnection,
+	after: &[u8],
+	limit: i64,
+) -> Result<(Option<Vec<u8>>, u64)> {
+	let mut tx = conn.begin().await?;
+	// not checked, the macros don't support the `void` it returns
+	sqlx::query("SELECT pg_advisory_xact_lock(hashtext('storage_values'))").execute(&mut tx).await?;
+	let scanned = sqlx::query!(
+		r#"WITH batch AS (
+            SELECT hash FROM storage_values WHERE hash > $1 ORDER BY hash LIMIT $2
+        ), deleted AS (
+            DELETE FROM storage_values v USING batch
+            WHERE v.hash = batch.hash
+            AND NOT EXISTS (SELECT 1 FROM storage s WHERE s.storage IS NULL AND s.value_hash = v.hash)
+            RETURNING v.hash
+        )
+        SELECT (SELECT hash FROM batch ORDER BY hash DESC LIMIT 1) AS last, (SELECT COUNT(*) FROM deleted) AS "deleted!""#,
+		after,
+		limit
+	)
+	.fetch_one(&mut tx)
+	.await?;
+	tx.commit().await?;
+	Ok((scanned.last, scanned.deleted as u64))
+}
+
+/// Get the block number the last partition of `table` ends before.
+pub(crate) async fn partition_bound(conn: &mut PgConnection, table: &str) -> Result<Option<u32>> {
+	let max = sqlx::query_as!(Max, "SELECT MAX(to_block) FROM partitions WHERE table_name = $1", table)
+		.fetch_one(conn)
+		.await?;
+	Ok(max.max.map(|v| v as u32))
+}
+
+/// Create the partition `name` of `table`, for the blocks `from..to`.
+/// `table` and `name` must be valid identifiers, they can't be bound as parameters of DDL.
+pub(crate) async fn create_partition(
+	conn: &mut PgConnection,
+	table: &str,
+	name: &str,
+	from: u32,
+	to: u32,
+) -> Result<()> {
+	let (from, to) = (i32::try_from(from)?, i32::try_from(to)?);
+	let mut tx = conn.begin().await?;
+	sqlx::query(&format!("CREATE TABLE {} PARTITION OF {} FOR VALUES FROM ({}) TO ({})", name, table, from, to))
+		.execute(&mut tx)
 		.await?;
+	sqlx::query!(
+		"INSERT INTO partitions (name, table_name, from_block, to_block) VALUES ($1, $2, $3, $4)",
+		name,
+		table,
+		from,
+		to
+	)
+	.execute(&mut tx)
+	.await?;
+	tx.commit().await?;
+	Ok(())
+}
+
+/// Get the names of the partitions of `table` which only hold rows of blocks before `block_num`.
+pub(crate) async fn partitions_before(conn: &mut PgConnection, table: &str, block_num: u32) -> Result<Vec<String>> {
+	let block_num = i32::try_from(block_num).unwrap_or(i32::MAX);
+	let rows = sqlx::query!(
+		"SELECT name FROM partitions WHERE table_name = $1 AND to_block <= $2 ORDER BY to_block",
+		table,
+		block_num
+	)
+	.fetch_all(conn)
+	.await?;
+	Ok(rows.into_iter().map(|r| r.name).collect())
+}
+
+/// Drop the partition `name` with all its rows.
+/// `name` must be the name of a partition recorded in `partitions`.
+pub(crate) async fn drop_partition(conn: &mut PgConnection, name: &str) -> Result<()> {
+	let mut tx = conn.begin().await?;
+	sqlx::query(&format!("DROP TABLE {}", name)).execute(&mut tx).await?;
+	sqlx::query!("DELETE FROM partitions WHERE name = $1", name).execute(&mut tx).await?;
+	tx.commit().await?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	async fn insert_block(conn: &mut PgConnection, block_num: i32) {
+		sqlx::query(
+			"INSERT INTO blocks (parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec)
+            VALUES ($1, $1, $2, $1, $1, $1, $1, 0)",
+		)
+		.bind(vec![block_num as u8; 32])
+		.bind(block_num)
+		.execute(conn)
+		.await
+		.unwrap();
+	}
+
+	async fn queue_job(conn: &mut PgConnection, hash: u8, priority: i32) {
+		let mut tx = conn.begin().await.unwrap();
+		sqlx::query("INSERT INTO _background_tasks (job_type, is_async, data) VALUES ('execute_block', false, $1)")
+			.bind(&[0u8][..])
+			.execute(&mut tx)
+			.await
+			.unwrap();
+		defer_last_job(&mut tx, &[hash], priority).await.unwrap();
+		tx.commit().await.unwrap();
+	}
+
+	#[test]
+	fn should_release_pending_jobs_by_priority() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = crate::PG_POOL.acquire().await.unwrap();
+			for (hash, priority) in [(1, 0), (2, 1), (3, 0), (4, 1)].iter() {
+				queue_job(&mut conn, *hash, *priority).await;
+			}
+			assert_eq!(queue_depth(&mut conn).await.unwrap(), 4);
+			assert_eq!(queued_blocks(&mut conn, &[vec![1], vec![5]]).await.unwrap().len(), 1);
+
+			assert_eq!(release_pending_jobs(&mut conn, 3).await.unwrap(), 3);
+			// the queue already holds as many jobs as requested
+			assert_eq!(release_pending_jobs(&mut conn, 3).await.unwrap(), 0);
+			let queued = sqlx::query_as::<_, (Vec<u8>,)>("SELECT block_hash FROM _background_tasks ORDER BY id")
+				.fetch_all(&mut conn)
+				.await
+				.unwrap();
+			assert_eq!(queued, vec![(vec![2],), (vec![4],), (vec![1],)]);
+			assert_eq!(queue_depth(&mut conn).await.unwrap(), 4);
+			assert_eq!(queued_blocks(&mut conn, &[vec![1], vec![3]]).await.unwrap().len(), 2);
+		});
+	}
+
+	#[test]
+	fn should_advance_indexer_cursor_through_contiguous_blocks() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = crate::PG_POOL.acquire().await.unwrap();
+			sqlx::query("DELETE FROM archive_state").execute(&mut conn).await.unwrap();
+			// the guard inserted the genesis block
+			for n in [1, 2, 4].iter() {
+				insert_block(&mut conn, *n).await;
+			}
+			advance_indexer_cursor(&mut conn).await.unwrap();
+			assert_eq!(indexer_cursor(&mut conn).await.unwrap(), Some(2));
+
+			// blocks beyond a gap don't move the cursor
+			insert_block(&mut conn, 5).await;
+			advance_indexer_cursor(&mut conn).await.unwrap();
+			assert_eq!(indexer_cursor(&mut conn).await.unwrap(), Some(2));
+
+			insert_block(&mut conn, 3).await;
+			advance_indexer_cursor(&mut conn).await.unwrap();
+			assert_eq!(indexer_cursor(&mut conn).await.unwrap(), Some(5));
+
+			// the cursor never moves back, but deleting blocks moves it before them
+			set_indexer_cursor(&mut conn, 1).await.unwrap();
+			assert_eq!(indexer_cursor(&mut conn).await.unwrap(), Some(5));
+			delete_blocks(&mut conn, 4, 5).await.unwrap();
+			assert_eq!(indexer_cursor(&mut conn).await.unwrap(), Some(3));
+			delete_blocks(&mut conn, 0, 3).await.unwrap();
+			assert_eq!(indexer_cursor(&mut conn).await.unwrap(), None);
+		});
+	}
+
+	#[test]
+	fn should_delete_superseded_storage() {
+		use sp_core::hashing::twox_128;
+
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = crate::PG_POOL.acquire().await.unwrap();
+			for n in 1..=4 {
+				insert_block(&mut conn, n).await;
+			}
+			crate::partitions::create_ahead(&mut conn, 4, 10).await.unwrap();
+			let key = |pallet: &[u8], item: &[u8]| [twox_128(pallet), twox_128(item)].concat();
+			let (now, moment) = (key(b"Timestamp", b"Now"), key(b"Timestamp", b"Moment"));
+			let (balances, account, number) =
+				(key(b"Balances", b"Locks"), key(b"System", b"Account"), key(b"System", b"Number"));
+			let changes = vec![
+				(&now, 1),
+				(&now, 2),
+				(&now, 4),
+				(&moment, 1),
+				(&balances, 1),
+				(&balances, 2),
+				(&account, 1),
+				(&account, 2),
+				(&number, 1),
+				(&number, 2),
+			];
+			for (key, block_num) in changes.iter() {
+				sqlx::query(
+					"INSERT INTO storage (block_num, hash, is_full, key, storage) VALUES ($1, $2, false, $3, $4)",
+				)
+				.bind(block_num)
+				.bind(vec![*block_num as u8; 32])
+				.bind(key.as_slice())
+				.bind(vec![*block_num as u8])
+				.execute(&mut conn)
+				.await
+				.unwrap();
+			}
+
+			let keep = vec![twox_128(b"Balances").to_vec(), account.clone()];
+			assert_eq!(delete_superseded_storage(&mut conn, 3, &keep, 1).await.unwrap(), 1);
+			assert_eq!(delete_superseded_storage(&mut conn, 3, &keep, 10).await.unwrap(), 1);
+			assert_eq!(delete_superseded_storage(&mut conn, 3, &keep, 10).await.unwrap(), 0);
+
+			let kept: Vec<(Vec<u8>, i32)> =
+				sqlx::query_as("SELECT key, block_num FROM storage ORDER BY key, block_num")
+					.fetch_all(&mut conn)
+					.await
+					.unwrap();
+			let mut expected = changes
+				.iter()
+				// superseded by a change at or before block 3
+				.filter(|(key, block_num)| !(*block_num == 1 && (*key == &now || *key == &number)))
+				.map(|(key, block_num)| (key.to_vec(), *block_num))
+				.collect::<Vec<_>>();
+			expected.sort();
+			assert_eq!(kept, expected);
+		});
+	}
+
+	#[test]
+	fn should_get_previous_values() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = crate::PG_POOL.acquire().await.unwrap();
+			insert_block(&mut conn, 1).await;
+			insert_block(&mut conn, 2).await;
+			crate::partitions::create_ahead(&mut conn, 2, 10).await.unwrap();
+			// an inline value at block 1, a deduplicated value at block 2
+			sqlx::query("INSERT INTO storage_values (hash, value) VALUES ($1, $2)")
+				.bind(vec![9u8; 32])
+				.bind(b"b".to_vec())
+				.execute(&mut conn)
+				.await
+				.unwrap();
+			sqlx::query(
+				"INSERT INTO storage (block_num, hash, is_full, key, storage, value_hash)
+                VALUES (1, $1, false, $3, 'a', NULL), (2, $2, false, $3, NULL, $4)",
+			)
+			.bind(vec![1u8; 32])
+			.bind(vec![2u8; 32])
+			.bind(b"key".to_vec())
+			.bind(vec![9u8; 32])
+			.execute(&mut conn)
+			.await
+			.unwrap();
 
-	// temporary struct to deserialize job
-	#[derive(Deserialize)]
-	struct JobIn<BL: BlockT> {
-		block: BL,
+			let keys = vec![b"key".to_vec(), b"key".to_vec(), b"key".to_vec(), b"unknown".to_vec()];
+			let values = previous_values(&mut conn, &keys, &[3, 2, 1, 3]).await.unwrap();
+			assert_eq!(
+				values,
+				vec![
+					(b"key".to_vec(), Some(b"b".to_vec())),
+					(b"key".to_vec(), Some(b"a".to_vec())),
+					(b"key".to_vec(), None),
+					(b"unknown".to_vec(), None),
+				]
+			);
+		});
 	}
-	Ok(blocks.into_iter().map(|r| {
-		let b: JobIn<B> = rmp_serde::from_read(r.data.as_slice())?;
-		Ok(b.block)
-	}))
 }
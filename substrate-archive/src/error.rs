@@ -42,6 +42,16 @@ pub enum ArchiveError {
 	Sql(#[from] sqlx::Error),
 	#[error("migration error: {0}")]
 	Migration(#[from] sqlx::migrate::MigrateError),
+	#[error("Invalid schema name {0}, expected lowercase letters, digits and underscores")]
+	InvalidSchema(String),
+	#[error("Connections do not use schema {0}, the `options` connection parameter requires sqlx 0.5.10 or later")]
+	SchemaIgnored(String),
+	#[error("Invalid storage prefix {0}, expected hex or `Pallet.Item`")]
+	InvalidPrefix(String),
+	#[error("Invalid callback URL {0}, expected a plain http:// URL")]
+	InvalidCallback(String),
+	#[error("Invalid {0} of {1} seconds, expected a finite number which is not negative")]
+	InvalidDuration(&'static str, f64),
 
 	/// background job error
 	#[error("Background job err {0}")]
@@ -59,9 +69,6 @@ pub enum ArchiveError {
 	#[error("Sending on a disconnected channel")]
 	Channel,
 
-	#[error("{0}")]
-	ConvertStorageChanges(String),
-
 	// archive backend error
 	#[error("Backend error: {0}")]
 	Backend(#[from] substrate_archive_backend::BackendError),
@@ -73,6 +80,29 @@ pub enum ArchiveError {
 	#[error("Tracing: {0}")]
 	Trace(#[from] TracingError),
 
+	#[error("Prometheus error: {0}")]
+	Prometheus(#[from] prometheus::Error),
+
+	/// error of a user-defined sink
+	#[error("Sink error: {0}")]
+	Sink(Box<dyn std::error::Error + Send + Sync>),
+
+	// export error
+	#[error("Arrow error: {0}")]
+	Arrow(#[from] arrow::error::ArrowError),
+	#[error("Parquet error: {0}")]
+	Parquet(#[from] parquet::errors::ParquetError),
+	#[error("Invalid export option: {0}")]
+	Export(String),
+	#[error("Failed to decode {0}")]
+	Decode(String),
+
+	// command line error
+	#[error("Invalid config: {0}")]
+	Config(#[from] toml::de::Error),
+	#[error("{0}")]
+	Command(&'static str),
+
 	#[error("Rust Standard Library does not support negative durations")]
 	TimestampOutOfRange,
 }
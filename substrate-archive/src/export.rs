@@ -0,0 +1,902 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Export archived data to Parquet files, for analytics tools like DuckDB or Spark.
+//!
+//! Every table is written to its own directory, partitioned in the Hive layout:
+//! `<dir>/<table>/spec=<version>/part-<first block>.parquet` when partitioning by spec version,
+//! or `<dir>/<table>/blocks=<start>-<end>/part-<first block>.parquet` when partitioning by block range.
+//! Tables are only exported into empty directories, so that the files of different exports never overlap.
+//! Storage, digests and the `ext` of blocks are exported as the SCALE-encoded bytes kept by the archive.
+//! Extrinsics and events are exported one per row, decoded with the metadata of their runtime version, with their
+//! arguments as JSON. Events are read from the `System.Events` storage, so they are only exported for blocks whose
+//! storage was indexed, and not filtered out or pruned.
+
+mod decode;
+
+use std::{
+	collections::HashMap,
+	convert::TryFrom,
+	fs::{self, File},
+	path::PathBuf,
+	str::FromStr,
+	sync::Arc,
+};
+
+use arrow::{
+	array::{ArrayRef, BinaryArray, BooleanArray, Int32Array, Int64Array, StringArray, TimestampNanosecondArray},
+	datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit},
+	record_batch::RecordBatch,
+};
+use codec::Decode;
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use sqlx::{Connection, PgConnection};
+
+use sp_core::hashing::twox_128;
+
+use self::decode::{ChainMetadata, DecodedEvent, DecodedExtrinsic};
+use crate::error::{ArchiveError, Result};
+
+/// Number of blocks whose rows are fetched from the database at once.
+const CHUNK_BLOCKS: u32 = 1000;
+
+/// The tables which can be exported.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExportTable {
+	Blocks,
+	Extrinsics,
+	Events,
+	Storage,
+	Traces,
+}
+
+impl ExportTable {
+	pub const ALL: [ExportTable; 5] =
+		[ExportTable::Blocks, ExportTable::Extrinsics, ExportTable::Events, ExportTable::Storage, ExportTable::Traces];
+
+	/// Name of the directory the table is exported to.
+	fn dir(&self) -> &'static str {
+		match self {
+			ExportTable::Blocks => BlockRow::TABLE,
+			ExportTable::Extrinsics => ExtrinsicRow::TABLE,
+			ExportTable::Events => EventRow::TABLE,
+			ExportTable::Storage => StorageRow::TABLE,
+			ExportTable::Traces => TraceRow::TABLE,
+		}
+	}
+}
+
+impl FromStr for ExportTable {
+	type Err = ArchiveError;
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"blocks" => Ok(ExportTable::Blocks),
+			"extrinsics" => Ok(ExportTable::Extrinsics),
+			"events" => Ok(ExportTable::Events),
+			"storage" => Ok(ExportTable::Storage),
+			"traces" | "state_traces" => Ok(ExportTable::Traces),
+			_ => Err(ArchiveError::Export(format!(
+				"Unknown table {}, expected one of blocks, extrinsics, events, storage, traces",
+				s
+			))),
+		}
+	}
+}
+
+/// How the exported files are partitioned.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Partitioning {
+	/// A partition for every runtime spec version.
+	Spec,
+	/// A partition for every range of this many blocks.
+	Range(u32),
+}
+
+impl FromStr for Partitioning {
+	type Err = ArchiveError;
+	/// Parses `spec`, or `range:<blocks>`.
+	fn from_str(s: &str) -> Result<Self> {
+		match s.split_once(':') {
+			None if s == "spec" => Ok(Partitioning::Spec),
+			Some(("range", size)) => match size.parse() {
+				Ok(size) if size > 0 => Ok(Partitioning::Range(size)),
+				_ => Err(ArchiveError::Export(format!("Invalid range size {}", size))),
+			},
+			_ => Err(ArchiveError::Export(format!("Unknown partitioning {}, expected spec or range:<blocks>", s))),
+		}
+	}
+}
+
+impl Partitioning {
+	fn partition(&self, block_num: i32, spec: i32) -> String {
+		match self {
+			Partitioning::Spec => format!("spec={}", spec),
+			Partitioning::Range(size) => {
+				let size = i64::from(*size);
+				let start = i64::from(block_num) / size * size;
+				format!("blocks={}-{}", start, start + size - 1)
+			}
+		}
+	}
+}
+
+/// What to export, and where to.
+#[derive(Clone, Debug)]
+pub struct ExportConfig {
+	/// Directory the files are written to.
+	pub dir: PathBuf,
+	/// First block of the range, inclusive.
+	pub from: u32,
+	/// Last block of the range, inclusive.
+	pub to: u32,
+	pub partitioning: Partitioning,
+	pub tables: Vec<ExportTable>,
+}
+
+/// Result of an export.
+#[derive(Clone, Debug, Default)]
+pub struct ExportSummary {
+	/// The Parquet files written.
+	pub files: Vec<PathBuf>,
+	/// Number of rows written, over all tables.
+	pub rows: u64,
+}
+
+/// Export the tables and block range in `config` to Parquet files.
+/// Fails before writing anything if the directory of one of the tables is not empty.
+pub async fn export<T: AsRef<str>>(url: T, config: &ExportConfig) -> Result<ExportSummary> {
+	for table in config.tables.iter() {
+		let dir = config.dir.join(table.dir());
+		if dir.exists() && fs::read_dir(&dir)?.next().is_some() {
+			return Err(ArchiveError::Export(format!(
+				"{} is not empty, remove it or export to another directory",
+				dir.display()
+			)));
+		}
+	}
+	let mut conn = PgConnection::connect(url.as_ref()).await?;
+	let mut summary = ExportSummary::default();
+	let mut metadata = MetadataCache::default();
+	for table in config.tables.iter() {
+		match table {
+			ExportTable::Blocks => export_table::<BlockRow>(&mut conn, &mut metadata, config, &mut summary).await?,
+			ExportTable::Extrinsics => {
+				export_table::<ExtrinsicRow>(&mut conn, &mut metadata, config, &mut summary).await?
+			}
+			ExportTable::Events => export_table::<EventRow>(&mut conn, &mut metadata, config, &mut summary).await?,
+			ExportTable::Storage => export_table::<StorageRow>(&mut conn, &mut metadata, config, &mut summary).await?,
+			ExportTable::Traces => export_table::<TraceRow>(&mut conn, &mut metadata, config, &mut summary).await?,
+		}
+	}
+	Ok(summary)
+}
+
+async fn export_table<R: Row>(
+	conn: &mut PgConnection,
+	metadata: &mut MetadataCache,
+	config: &ExportConfig,
+	summary: &mut ExportSummary,
+) -> Result<()> {
+	let schema = Arc::new(R::schema());
+	let mut writer = PartitionWriter::new(config.dir.join(R::TABLE), schema.clone());
+	let mut from = config.from;
+	while from <= config.to {
+		let to = from.saturating_add(CHUNK_BLOCKS - 1).min(config.to);
+		let rows = R::fetch(conn, metadata, i32::try_from(from)?, i32::try_from(to)?).await?;
+		// rows are ordered by block number, so every partition is a contiguous run of rows
+		let mut start = 0;
+		while start < rows.len() {
+			let partition = config.partitioning.partition(rows[start].block_num(), rows[start].spec());
+			let end = rows[start..]
+				.iter()
+				.position(|r| config.partitioning.partition(r.block_num(), r.spec()) != partition)
+				.map(|i| start + i)
+				.unwrap_or_else(|| rows.len());
+			let batch = R::to_batch(&rows[start..end], schema.clone())?;
+			writer.write(partition, rows[start].block_num(), &batch)?;
+			summary.rows += (end - start) as u64;
+			start = end;
+		}
+		log::debug!("Exported {} {} rows of blocks {}..={}", rows.len(), R::TABLE, from, to);
+		from = match to.checked_add(1) {
+			Some(next) => next,
+			None => break,
+		};
+	}
+	summary.files.extend(writer.finish()?);
+	Ok(())
+}
+
+/// Writes the batches of one table to a file per partition.
+struct PartitionWriter {
+	dir: PathBuf,
+	schema: SchemaRef,
+	props: WriterProperties,
+	current: Option<(String, ArrowWriter<File>)>,
+	files: Vec<PathBuf>,
+}
+
+impl PartitionWriter {
+	fn new(dir: PathBuf, schema: SchemaRef) -> Self {
+		let props = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+		Self { dir, schema, props, current: None, files: Vec::new() }
+	}
+
+	fn write(&mut self, partition: String, first_block: i32, batch: &RecordBatch) -> Result<()> {
+		if self.current.as_ref().map(|(p, _)| p != &partition).unwrap_or(true) {
+			self.close()?;
+			let path = self.file_path(&partition, first_block)?;
+			let writer = ArrowWriter::try_new(File::create(&path)?, self.schema.clone(), Some(self.props.clone()))?;
+			self.files.push(path);
+			self.current = Some((partition, writer));
+		}
+		if let Some((_, writer)) = self.current.as_mut() {
+			writer.write(batch)?;
+		}
+		Ok(())
+	}
+
+	fn file_path(&self, partition: &str, first_block: i32) -> Result<PathBuf> {
+		let dir = self.dir.join(partition);
+		fs::create_dir_all(&dir)?;
+		Ok(dir.join(format!("part-{:010}.parquet", first_block)))
+	}
+
+	fn close(&mut self) -> Result<()> {
+		if let Some((_, mut writer)) = self.current.take() {
+			writer.close()?;
+		}
+		Ok(())
+	}
+
+	fn finish(mut self) -> Result<Vec<PathBuf>> {
+		self.close()?;
+		Ok(self.files)
+	}
+}
+
+/// The decoded metadata of the runtime versions seen so far, or `None` if it couldn't be decoded.
+#[derive(Default)]
+struct MetadataCache(HashMap<i32, Option<ChainMetadata>>);
+
+impl MetadataCache {
+	async fn get(&mut self, conn: &mut PgConnection, spec: i32) -> Result<Option<&ChainMetadata>> {
+		if !self.0.contains_key(&spec) {
+			let meta = sqlx::query!("SELECT meta FROM metadata WHERE version = $1", spec).fetch_optional(conn).await?;
+			let meta = match meta.map(|m| ChainMetadata::new(&m.meta)) {
+				Some(Ok(meta)) => Some(meta),
+				Some(Err(e)) => {
+					log::warn!("Extrinsics and events of runtime version {} are not decoded: {}", spec, e);
+					None
+				}
+				None => {
+					log::warn!("Extrinsics and events of runtime version {} are not decoded: no metadata", spec);
+					None
+				}
+			};
+			self.0.insert(spec, meta);
+		}
+		Ok(self.0.get(&spec).and_then(Option::as_ref))
+	}
+}
+
+/// A row of an exported table.
+#[async_trait::async_trait]
+trait Row: Sized + Send {
+	/// Name of the directory the table is exported to.
+	const TABLE: &'static str;
+	fn schema() -> Schema;
+	/// Fetch the rows of blocks `from..=to`, ordered by block number.
+	async fn fetch(conn: &mut PgConnection, metadata: &mut MetadataCache, from: i32, to: i32) -> Result<Vec<Self>>;
+	fn to_batch(rows: &[Self], schema: SchemaRef) -> Result<RecordBatch>;
+	fn block_num(&self) -> i32;
+	fn spec(&self) -> i32;
+}
+
+struct BlockRow {
+	block_num: i32,
+	hash: Vec<u8>,
+	parent_hash: Vec<u8>,
+	state_root: Vec<u8>,
+	extrinsics_root: Vec<u8>,
+	digest: Vec<u8>,
+	ext: Vec<u8>,
+	spec: i32,
+}
+
+#[async_trait::async_trait]
+impl Row for BlockRow {
+	const TABLE: &'static str = "blocks";
+
+	fn schema() -> Schema {
+		Schema::new(vec![
+			Field::new("block_num", DataType::Int32, false),
+			Field::new("hash", DataType::Binary, false),
+			Field::new("parent_hash", DataType::Binary, false),
+			Field::new("state_root", DataType::Binary, false),
+			Field::new("extrinsics_root", DataType::Binary, false),
+			Field::new("digest", DataType::Binary, false),
+			Field::new("ext", DataType::Binary, false),
+			Field::new("spec", DataType::Int32, false),
+		])
+	}
+
+	async fn fetch(conn: &mut PgConnection, _: &mut MetadataCache, from: i32, to: i32) -> Result<Vec<Self>> {
+		sqlx::query_as!(
+			BlockRow,
+			"SELECT block_num, hash, parent_hash, state_root, extrinsics_root, digest, ext, spec FROM blocks
+        WHERE block_num BETWEEN $1 AND $2 ORDER BY block_num",
+			from,
+			to
+		)
+		.fetch_all(conn)
+		.await
+		.map_err(Into::into)
+	}
+
+	fn to_batch(rows: &[Self], schema: SchemaRef) -> Result<RecordBatch> {
+		let binary = |f: fn(&Self) -> &[u8]| Arc::new(BinaryArray::from(rows.iter().map(f).collect::<Vec<_>>()));
+		let columns: Vec<ArrayRef> = vec![
+			Arc::new(Int32Array::from(rows.iter().map(|r| r.block_num).collect::<Vec<_>>())),
+			binary(|r| r.hash.as_slice()),
+			binary(|r| r.parent_hash.as_slice()),
+			binary(|r| r.state_root.as_slice()),
+			binary(|r| r.extrinsics_root.as_slice()),
+			binary(|r| r.digest.as_slice()),
+			binary(|r| r.ext.as_slice()),
+			Arc::new(Int32Array::from(rows.iter().map(|r| r.spec).collect::<Vec<_>>())),
+		];
+		Ok(RecordBatch::try_new(schema, columns)?)
+	}
+
+	fn block_num(&self) -> i32 {
+		self.block_num
+	}
+
+	fn spec(&self) -> i32 {
+		self.spec
+	}
+}
+
+struct ExtrinsicRow {
+	block_num: i32,
+	hash: Vec<u8>,
+	spec: i32,
+	/// Index of the extrinsic in its block.
+	index: i32,
+	extrinsic: Vec<u8>,
+	decoded: DecodedExtrinsic,
+}
+
+#[async_trait::async_trait]
+impl Row for ExtrinsicRow {
+	const TABLE: &'static str = "extrinsics";
+
+	fn schema() -> Schema {
+		Schema::new(vec![
+			Field::new("block_num", DataType::Int32, false),
+			Field::new("hash", DataType::Binary, false),
+			Field::new("spec", DataType::Int32, false),
+			Field::new("index", DataType::Int32, false),
+			Field::new("signed", DataType::Boolean, false),
+			Field::new("signer", DataType::Utf8, true),
+			Field::new("pallet", DataType::Utf8, true),
+			Field::new("call", DataType::Utf8, true),
+			Field::new("args", DataType::Utf8, true),
+			Field::new("extrinsic", DataType::Binary, false),
+		])
+	}
+
+	async fn fetch(conn: &mut PgConnection, metadata: &mut MetadataCache, from: i32, to: i32) -> Result<Vec<Self>> {
+		let blocks = sqlx::query!(
+			"SELECT block_num, hash, spec, ext FROM blocks WHERE block_num BETWEEN $1 AND $2 ORDER BY block_num",
+			from,
+			to
+		)
+		.fetch_all(&mut *conn)
+		.await?;
+		let mut rows = Vec::new();
+		for block in blocks {
+			let meta = metadata.get(conn, block.spec).await?;
+			for (index, extrinsic) in Vec::<Vec<u8>>::decode(&mut block.ext.as_slice())?.into_iter().enumerate() {
+				let decoded = match meta {
+					Some(meta) => meta.extrinsic(&extrinsic),
+					None => DecodedExtrinsic { signed: decode::is_signed(&extrinsic), ..Default::default() },
+				};
+				rows.push(ExtrinsicRow {
+					block_num: block.block_num,
+					hash: block.hash.clone(),
+					spec: block.spec,
+					index: i32::try_from(index)?,
+					extrinsic,
+					decoded,
+				});
+			}
+		}
+		Ok(rows)
+	}
+
+	fn to_batch(rows: &[Self], schema: SchemaRef) -> Result<RecordBatch> {
+		let call = |r: &Self| r.decoded.call.as_ref();
+		let signers = rows.iter().map(|r| r.decoded.signer.as_ref().map(|s| s.to_string())).collect::<Vec<_>>();
+		let args =
+			rows.iter().map(|r| call(r).and_then(|c| c.args.as_ref()).map(|a| a.to_string())).collect::<Vec<_>>();
+		let columns: Vec<ArrayRef> = vec![
+			Arc::new(Int32Array::from(rows.iter().map(|r| r.block_num).collect::<Vec<_>>())),
+			Arc::new(BinaryArray::from(rows.iter().map(|r| r.hash.as_slice()).collect::<Vec<_>>())),
+			Arc::new(Int32Array::from(rows.iter().map(|r| r.spec).collect::<Vec<_>>())),
+			Arc::new(Int32Array::from(rows.iter().map(|r| r.index).collect::<Vec<_>>())),
+			Arc::new(BooleanArray::from(rows.iter().map(|r| r.decoded.signed).collect::<Vec<_>>())),
+			Arc::new(StringArray::from(signers.iter().map(Option::as_deref).collect::<Vec<_>>())),
+			Arc::new(StringArray::from(rows.iter().map(|r| call(r).map(|c| c.pallet.as_str())).collect::<Vec<_>>())),
+			Arc::new(StringArray::from(rows.iter().map(|r| call(r).map(|c| c.name.as_str())).collect::<Vec<_>>())),
+			Arc::new(StringArray::from(args.iter().map(Option::as_deref).collect::<Vec<_>>())),
+			Arc::new(BinaryArray::from(rows.iter().map(|r| r.extrinsic.as_slice()).collect::<Vec<_>>())),
+		];
+		Ok(RecordBatch::try_new(schema, columns)?)
+	}
+
+	fn block_num(&self) -> i32 {
+		self.block_num
+	}
+
+	fn spec(&self) -> i32 {
+		self.spec
+	}
+}
+
+struct EventRow {
+	block_num: i32,
+	hash: Vec<u8>,
+	spec: i32,
+	/// Index of the event in its block.
+	index: i32,
+	event: DecodedEvent,
+}
+
+#[async_trait::async_trait]
+impl Row for EventRow {
+	const TABLE: &'static str = "events";
+
+	fn schema() -> Schema {
+		Schema::new(vec![
+			Field::new("block_num", DataType::Int32, false),
+			Field::new("hash", DataType::Binary, false),
+			Field::new("spec", DataType::Int32, false),
+			Field::new("index", DataType::Int32, false),
+			Field::new("phase", DataType::Utf8, false),
+			Field::new("extrinsic_index", DataType::Int32, true),
+			Field::new("pallet", DataType::Utf8, false),
+			Field::new("event", DataType::Utf8, false),
+			Field::new("args", DataType::Utf8, true),
+		])
+	}
+
+	async fn fetch(conn: &mut PgConnection, metadata: &mut MetadataCache, from: i32, to: i32) -> Result<Vec<Self>> {
+		let key = [twox_128(b"System"), twox_128(b"Events")].concat();
+		let values = sqlx::query!(
+			r#"SELECT DISTINCT ON (storage.block_num, storage.hash) storage.block_num, storage.hash, blocks.spec,
+            COALESCE(storage.storage, storage_values.value) AS "events?"
+        FROM storage JOIN blocks ON storage.hash = blocks.hash
+        LEFT JOIN storage_values ON storage.storage IS NULL AND storage_values.hash = storage.value_hash
+        WHERE storage.key = $3 AND storage.block_num BETWEEN $1 AND $2 ORDER BY storage.block_num, storage.hash"#,
+			from,
+			to,
+			key
+		)
+		.fetch_all(&mut *conn)
+		.await?;
+		let mut rows = Vec::new();
+		for value in values {
+			let (meta, events) = match (metadata.get(conn, value.spec).await?, value.events) {
+				(Some(meta), Some(events)) => (meta, events),
+				_ => continue,
+			};
+			let mut decoded = Vec::new();
+			if let Err(e) = meta.events(&events, &mut decoded) {
+				log::warn!("Failed to decode all events of block {}: {}", value.block_num, e);
+			}
+			for (index, event) in decoded.into_iter().enumerate() {
+				let index = i32::try_from(index)?;
+				rows.push(EventRow {
+					block_num: value.block_num,
+					hash: value.hash.clone(),
+					spec: value.spec,
+					index,
+					event,
+				});
+			}
+		}
+		Ok(rows)
+	}
+
+	fn to_batch(rows: &[Self], schema: SchemaRef) -> Result<RecordBatch> {
+		let args = rows.iter().map(|r| r.event.event.args.as_ref().map(|a| a.to_string())).collect::<Vec<_>>();
+		let columns: Vec<ArrayRef> = vec![
+			Arc::new(Int32Array::from(rows.iter().map(|r| r.block_num).collect::<Vec<_>>())),
+			Arc::new(BinaryArray::from(rows.iter().map(|r| r.hash.as_slice()).collect::<Vec<_>>())),
+			Arc::new(Int32Array::from(rows.iter().map(|r| r.spec).collect::<Vec<_>>())),
+			Arc::new(Int32Array::from(rows.iter().map(|r| r.index).collect::<Vec<_>>())),
+			Arc::new(StringArray::from(rows.iter().map(|r| r.event.phase.as_str()).collect::<Vec<_>>())),
+			Arc::new(Int32Array::from(
+				rows.iter()
+					.map(|r| r.event.extrinsic_index.map(i32::try_from).transpose())
+					.collect::<std::result::Result<Vec<_>, _>>()?,
+			)),
+			Arc::new(StringArray::from(rows.iter().map(|r| r.event.event.pallet.as_str()).collect::<Vec<_>>())),
+			Arc::new(StringArray::from(rows.iter().map(|r| r.event.event.name.as_str()).collect::<Vec<_>>())),
+			Arc::new(StringArray::from(args.iter().map(Option::as_deref).collect::<Vec<_>>())),
+		];
+		Ok(RecordBatch::try_new(schema, columns)?)
+	}
+
+	fn block_num(&self) -> i32 {
+		self.block_num
+	}
+
+	fn spec(&self) -> i32 {
+		self.spec
+	}
+}
+
+struct StorageRow {
+	block_num: i32,
+	hash: Vec<u8>,
+	spec: i32,
+	is_full: bool,
+	key: Vec<u8>,
+	storage: Option<Vec<u8>>,
+}
+
+#[async_trait::async_trait]
+impl Row for StorageRow {
+	const TABLE: &'static str = "storage";
+
+	fn schema() -> Schema {
+		Schema::new(vec![
+			Field::new("block_num", DataType::Int32, false),
+			Field::new("hash", DataType::Binary, false),
+			Field::new("spec", DataType::Int32, false),
+			Field::new("is_full", DataType::Boolean, false),
+			Field::new("key", DataType::Binary, false),
+			Field::new("storage", DataType::Binary, true),
+		])
+	}
+
+	async fn fetch(conn: &mut PgConnection, _: &mut MetadataCache, from: i32, to: i32) -> Result<Vec<Self>> {
+		sqlx::query_as!(
+			StorageRow,
+			"SELECT storage.block_num, storage.hash, blocks.spec, storage.is_full, storage.key,
+        COALESCE(storage.storage, storage_values.value) AS storage
+        FROM storage JOIN blocks ON storage.hash = blocks.hash
+        LEFT JOIN storage_values ON storage.storage IS NULL AND storage_values.hash = storage.value_hash
+        WHERE storage.block_num BETWEEN $1 AND $2 ORDER BY storage.block_num, storage.id",
+			from,
+			to
+		)
+		.fetch_all(conn)
+		.await
+		.map_err(Into::into)
+	}
+
+	fn to_batch(rows: &[Self], schema: SchemaRef) -> Result<RecordBatch> {
+		let columns: Vec<ArrayRef> = vec![
+			Arc::new(Int32Array::from(rows.iter().map(|r| r.block_num).collect::<Vec<_>>())),
+			Arc::new(BinaryArray::from(rows.iter().map(|r| r.hash.as_slice()).collect::<Vec<_>>())),
+			Arc::new(Int32Array::from(rows.iter().map(|r| r.spec).collect::<Vec<_>>())),
+			Arc::new(BooleanArray::from(rows.iter().map(|r| r.is_full).collect::<Vec<_>>())),
+			Arc::new(BinaryArray::from(rows.iter().map(|r| r.key.as_slice()).collect::<Vec<_>>())),
+			Arc::new(BinaryArray::from(rows.iter().map(|r| r.storage.as_deref()).collect::<Vec<_>>())),
+		];
+		Ok(RecordBatch::try_new(schema, columns)?)
+	}
+
+	fn block_num(&self) -> i32 {
+		self.block_num
+	}
+
+	fn spec(&self) -> i32 {
+		self.spec
+	}
+}
+
+struct TraceRow {
+	block_num: i32,
+	hash: Vec<u8>,
+	spec: i32,
+	is_event: bool,
+	timestamp: Option<chrono::NaiveDateTime>,
+	duration: Option<i64>,
+	file: Option<String>,
+	line: Option<i32>,
+	trace_id: Option<i32>,
+	trace_parent_id: Option<i32>,
+	target: Option<String>,
+	name: Option<String>,
+	/// The traced values, as JSON.
+	traces: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl Row for TraceRow {
+	const TABLE: &'static str = "state_traces";
+
+	fn schema() -> Schema {
+		Schema::new(vec![
+			Field::new("block_num", DataType::Int32, false),
+			Field::new("hash", DataType::Binary, false),
+			Field::new("spec", DataType::Int32, false),
+			Field::new("is_event", DataType::Boolean, false),
+			Field::new("timestamp", DataType::Timestamp(TimeUnit::Nanosecond, None), true),
+			Field::new("duration", DataType::Int64, true),
+			Field::new("file", DataType::Utf8, true),
+			Field::new("line", DataType::Int32, true),
+			Field::new("trace_id", DataType::Int32, true),
+			Field::new("trace_parent_id", DataType::Int32, true),
+			Field::new("target", DataType::Utf8, true),
+			Field::new("name", DataType::Utf8, true),
+			Field::new("traces", DataType::Utf8, true),
+		])
+	}
+
+	async fn fetch(conn: &mut PgConnection, _: &mut MetadataCache, from: i32, to: i32) -> Result<Vec<Self>> {
+		sqlx::query_as!(
+			TraceRow,
+			r#"SELECT state_traces.block_num, state_traces.hash, blocks.spec, is_event, timestamp, duration, file, line,
+            trace_id, trace_parent_id, target, name, traces::text AS "traces?"
+        FROM state_traces JOIN blocks ON state_traces.hash = blocks.hash
+        WHERE state_traces.block_num BETWEEN $1 AND $2 ORDER BY state_traces.block_num, state_traces.id"#,
+			from,
+			to
+		)
+		.fetch_all(conn)
+		.await
+		.map_err(Into::into)
+	}
+
+	fn to_batch(rows: &[Self], schema: SchemaRef) -> Result<RecordBatch> {
+		let string = |f: fn(&Self) -> Option<&str>| Arc::new(StringArray::from(rows.iter().map(f).collect::<Vec<_>>()));
+		let columns: Vec<ArrayRef> = vec![
+			Arc::new(Int32Array::from(rows.iter().map(|r| r.block_num).collect::<Vec<_>>())),
+			Arc::new(BinaryArray::from(rows.iter().map(|r| r.hash.as_slice()).collect::<Vec<_>>())),
+			Arc::new(Int32Array::from(rows.iter().map(|r| r.spec).collect::<Vec<_>>())),
+			Arc::new(BooleanArray::from(rows.iter().map(|r| r.is_event).collect::<Vec<_>>())),
+			Arc::new(TimestampNanosecondArray::from(
+				rows.iter().map(|r| r.timestamp.map(|t| t.timestamp_nanos())).collect::<Vec<_>>(),
+			)),
+			Arc::new(Int64Array::from(rows.iter().map(|r| r.duration).collect::<Vec<_>>())),
+			string(|r| r.file.as_deref()),
+			Arc::new(Int32Array::from(rows.iter().map(|r| r.line).collect::<Vec<_>>())),
+			Arc::new(Int32Array::from(rows.iter().map(|r| r.trace_id).collect::<Vec<_>>())),
+			Arc::new(Int32Array::from(rows.iter().map(|r| r.trace_parent_id).collect::<Vec<_>>())),
+			string(|r| r.target.as_deref()),
+			string(|r| r.name.as_deref()),
+			string(|r| r.traces.as_deref()),
+		];
+		Ok(RecordBatch::try_new(schema, columns)?)
+	}
+
+	fn block_num(&self) -> i32 {
+		self.block_num
+	}
+
+	fn spec(&self) -> i32 {
+		self.spec
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use arrow::array::Array;
+	use codec::Encode;
+	use frame_metadata::{
+		v14::{
+			ExtrinsicMetadata, PalletCallMetadata, PalletMetadata, PalletStorageMetadata, RuntimeMetadataV14,
+			StorageEntryMetadata, StorageEntryModifier, StorageEntryType,
+		},
+		RuntimeMetadata, RuntimeMetadataPrefixed,
+	};
+	use parquet::{
+		arrow::{ArrowReader, ParquetFileArrowReader},
+		file::reader::SerializedFileReader,
+	};
+	use scale_info::{meta_type, TypeInfo};
+
+	#[test]
+	fn should_parse_partitioning() {
+		assert_eq!("spec".parse::<Partitioning>().unwrap(), Partitioning::Spec);
+		assert_eq!("range:1000".parse::<Partitioning>().unwrap(), Partitioning::Range(1000));
+		assert!("range:0".parse::<Partitioning>().is_err());
+		assert!("blocks".parse::<Partitioning>().is_err());
+	}
+
+	#[test]
+	fn should_parse_tables() {
+		assert_eq!("events".parse::<ExportTable>().unwrap(), ExportTable::Events);
+		assert_eq!("state_traces".parse::<ExportTable>().unwrap(), ExportTable::Traces);
+		assert!("digests".parse::<ExportTable>().is_err());
+	}
+
+	#[test]
+	fn should_name_partitions() {
+		assert_eq!(Partitioning::Spec.partition(1234, 9050), "spec=9050");
+		assert_eq!(Partitioning::Range(1000).partition(1234, 9050), "blocks=1000-1999");
+		assert_eq!(Partitioning::Range(1000).partition(999, 9050), "blocks=0-999");
+	}
+
+	#[test]
+	fn should_write_a_file_per_partition() {
+		let dir = tempfile::tempdir().unwrap();
+		let schema = Arc::new(StorageRow::schema());
+		let rows = (0..4)
+			.map(|n| StorageRow {
+				block_num: n,
+				hash: vec![n as u8],
+				spec: if n < 2 { 1 } else { 2 },
+				is_full: false,
+				key: vec![0xff],
+				storage: if n % 2 == 0 { Some(vec![n as u8]) } else { None },
+			})
+			.collect::<Vec<_>>();
+		let mut writer = PartitionWriter::new(dir.path().join(StorageRow::TABLE), schema.clone());
+		writer.write("spec=1".into(), 0, &StorageRow::to_batch(&rows[..2], schema.clone()).unwrap()).unwrap();
+		writer.write("spec=2".into(), 2, &StorageRow::to_batch(&rows[2..], schema).unwrap()).unwrap();
+		let files = writer.finish().unwrap();
+		assert_eq!(
+			files,
+			vec![
+				dir.path().join("storage/spec=1/part-0000000000.parquet"),
+				dir.path().join("storage/spec=2/part-0000000002.parquet"),
+			]
+		);
+		assert!(files.iter().all(|f| f.exists()));
+	}
+
+	// the types of a runtime with a `System` pallet which can only remark
+	#[allow(non_camel_case_types)]
+	#[derive(Encode, TypeInfo)]
+	enum SystemCall {
+		remark { remark: Vec<u8> },
+	}
+
+	#[derive(Encode, TypeInfo)]
+	enum SystemEvent {
+		Remarked(u32),
+	}
+
+	#[derive(Encode, TypeInfo)]
+	enum Event {
+		System(SystemEvent),
+	}
+
+	#[derive(Encode, TypeInfo)]
+	enum Phase {
+		ApplyExtrinsic(u32),
+	}
+
+	#[derive(Encode, TypeInfo)]
+	struct EventRecord {
+		phase: Phase,
+		event: Event,
+		topics: Vec<[u8; 32]>,
+	}
+
+	fn metadata() -> Vec<u8> {
+		let system = PalletMetadata {
+			name: "System",
+			storage: Some(PalletStorageMetadata {
+				prefix: "System",
+				entries: vec![StorageEntryMetadata {
+					name: "Events",
+					modifier: StorageEntryModifier::Default,
+					ty: StorageEntryType::Plain(meta_type::<Vec<EventRecord>>()),
+					default: vec![0],
+					docs: Vec::new(),
+				}],
+			}),
+			calls: Some(PalletCallMetadata { ty: meta_type::<SystemCall>() }),
+			event: None,
+			constants: Vec::new(),
+			error: None,
+			index: 0,
+		};
+		let extrinsic = ExtrinsicMetadata { ty: meta_type::<Vec<u8>>(), version: 4, signed_extensions: Vec::new() };
+		let meta = RuntimeMetadataV14::new(vec![system], extrinsic, meta_type::<()>());
+		RuntimeMetadataPrefixed(u32::from_le_bytes(*b"meta"), RuntimeMetadata::V14(meta)).encode()
+	}
+
+	fn read(path: &std::path::Path) -> RecordBatch {
+		let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+		let mut reader = ParquetFileArrowReader::new(Arc::new(reader));
+		reader.get_record_reader(1024).unwrap().next().unwrap().unwrap()
+	}
+
+	fn string(batch: &RecordBatch, column: usize) -> &str {
+		batch.column(column).as_any().downcast_ref::<StringArray>().unwrap().value(0)
+	}
+
+	#[test]
+	fn should_export_decoded_extrinsics_and_events() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = crate::PG_POOL.acquire().await.unwrap();
+			sqlx::query("INSERT INTO metadata (version, meta) VALUES (1, $1)")
+				.bind(metadata())
+				.execute(&mut conn)
+				.await
+				.unwrap();
+			// an unsigned remark of pallet 0
+			let remark = [&[4u8, 0][..], &SystemCall::remark { remark: vec![1, 2] }.encode()].concat();
+			sqlx::query(
+				"INSERT INTO blocks (parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec)
+                VALUES ($1, $1, 1, $1, $1, $1, $2, 1)",
+			)
+			.bind(vec![1u8; 32])
+			.bind(vec![remark].encode())
+			.execute(&mut conn)
+			.await
+			.unwrap();
+			crate::partitions::create_ahead(&mut conn, 1, 10).await.unwrap();
+			let events = vec![EventRecord {
+				phase: Phase::ApplyExtrinsic(0),
+				event: Event::System(SystemEvent::Remarked(7)),
+				topics: Vec::new(),
+			}];
+			sqlx::query("INSERT INTO storage (block_num, hash, is_full, key, storage) VALUES (1, $1, false, $2, $3)")
+				.bind(vec![1u8; 32])
+				.bind([twox_128(b"System"), twox_128(b"Events")].concat())
+				.bind(events.encode())
+				.execute(&mut conn)
+				.await
+				.unwrap();
+
+			let dir = tempfile::tempdir().unwrap();
+			let config = ExportConfig {
+				dir: dir.path().to_path_buf(),
+				from: 1,
+				to: 1,
+				partitioning: Partitioning::Spec,
+				tables: vec![ExportTable::Extrinsics, ExportTable::Events],
+			};
+			let summary = export(&*crate::DATABASE_URL, &config).await.unwrap();
+			assert_eq!(summary.rows, 2);
+			assert_eq!(
+				summary.files,
+				vec![
+					dir.path().join("extrinsics/spec=1/part-0000000001.parquet"),
+					dir.path().join("events/spec=1/part-0000000001.parquet"),
+				]
+			);
+
+			let extrinsics = read(&summary.files[0]);
+			assert_eq!(extrinsics.num_rows(), 1);
+			assert_eq!((string(&extrinsics, 6), string(&extrinsics, 7)), ("System", "remark"));
+			assert_eq!(string(&extrinsics, 8), r#"{"remark":"0x0102"}"#);
+			let events = read(&summary.files[1]);
+			assert_eq!(events.num_rows(), 1);
+			assert_eq!(
+				(string(&events, 4), string(&events, 6), string(&events, 7)),
+				("ApplyExtrinsic", "System", "Remarked")
+			);
+			assert_eq!(string(&events, 8), "7");
+
+			// exporting again, e.g. from another block, would mix the files of both exports
+			assert!(matches!(export(&*crate::DATABASE_URL, &config).await, Err(ArchiveError::Export(_))));
+		});
+	}
+}
@@ -0,0 +1,846 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decoding extrinsics and events with the metadata of the runtime they belong to.
+//!
+//! Metadata `V14` describes every type, so calls and events are decoded completely.
+//! Metadata `V12` and `V13` only name the types of call and event arguments. Arguments are decoded if their types
+//! are primitives or common FRAME types, and signed extrinsics are expected to use `MultiAddress` and
+//! `MultiSignature`. Otherwise only the pallet and name of a call or event are known.
+//!
+//! Values are decoded to JSON: byte arrays as hex, integers wider than 64 bits as strings.
+
+use std::{collections::HashMap, convert::TryFrom};
+
+use codec::{Compact, Decode};
+use frame_metadata::{
+	decode_different::DecodeDifferent,
+	v14::{RuntimeMetadataV14, StorageEntryType},
+	RuntimeMetadata, RuntimeMetadataPrefixed,
+};
+use scale_info::{form::PortableForm, Field, TypeDef, TypeDefPrimitive, Variant};
+use serde_json::{json, Map, Value};
+
+use crate::error::{ArchiveError, Result};
+
+/// A call or event.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Decoded {
+	pub pallet: String,
+	pub name: String,
+	/// The arguments, unless they could not be decoded.
+	pub args: Option<Value>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct DecodedExtrinsic {
+	pub signed: bool,
+	/// Address of the signer, of signed extrinsics which could be decoded.
+	pub signer: Option<Value>,
+	pub call: Option<Decoded>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct DecodedEvent {
+	/// `ApplyExtrinsic`, `Finalization` or `Initialization`.
+	pub phase: String,
+	/// Index of the extrinsic which emitted the event, in the `ApplyExtrinsic` phase.
+	pub extrinsic_index: Option<u32>,
+	pub event: Decoded,
+}
+
+/// Collect the pallets of `V12` or `V13` metadata, which have the same layout but distinct types.
+macro_rules! legacy {
+	($meta:expr) => {{
+		let meta = $meta;
+		let mut pallets = HashMap::new();
+		for module in decoded(&meta.modules)? {
+			let mut pallet =
+				LegacyPallet { name: decoded(&module.name)?.clone(), calls: Vec::new(), events: Vec::new() };
+			for call in module.calls.as_ref().map(decoded).transpose()?.into_iter().flatten() {
+				let args = decoded(&call.arguments)?
+					.iter()
+					.map(|arg| Ok((decoded(&arg.name)?.clone(), decoded(&arg.ty)?.clone())))
+					.collect::<Result<_>>()?;
+				pallet.calls.push((decoded(&call.name)?.clone(), args));
+			}
+			for event in module.event.as_ref().map(decoded).transpose()?.into_iter().flatten() {
+				pallet.events.push((decoded(&event.name)?.clone(), decoded(&event.arguments)?.clone()));
+			}
+			pallets.insert(module.index, pallet);
+		}
+		let signed_extensions =
+			meta.extrinsic.signed_extensions.iter().map(|e| Ok(decoded(e)?.clone())).collect::<Result<_>>()?;
+		Legacy { pallets, signed_extensions }
+	}};
+}
+
+/// The metadata of a runtime version.
+pub(crate) enum ChainMetadata {
+	Legacy(Legacy),
+	V14(V14),
+}
+
+impl ChainMetadata {
+	/// Decode the metadata kept in the `metadata` table.
+	pub(crate) fn new(meta: &[u8]) -> Result<Self> {
+		match RuntimeMetadataPrefixed::decode(&mut &meta[..])?.1 {
+			RuntimeMetadata::V12(meta) => Ok(ChainMetadata::Legacy(legacy!(meta))),
+			RuntimeMetadata::V13(meta) => Ok(ChainMetadata::Legacy(legacy!(meta))),
+			RuntimeMetadata::V14(meta) => Ok(ChainMetadata::V14(V14::new(meta)?)),
+			_ => Err(ArchiveError::Decode("metadata older than V12".into())),
+		}
+	}
+
+	/// Decode an extrinsic, as far as the metadata allows.
+	pub(crate) fn extrinsic(&self, ext: &[u8]) -> DecodedExtrinsic {
+		let mut decoded = DecodedExtrinsic { signed: is_signed(ext), ..Default::default() };
+		// skip the version
+		let input = &mut ext.get(1..).unwrap_or_default();
+		if decoded.signed {
+			let signer = match self {
+				ChainMetadata::Legacy(meta) => meta.signature(input),
+				ChainMetadata::V14(meta) => meta.signature(input),
+			};
+			match signer {
+				Ok(signer) => decoded.signer = Some(signer),
+				Err(e) => {
+					log::debug!("Failed to decode the signature of an extrinsic: {}", e);
+					return decoded;
+				}
+			}
+		}
+		let call = match self {
+			ChainMetadata::Legacy(meta) => meta.call(input),
+			ChainMetadata::V14(meta) => meta.call(input),
+		};
+		decoded.call = call.map_err(|e| log::debug!("Failed to decode the call of an extrinsic: {}", e)).ok();
+		decoded
+	}
+
+	/// Decode the value of `System.Events` into `events`.
+	/// Stops at the first event whose arguments can't be decoded, since the following events can't be found
+	/// either, after pushing it without arguments.
+	pub(crate) fn events(&self, value: &[u8], events: &mut Vec<DecodedEvent>) -> Result<()> {
+		let input = &mut &value[..];
+		let len = Compact::<u32>::decode(input)?.0;
+		for _ in 0..len {
+			let event = match self {
+				ChainMetadata::Legacy(meta) => meta.event_record(input)?,
+				ChainMetadata::V14(meta) => meta.event_record(input)?,
+			};
+			let complete = event.event.args.is_some();
+			events.push(event);
+			if !complete {
+				return Err(ArchiveError::Decode(format!("arguments of event {}", events.len() - 1)));
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Whether the version of an extrinsic has the bit of signed extrinsics set.
+pub(crate) fn is_signed(ext: &[u8]) -> bool {
+	ext.first().map(|version| version & 0b1000_0000 != 0).unwrap_or(false)
+}
+
+/// Calls and events of metadata which only names their argument types.
+pub(crate) struct Legacy {
+	pallets: HashMap<u8, LegacyPallet>,
+	signed_extensions: Vec<String>,
+}
+
+struct LegacyPallet {
+	name: String,
+	/// Names and types of the arguments of every call, by call index.
+	calls: Vec<(String, Vec<(String, String)>)>,
+	/// Argument types of every event, by event index.
+	events: Vec<(String, Vec<String>)>,
+}
+
+fn decoded<B: 'static, O: 'static>(value: &DecodeDifferent<B, O>) -> Result<&O> {
+	match value {
+		DecodeDifferent::Decoded(value) => Ok(value),
+		DecodeDifferent::Encode(_) => Err(ArchiveError::Decode("metadata".into())),
+	}
+}
+
+/// Type names with the same encoding as another type.
+const ALIASES: &[(&str, &str)] = &[
+	("AccountId", "[u8; 32]"),
+	("AccountId32", "[u8; 32]"),
+	("AuthorityId", "[u8; 32]"),
+	("ValidatorId", "[u8; 32]"),
+	("Hash", "[u8; 32]"),
+	("H256", "[u8; 32]"),
+	("BlockHash", "[u8; 32]"),
+	("CallHash", "[u8; 32]"),
+	("H160", "[u8; 20]"),
+	("Kind", "[u8; 16]"),
+	("Balance", "u128"),
+	("BalanceOf", "u128"),
+	("BlockNumber", "u32"),
+	("AccountIndex", "u32"),
+	("SessionIndex", "u32"),
+	("EraIndex", "u32"),
+	("ParaId", "u32"),
+	("LeasePeriod", "u32"),
+	("LeasePeriodOf", "u32"),
+	("PropIndex", "u32"),
+	("ReferendumIndex", "u32"),
+	("ProposalIndex", "u32"),
+	("MemberCount", "u32"),
+	("RegistrarIndex", "u32"),
+	("AssetId", "u32"),
+	("Perbill", "u32"),
+	("Permill", "u32"),
+	("Percent", "u8"),
+	("Weight", "u64"),
+	("Moment", "u64"),
+	("Bytes", "Vec<u8>"),
+	("Text", "Vec<u8>"),
+	("HeadData", "Vec<u8>"),
+	("OpaqueTimeSlot", "Vec<u8>"),
+	("AuthorityList", "Vec<([u8; 32], u64)>"),
+	("Timepoint", "(u32, u32)"),
+	("LookupSource", "MultiAddress"),
+	("Source", "MultiAddress"),
+	("Address", "MultiAddress"),
+	("Proposal", "Call"),
+	("DispatchResult", "Result<(), DispatchError>"),
+	("ProxyType", "u8"),
+	("VoteThreshold", "u8"),
+	("Conviction", "u8"),
+];
+
+/// Signed extensions of FRAME and Polkadot which don't add anything to the extrinsic.
+const EMPTY_EXTENSIONS: &[&str] = &[
+	"CheckSpecVersion",
+	"CheckTxVersion",
+	"CheckVersion",
+	"CheckGenesis",
+	"CheckWeight",
+	"CheckNonZeroSender",
+	"PrevalidateAttests",
+	"ValidateDoubleVoteReports",
+	"LimitParathreadCommits",
+	"OnlyStakingAndClaims",
+	"RestrictFunctionality",
+];
+
+impl Legacy {
+	/// Decode the signature of an extrinsic, returning the signer.
+	fn signature(&self, input: &mut &[u8]) -> Result<Value> {
+		let signer = self.value("MultiAddress", input)?;
+		match u8::decode(input)? {
+			0 | 1 => take(input, 64)?,
+			2 => take(input, 65)?,
+			_ => return Err(ArchiveError::Decode("MultiSignature".into())),
+		};
+		for extension in self.signed_extensions.iter() {
+			match extension.as_str() {
+				"CheckMortality" | "CheckEra" => {
+					if u8::decode(input)? != 0 {
+						u8::decode(input)?;
+					}
+				}
+				"CheckNonce" => {
+					Compact::<u32>::decode(input)?;
+				}
+				"ChargeTransactionPayment" => {
+					Compact::<u128>::decode(input)?;
+				}
+				e if EMPTY_EXTENSIONS.contains(&e) => {}
+				e => return Err(ArchiveError::Decode(format!("signed extension {}", e))),
+			}
+		}
+		Ok(signer)
+	}
+
+	fn call(&self, input: &mut &[u8]) -> Result<Decoded> {
+		let (pallet_index, call_index) = (u8::decode(input)?, u8::decode(input)?);
+		let pallet = self.pallet(pallet_index)?;
+		let (name, args) = pallet
+			.calls
+			.get(usize::from(call_index))
+			.ok_or_else(|| ArchiveError::Decode(format!("call {} of pallet {}", call_index, pallet.name)))?;
+		let args = args
+			.iter()
+			.map(|(arg, ty)| Ok((arg.clone(), self.value(ty, input)?)))
+			.collect::<Result<Map<_, _>>>()
+			.map_err(|e| log::debug!("Failed to decode the arguments of {}.{}: {}", pallet.name, name, e))
+			.ok()
+			.map(Value::Object);
+		Ok(Decoded { pallet: pallet.name.clone(), name: name.clone(), args })
+	}
+
+	fn event_record(&self, input: &mut &[u8]) -> Result<DecodedEvent> {
+		let (phase, extrinsic_index) = match u8::decode(input)? {
+			0 => ("ApplyExtrinsic", Some(u32::decode(input)?)),
+			1 => ("Finalization", None),
+			2 => ("Initialization", None),
+			_ => return Err(ArchiveError::Decode("Phase".into())),
+		};
+		let (pallet_index, event_index) = (u8::decode(input)?, u8::decode(input)?);
+		let pallet = self.pallet(pallet_index)?;
+		let (name, types) = pallet
+			.events
+			.get(usize::from(event_index))
+			.ok_or_else(|| ArchiveError::Decode(format!("event {} of pallet {}", event_index, pallet.name)))?;
+		let args = types
+			.iter()
+			.map(|ty| self.value(ty, input))
+			.collect::<Result<Vec<_>>>()
+			.map_err(|e| log::debug!("Failed to decode the arguments of {}.{}: {}", pallet.name, name, e))
+			.ok();
+		if args.is_some() {
+			// the topics
+			Vec::<[u8; 32]>::decode(input)?;
+		}
+		let event = Decoded { pallet: pallet.name.clone(), name: name.clone(), args: args.map(Value::Array) };
+		Ok(DecodedEvent { phase: phase.to_string(), extrinsic_index, event })
+	}
+
+	fn pallet(&self, index: u8) -> Result<&LegacyPallet> {
+		self.pallets.get(&index).ok_or_else(|| ArchiveError::Decode(format!("pallet {}", index)))
+	}
+
+	/// Decode a value of the type named `ty`.
+	fn value(&self, ty: &str, input: &mut &[u8]) -> Result<Value> {
+		let ty = ty.trim();
+		let unknown = || ArchiveError::Decode(format!("type {}", ty));
+		if let Some(inner) = ty.strip_prefix('(').and_then(|t| t.strip_suffix(')')) {
+			let items = split_params(inner).into_iter().map(|t| self.value(t, input)).collect::<Result<Vec<_>>>()?;
+			return Ok(if items.is_empty() { Value::Null } else { Value::Array(items) });
+		}
+		if let Some(inner) = ty.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+			let (item, len) = inner.split_once(';').ok_or_else(unknown)?;
+			let len = len.trim().parse().map_err(|_| unknown())?;
+			return self.items(item, len, input);
+		}
+		if ty.starts_with('<') {
+			// a qualified path, e.g. `<T as Config>::Call`
+			let end = closing(ty).ok_or_else(unknown)?;
+			let name = ty[end + 1..].trim_start_matches("::");
+			return self.value(name, input);
+		}
+		let (base, params) = match ty.find('<') {
+			Some(start) if ty.ends_with('>') => (&ty[..start], split_params(&ty[start + 1..ty.len() - 1])),
+			_ => (ty, Vec::new()),
+		};
+		let name = base.rsplit("::").next().unwrap_or(base);
+		match (name, params.as_slice()) {
+			("Vec" | "BoundedVec" | "WeakBoundedVec" | "BTreeSet", [item, ..]) => {
+				let len = Compact::<u32>::decode(input)?.0;
+				self.items(item, len, input)
+			}
+			("Option", [item]) => match u8::decode(input)? {
+				0 => Ok(Value::Null),
+				1 => self.value(item, input),
+				_ => Err(unknown()),
+			},
+			("Result", [ok, err]) => match u8::decode(input)? {
+				0 => Ok(json!({ "Ok": self.value(ok, input)? })),
+				1 => Ok(json!({ "Err": self.value(err, input)? })),
+				_ => Err(unknown()),
+			},
+			("Compact", [item]) => compact(self.is_wide(item), input),
+			("Box", [item]) => self.value(item, input),
+			("Call", _) => {
+				let call = self.call(input)?;
+				Ok(json!({ "pallet": call.pallet, "call": call.name, "args": call.args.ok_or_else(unknown)? }))
+			}
+			("MultiAddress", _) => match u8::decode(input)? {
+				0 => Ok(json!({ "Id": hex_bytes(take(input, 32)?) })),
+				1 => Ok(json!({ "Index": Compact::<u32>::decode(input)?.0 })),
+				2 => Ok(json!({ "Raw": self.value("Vec<u8>", input)? })),
+				3 => Ok(json!({ "Address32": hex_bytes(take(input, 32)?) })),
+				4 => Ok(json!({ "Address20": hex_bytes(take(input, 20)?) })),
+				_ => Err(unknown()),
+			},
+			("DispatchInfo", _) => {
+				let weight = u64::decode(input)?;
+				let class = variant(&["Normal", "Operational", "Mandatory"], input)?;
+				let pays_fee = variant(&["Yes", "No"], input)?;
+				Ok(json!({ "weight": weight, "class": class, "pays_fee": pays_fee }))
+			}
+			("DispatchError", _) => {
+				let names = [
+					"Other",
+					"CannotLookup",
+					"BadOrigin",
+					"Module",
+					"ConsumerRemaining",
+					"NoProviders",
+					"Token",
+					"Arithmetic",
+				];
+				match u8::decode(input)? {
+					3 => Ok(json!({ "Module": { "index": u8::decode(input)?, "error": u8::decode(input)? } })),
+					i @ 6..=7 => {
+						let name = names[usize::from(i)];
+						Ok(json!({ name: u8::decode(input)? }))
+					}
+					i => Ok(json!(names.get(usize::from(i)).ok_or_else(unknown)?)),
+				}
+			}
+			_ => match (primitive(name), ALIASES.iter().find(|(alias, _)| *alias == name)) {
+				(Some(ty), _) => primitive_value(&ty, input),
+				(None, Some((_, ty))) => self.value(ty, input),
+				(None, None) => Err(unknown()),
+			},
+		}
+	}
+
+	fn items(&self, item: &str, len: u32, input: &mut &[u8]) -> Result<Value> {
+		if item.trim() == "u8" {
+			return Ok(hex_bytes(take(input, len)?));
+		}
+		(0..len).map(|_| self.value(item, input)).collect::<Result<Vec<_>>>().map(Value::Array)
+	}
+
+	/// Whether `ty` is decoded as a 128 bit integer.
+	fn is_wide(&self, ty: &str) -> bool {
+		let ty = ty.trim();
+		let name = ty.split('<').next().unwrap_or(ty).rsplit("::").next().unwrap_or(ty);
+		match ALIASES.iter().find(|(alias, _)| *alias == name) {
+			Some((_, ty)) => self.is_wide(ty),
+			None => matches!(name, "u128" | "i128"),
+		}
+	}
+}
+
+/// Calls and events of metadata describing their types.
+pub(crate) struct V14 {
+	meta: RuntimeMetadataV14,
+	/// Type of the signer and signature of extrinsics.
+	address: Option<u32>,
+	signature: Option<u32>,
+	/// Type of `System.Events`.
+	events: Option<u32>,
+}
+
+impl V14 {
+	fn new(meta: RuntimeMetadataV14) -> Result<Self> {
+		let extrinsic =
+			meta.types.resolve(meta.extrinsic.ty.id()).ok_or_else(|| ArchiveError::Decode("extrinsic type".into()))?;
+		let param = |name: &str| {
+			extrinsic.type_params().iter().find(|p| p.name() == name).and_then(|p| p.ty()).map(|ty| ty.id())
+		};
+		let (address, signature) = (param("Address"), param("Signature"));
+		let events = meta
+			.pallets
+			.iter()
+			.find(|p| p.name == "System")
+			.and_then(|p| p.storage.as_ref())
+			.and_then(|s| s.entries.iter().find(|e| e.name == "Events"))
+			.and_then(|e| match &e.ty {
+				StorageEntryType::Plain(ty) => Some(ty.id()),
+				StorageEntryType::Map { .. } => None,
+			});
+		Ok(Self { meta, address, signature, events })
+	}
+
+	fn signature(&self, input: &mut &[u8]) -> Result<Value> {
+		let unknown = || ArchiveError::Decode("signature type".into());
+		let signer = self.value(self.address.ok_or_else(unknown)?, input)?;
+		self.value(self.signature.ok_or_else(unknown)?, input)?;
+		for extension in self.meta.extrinsic.signed_extensions.iter() {
+			self.value(extension.ty.id(), input)?;
+		}
+		Ok(signer)
+	}
+
+	fn call(&self, input: &mut &[u8]) -> Result<Decoded> {
+		let index = u8::decode(input)?;
+		let pallet = self
+			.meta
+			.pallets
+			.iter()
+			.find(|p| p.index == index)
+			.ok_or_else(|| ArchiveError::Decode(format!("pallet {}", index)))?;
+		let calls = pallet.calls.as_ref().ok_or_else(|| ArchiveError::Decode(format!("calls of {}", pallet.name)))?;
+		let call = self.variant(calls.ty.id(), input)?;
+		let args = self
+			.fields(call.fields(), input)
+			.map_err(|e| log::debug!("Failed to decode the arguments of {}.{}: {}", pallet.name, call.name(), e))
+			.ok();
+		Ok(Decoded { pallet: pallet.name.clone(), name: call.name().clone(), args })
+	}
+
+	fn event_record(&self, input: &mut &[u8]) -> Result<DecodedEvent> {
+		let events = self.events.ok_or_else(|| ArchiveError::Decode("System.Events type".into()))?;
+		let record = match self.resolve(events)?.type_def() {
+			TypeDef::Sequence(seq) => self.resolve(seq.type_param().id())?,
+			_ => return Err(ArchiveError::Decode("System.Events type".into())),
+		};
+		let fields = match record.type_def() {
+			TypeDef::Composite(record) => record.fields(),
+			_ => return Err(ArchiveError::Decode("EventRecord type".into())),
+		};
+		let (mut phase, mut event) = (None, None);
+		for field in fields {
+			match field.name().map(String::as_str) {
+				Some("phase") => {
+					let variant = self.variant(field.ty().id(), input)?;
+					let index = self.fields(variant.fields(), input)?.as_u64().map(u32::try_from).transpose()?;
+					phase = Some((variant.name().clone(), index));
+				}
+				Some("event") => {
+					// the event of the runtime is a variant for every pallet, of the event of the pallet
+					let pallet = self.variant(field.ty().id(), input)?;
+					let ty = pallet.fields().first().ok_or_else(|| ArchiveError::Decode(pallet.name().clone()))?;
+					let variant = self.variant(ty.ty().id(), input)?;
+					let args = self.fields(variant.fields(), input);
+					let complete = args.is_ok();
+					event =
+						Some(Decoded { pallet: pallet.name().clone(), name: variant.name().clone(), args: args.ok() });
+					if !complete {
+						break;
+					}
+				}
+				_ => {
+					self.value(field.ty().id(), input)?;
+				}
+			}
+		}
+		match (phase, event) {
+			(Some((phase, extrinsic_index)), Some(event)) => Ok(DecodedEvent { phase, extrinsic_index, event }),
+			_ => Err(ArchiveError::Decode("EventRecord type".into())),
+		}
+	}
+
+	fn resolve(&self, id: u32) -> Result<&scale_info::Type<PortableForm>> {
+		self.meta.types.resolve(id).ok_or_else(|| ArchiveError::Decode(format!("type {}", id)))
+	}
+
+	/// Read the index of a variant of the enum `id`.
+	fn variant(&self, id: u32, input: &mut &[u8]) -> Result<&Variant<PortableForm>> {
+		let index = u8::decode(input)?;
+		let variant = match self.resolve(id)?.type_def() {
+			TypeDef::Variant(ty) => ty.variants().iter().find(|v| v.index() == index),
+			_ => None,
+		};
+		variant.ok_or_else(|| ArchiveError::Decode(format!("variant {} of type {}", index, id)))
+	}
+
+	/// Decode fields as an object if they are named, otherwise as an array, or as the only field.
+	fn fields(&self, fields: &[Field<PortableForm>], input: &mut &[u8]) -> Result<Value> {
+		match fields {
+			[] => Ok(Value::Null),
+			[field] if field.name().is_none() => self.value(field.ty().id(), input),
+			fields if fields.iter().all(|f| f.name().is_some()) => fields
+				.iter()
+				.map(|f| Ok((f.name().cloned().unwrap_or_default(), self.value(f.ty().id(), input)?)))
+				.collect::<Result<Map<_, _>>>()
+				.map(Value::Object),
+			fields => {
+				fields.iter().map(|f| self.value(f.ty().id(), input)).collect::<Result<Vec<_>>>().map(Value::Array)
+			}
+		}
+	}
+
+	fn value(&self, id: u32, input: &mut &[u8]) -> Result<Value> {
+		match self.resolve(id)?.type_def() {
+			TypeDef::Composite(ty) => self.fields(ty.fields(), input),
+			TypeDef::Variant(_) => {
+				let variant = self.variant(id, input)?;
+				if variant.fields().is_empty() {
+					Ok(json!(variant.name()))
+				} else {
+					Ok(json!({ variant.name(): self.fields(variant.fields(), input)? }))
+				}
+			}
+			TypeDef::Sequence(ty) => {
+				let len = Compact::<u32>::decode(input)?.0;
+				self.items(ty.type_param().id(), len, input)
+			}
+			TypeDef::Array(ty) => self.items(ty.type_param().id(), ty.len(), input),
+			TypeDef::Tuple(ty) if ty.fields().is_empty() => Ok(Value::Null),
+			TypeDef::Tuple(ty) => {
+				ty.fields().iter().map(|f| self.value(f.id(), input)).collect::<Result<Vec<_>>>().map(Value::Array)
+			}
+			TypeDef::Primitive(ty) => primitive_value(ty, input),
+			TypeDef::Compact(ty) => compact(self.is_wide(ty.type_param().id())?, input),
+			TypeDef::BitSequence(ty) => {
+				let bits = Compact::<u32>::decode(input)?.0;
+				let store = match self.resolve(ty.bit_store_type().id())?.type_def() {
+					TypeDef::Primitive(TypeDefPrimitive::U8) => 1,
+					TypeDef::Primitive(TypeDefPrimitive::U16) => 2,
+					TypeDef::Primitive(TypeDefPrimitive::U32) => 4,
+					TypeDef::Primitive(TypeDefPrimitive::U64) => 8,
+					_ => return Err(ArchiveError::Decode(format!("bit store of type {}", id))),
+				};
+				Ok(hex_bytes(take(input, bits.div_ceil(store * 8) * store)?))
+			}
+		}
+	}
+
+	fn items(&self, item: u32, len: u32, input: &mut &[u8]) -> Result<Value> {
+		if let TypeDef::Primitive(TypeDefPrimitive::U8) = self.resolve(item)?.type_def() {
+			return Ok(hex_bytes(take(input, len)?));
+		}
+		(0..len).map(|_| self.value(item, input)).collect::<Result<Vec<_>>>().map(Value::Array)
+	}
+
+	/// Whether `id` is a 128 bit integer, or a wrapper of one.
+	fn is_wide(&self, id: u32) -> Result<bool> {
+		match self.resolve(id)?.type_def() {
+			TypeDef::Primitive(ty) => Ok(matches!(ty, TypeDefPrimitive::U128 | TypeDefPrimitive::I128)),
+			TypeDef::Composite(ty) if ty.fields().len() == 1 => self.is_wide(ty.fields()[0].ty().id()),
+			_ => Ok(false),
+		}
+	}
+}
+
+/// Split the parameters of a generic type or tuple at the commas which are not nested.
+fn split_params(params: &str) -> Vec<&str> {
+	let (mut depth, mut start, mut split) = (0, 0, Vec::new());
+	for (i, c) in params.char_indices() {
+		match c {
+			'<' | '(' | '[' => depth += 1,
+			'>' | ')' | ']' => depth -= 1,
+			',' if depth == 0 => {
+				split.push(params[start..i].trim());
+				start = i + 1;
+			}
+			_ => {}
+		}
+	}
+	split.push(params[start..].trim());
+	split.retain(|p| !p.is_empty());
+	split
+}
+
+/// Position of the `>` closing the `<` `ty` starts with.
+fn closing(ty: &str) -> Option<usize> {
+	let mut depth = 0;
+	for (i, c) in ty.char_indices() {
+		match c {
+			'<' => depth += 1,
+			'>' if depth == 1 => return Some(i),
+			'>' => depth -= 1,
+			_ => {}
+		}
+	}
+	None
+}
+
+fn primitive(name: &str) -> Option<TypeDefPrimitive> {
+	Some(match name {
+		"bool" => TypeDefPrimitive::Bool,
+		"u8" => TypeDefPrimitive::U8,
+		"u16" => TypeDefPrimitive::U16,
+		"u32" => TypeDefPrimitive::U32,
+		"u64" => TypeDefPrimitive::U64,
+		"u128" => TypeDefPrimitive::U128,
+		"i8" => TypeDefPrimitive::I8,
+		"i16" => TypeDefPrimitive::I16,
+		"i32" => TypeDefPrimitive::I32,
+		"i64" => TypeDefPrimitive::I64,
+		"i128" => TypeDefPrimitive::I128,
+		_ => return None,
+	})
+}
+
+fn primitive_value(ty: &TypeDefPrimitive, input: &mut &[u8]) -> Result<Value> {
+	Ok(match ty {
+		TypeDefPrimitive::Bool => json!(bool::decode(input)?),
+		TypeDefPrimitive::Char => {
+			json!(std::char::from_u32(u32::decode(input)?).ok_or_else(|| ArchiveError::Decode("char".into()))?)
+		}
+		TypeDefPrimitive::Str => json!(String::decode(input)?),
+		TypeDefPrimitive::U8 => json!(u8::decode(input)?),
+		TypeDefPrimitive::U16 => json!(u16::decode(input)?),
+		TypeDefPrimitive::U32 => json!(u32::decode(input)?),
+		TypeDefPrimitive::U64 => json!(u64::decode(input)?),
+		TypeDefPrimitive::U128 => json!(u128::decode(input)?.to_string()),
+		TypeDefPrimitive::I8 => json!(i8::decode(input)?),
+		TypeDefPrimitive::I16 => json!(i16::decode(input)?),
+		TypeDefPrimitive::I32 => json!(i32::decode(input)?),
+		TypeDefPrimitive::I64 => json!(i64::decode(input)?),
+		TypeDefPrimitive::I128 => json!(i128::decode(input)?.to_string()),
+		TypeDefPrimitive::U256 | TypeDefPrimitive::I256 => hex_bytes(take(input, 32)?),
+	})
+}
+
+/// Decode a compact integer, as a string if its type is 128 bits wide.
+fn compact(wide: bool, input: &mut &[u8]) -> Result<Value> {
+	let value = Compact::<u128>::decode(input)?.0;
+	if wide {
+		Ok(json!(value.to_string()))
+	} else {
+		Ok(json!(u64::try_from(value)?))
+	}
+}
+
+/// Read the index of a variant without fields.
+fn variant<'a>(names: &[&'a str], input: &mut &[u8]) -> Result<&'a str> {
+	let index = u8::decode(input)?;
+	names.get(usize::from(index)).copied().ok_or_else(|| ArchiveError::Decode(format!("variant {}", index)))
+}
+
+fn take<'a>(input: &mut &'a [u8], len: u32) -> Result<&'a [u8]> {
+	let len = usize::try_from(len)?;
+	if input.len() < len {
+		return Err(codec::Error::from("Not enough data to fill buffer").into());
+	}
+	let (taken, rest) = input.split_at(len);
+	*input = rest;
+	Ok(taken)
+}
+
+fn hex_bytes(bytes: &[u8]) -> Value {
+	Value::String(format!("0x{}", hex::encode(bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use codec::Encode;
+
+	fn legacy() -> Legacy {
+		let mut pallets = HashMap::new();
+		pallets.insert(
+			0,
+			LegacyPallet {
+				name: "System".into(),
+				calls: vec![("remark".into(), vec![("_remark".into(), "Vec<u8>".into())])],
+				events: vec![
+					("ExtrinsicSuccess".into(), vec!["DispatchInfo".into()]),
+					("Unknown".into(), vec!["SomethingElse".into()]),
+				],
+			},
+		);
+		pallets.insert(
+			5,
+			LegacyPallet {
+				name: "Balances".into(),
+				calls: vec![(
+					"transfer".into(),
+					vec![
+						("dest".into(), "<T::Lookup as StaticLookup>::Source".into()),
+						("value".into(), "Compact<T::Balance>".into()),
+					],
+				)],
+				events: vec![(
+					"Transfer".into(),
+					vec!["T::AccountId".into(), "T::AccountId".into(), "T::Balance".into()],
+				)],
+			},
+		);
+		let signed_extensions = ["CheckSpecVersion", "CheckMortality", "CheckNonce", "ChargeTransactionPayment"];
+		Legacy { pallets, signed_extensions: signed_extensions.iter().map(|e| e.to_string()).collect() }
+	}
+
+	fn transfer() -> Vec<u8> {
+		let mut call = vec![5, 0];
+		call.push(0);
+		call.extend_from_slice(&[1; 32]);
+		call.extend(Compact(10_000_000_000_000_000_000u128).encode());
+		call
+	}
+
+	#[test]
+	fn should_split_params() {
+		assert_eq!(split_params("T::AccountId, BalanceOf<T, I>"), vec!["T::AccountId", "BalanceOf<T, I>"]);
+		assert_eq!(split_params("Vec<(u32, u64)>"), vec!["Vec<(u32, u64)>"]);
+		assert!(split_params("").is_empty());
+		assert_eq!(closing("<T as Config<I>>::Proposal"), Some(15));
+	}
+
+	#[test]
+	fn should_decode_legacy_values() {
+		let meta = legacy();
+		let decode = |ty: &str, bytes: Vec<u8>| meta.value(ty, &mut bytes.as_slice()).unwrap();
+		assert_eq!(decode("Option<T::BlockNumber>", Some(7u32).encode()), json!(7));
+		assert_eq!(decode("BalanceOf<T, I>", 5u128.encode()), json!("5"));
+		assert_eq!(
+			decode("Vec<(T::AccountId, u32)>", vec![([2u8; 32], 1u32)].encode()),
+			json!([[hex_bytes(&[2; 32]), 1]])
+		);
+		assert_eq!(
+			decode("DispatchResult", vec![1, 3, 5, 2]),
+			json!({ "Err": { "Module": { "index": 5, "error": 2 } } })
+		);
+		assert_eq!(
+			decode("Box<<T as Config>::Call>", transfer()),
+			json!({
+				"pallet": "Balances",
+				"call": "transfer",
+				"args": { "dest": { "Id": hex_bytes(&[1; 32]) }, "value": "10000000000000000000" },
+			})
+		);
+		assert!(meta.value("SomethingElse", &mut &[0u8][..]).is_err());
+	}
+
+	#[test]
+	fn should_decode_legacy_extrinsics() {
+		let meta = ChainMetadata::Legacy(legacy());
+
+		let mut signed = vec![0b1000_0100, 0];
+		signed.extend_from_slice(&[3; 32]);
+		signed.push(1);
+		signed.extend_from_slice(&[0; 64]);
+		// mortal era, nonce and tip
+		signed.extend_from_slice(&[0x45, 0x02]);
+		signed.extend(Compact(4u32).encode());
+		signed.extend(Compact(0u128).encode());
+		signed.extend(transfer());
+		let decoded = meta.extrinsic(&signed);
+		assert!(decoded.signed);
+		assert_eq!(decoded.signer, Some(json!({ "Id": hex_bytes(&[3; 32]) })));
+		let call = decoded.call.unwrap();
+		assert_eq!((call.pallet.as_str(), call.name.as_str()), ("Balances", "transfer"));
+
+		let unsigned = [&[4u8, 0, 0][..], &vec![1u8, 2].encode()].concat();
+		let decoded = meta.extrinsic(&unsigned);
+		assert!(!decoded.signed);
+		assert_eq!(decoded.signer, None);
+		assert_eq!(decoded.call.unwrap().args, Some(json!({ "_remark": "0x0102" })));
+
+		// an unknown call
+		assert_eq!(meta.extrinsic(&[4, 9, 0]), DecodedExtrinsic { signed: false, signer: None, call: None });
+	}
+
+	#[test]
+	fn should_decode_legacy_events() {
+		let meta = ChainMetadata::Legacy(legacy());
+		let mut value = Compact(3u32).encode();
+		// a transfer applied by the extrinsic 1
+		value.extend_from_slice(&[0, 1, 0, 0, 0, 5, 0]);
+		value.extend_from_slice(&[[1u8; 32], [2u8; 32]].concat());
+		value.extend(7u128.encode());
+		value.extend(Vec::<[u8; 32]>::new().encode());
+		// success of the extrinsic 1
+		value.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0]);
+		value.extend(10u64.encode());
+		value.extend_from_slice(&[0, 0]);
+		value.extend(Vec::<[u8; 32]>::new().encode());
+		// an event which can't be decoded
+		value.extend_from_slice(&[1, 0, 1, 0xff]);
+
+		let mut events = Vec::new();
+		assert!(meta.events(&value, &mut events).is_err());
+		assert_eq!(events.len(), 3);
+		assert_eq!(events[0].phase, "ApplyExtrinsic");
+		assert_eq!(events[0].extrinsic_index, Some(1));
+		assert_eq!(events[0].event.args, Some(json!([hex_bytes(&[1; 32]), hex_bytes(&[2; 32]), "7"])));
+		assert_eq!(events[1].event.args, Some(json!([{ "weight": 10, "class": "Normal", "pays_fee": "Yes" }])));
+		assert_eq!((events[2].phase.as_str(), events[2].event.name.as_str()), ("Finalization", "Unknown"));
+		assert_eq!(events[2].event.args, None);
+	}
+}
@@ -0,0 +1,71 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Archive any Substrate chain without compiling its native runtime.
+//!
+//! Blocks are decoded as [`OpaqueBlock`]s, and `Core_execute_block` and `Metadata_metadata` are called through the
+//! `CallExecutor` of the client, so no runtime API type is needed. [`WasmExecutor`] has no native runtime,
+//! so the runtime is always executed from the Wasm code stored on chain.
+//!
+//! Chains with a block number other than `u32`, or a hashing other than `BlakeTwo256`, still need their own types.
+//!
+//! ```ignore
+//! let archive = GenericArchiveBuilder::<SecondaryRocksDb>::with_config(config).chain_spec(spec).build()?;
+//! ```
+
+use sc_executor::{error::Error as ExecutorError, Externalities, NativeExecutionDispatch, NativeVersion};
+use sp_api::RuntimeVersion;
+use sp_runtime::{create_runtime_str, generic, traits::BlakeTwo256, OpaqueExtrinsic};
+
+use crate::archive::ArchiveBuilder;
+
+/// Header of a chain with `u32` block numbers and `BlakeTwo256` hashing.
+pub type Header = generic::Header<u32, BlakeTwo256>;
+/// Block of a chain whose extrinsics are not decoded.
+pub type OpaqueBlock = generic::Block<Header, OpaqueExtrinsic>;
+/// Builds an archive of any chain using [`OpaqueBlock`], executing the runtime only from Wasm.
+pub type GenericArchiveBuilder<DB> = ArchiveBuilder<OpaqueBlock, (), WasmExecutor, DB>;
+
+/// Executor which has no native runtime to dispatch to.
+/// Its native version never matches the version on chain, so every call is executed from Wasm.
+pub struct WasmExecutor;
+
+impl NativeExecutionDispatch for WasmExecutor {
+	// the Substrate host functions are always provided by the executor
+	type ExtendHostFunctions = ();
+
+	fn dispatch(_: &mut dyn Externalities, method: &str, _: &[u8]) -> sc_executor::error::Result<Vec<u8>> {
+		Err(ExecutorError::MethodNotFound(method.to_string()))
+	}
+
+	fn native_version() -> NativeVersion {
+		NativeVersion {
+			runtime_version: RuntimeVersion { spec_name: create_runtime_str!("wasm-only"), ..Default::default() },
+			can_author_with: Default::default(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn should_never_execute_natively() {
+		let on_chain = RuntimeVersion { spec_name: create_runtime_str!("polkadot"), ..Default::default() };
+		assert!(!WasmExecutor::native_version().runtime_version.can_call_with(&on_chain));
+	}
+}
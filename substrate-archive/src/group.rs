@@ -0,0 +1,143 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Several archives running in the same process.
+//! Each chain is archived by its own actor system, while status, metrics and shutdown are shared.
+
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc};
+
+use sp_runtime::traits::Block as BlockT;
+
+use substrate_archive_backend::ReadOnlyDb;
+
+use crate::{
+	archive::Archive,
+	error::Result,
+	http::{self, Response},
+	metrics::Metrics,
+	status::{ArchiveStatus, StatusTracker},
+};
+
+/// Status and metrics of one archive in the group.
+#[derive(Clone)]
+struct Chain {
+	name: String,
+	status: StatusTracker,
+	metrics: Metrics,
+}
+
+/// A group of archives, one for each chain.
+pub struct ArchiveGroup<B: BlockT + Unpin, D: ReadOnlyDb>
+where
+	B::Hash: Unpin,
+{
+	archives: Vec<Box<dyn Archive<B, D>>>,
+	chains: Vec<Chain>,
+	server: Option<smol::Task<()>>,
+}
+
+impl<B: BlockT + Unpin, D: ReadOnlyDb> Default for ArchiveGroup<B, D>
+where
+	B::Hash: Unpin,
+{
+	fn default() -> Self {
+		Self { archives: Vec::new(), chains: Vec::new(), server: None }
+	}
+}
+
+impl<B: BlockT + Unpin, D: ReadOnlyDb> ArchiveGroup<B, D>
+where
+	B::Hash: Unpin,
+{
+	/// Add the archive of the chain `name` to the group.
+	pub fn add<S: Into<String>>(&mut self, name: S, archive: Box<dyn Archive<B, D>>) {
+		let context = archive.context();
+		self.chains.push(Chain {
+			name: name.into(),
+			status: context.status().clone(),
+			metrics: context.metrics().clone(),
+		});
+		self.archives.push(archive);
+	}
+
+	/// Start driving the execution of every archive.
+	pub fn drive(&mut self) -> Result<()> {
+		for archive in self.archives.iter_mut() {
+			archive.drive()?;
+		}
+		Ok(())
+	}
+
+	/// Serve the metrics of all archives on `addr` under `/metrics`,
+	/// their status keyed by chain name under `/status`,
+	/// and health checks which only pass if they pass for every chain under `/health` and `/ready`.
+	pub fn serve(&mut self, addr: SocketAddr) {
+		let chains = Arc::new(self.chains.clone());
+		self.server = Some(smol::spawn(async move {
+			let serve = http::serve(addr, move |path| {
+				let response = respond(&chains, &path);
+				async move { response }
+			});
+			if let Err(e) = serve.await {
+				log::error!("Metrics endpoint stopped: {}", e);
+			}
+		}));
+	}
+
+	/// Get the current indexing status of each archive, keyed by chain name.
+	pub fn status(&self) -> BTreeMap<String, ArchiveStatus> {
+		statuses(&self.chains)
+	}
+
+	/// Shutdown every archive, one after another.
+	/// All archives are shut down even if some fail to, the first error is returned.
+	pub fn shutdown(self) -> Result<()> {
+		std::mem::drop(self.server);
+		let mut result = Ok(());
+		for (archive, chain) in self.archives.into_iter().zip(self.chains.iter()) {
+			log::info!("Shutting down the archive of {}", chain.name);
+			if let Err(e) = archive.boxed_shutdown() {
+				log::error!("Failed to shutdown the archive of {}: {}", chain.name, e);
+				if result.is_ok() {
+					result = Err(e);
+				}
+			}
+		}
+		result
+	}
+}
+
+fn statuses(chains: &[Chain]) -> BTreeMap<String, ArchiveStatus> {
+	chains.iter().map(|c| (c.name.clone(), c.status.snapshot())).collect()
+}
+
+/// Answer a request to the shared endpoint.
+fn respond(chains: &[Chain], path: &str) -> Response {
+	let healthy = match path {
+		"/health" => chains.iter().all(|c| c.status.snapshot().is_healthy()),
+		"/ready" => chains.iter().all(|c| c.status.is_ready()),
+		"/status" => true,
+		"/metrics" => {
+			return match Metrics::encode_all(chains.iter().map(|c| &c.metrics)) {
+				Ok(body) => Response::ok("text/plain; version=0.0.4", body),
+				Err(e) => Response::new(500, "text/plain", e.to_string()),
+			}
+		}
+		_ => return Response::not_found(),
+	};
+	let body = serde_json::to_vec(&statuses(chains)).unwrap_or_default();
+	Response::new(if healthy { 200 } else { 503 }, "application/json", body)
+}
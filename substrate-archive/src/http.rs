@@ -0,0 +1,216 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A minimal HTTP/1.1 server for the operational endpoints of the archive,
+//! and a client for POSTing webhooks.
+//! Only `GET` requests are understood, and every connection is closed after one response.
+
+use std::{future::Future, io, net::SocketAddr, sync::Arc};
+
+use smol::{
+	io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+	net::{TcpListener, TcpStream},
+};
+
+/// A response to an HTTP request.
+#[derive(Debug)]
+pub struct Response {
+	status: u16,
+	content_type: &'static str,
+	body: Vec<u8>,
+}
+
+impl Response {
+	pub fn new(status: u16, content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+		Self { status, content_type, body: body.into() }
+	}
+
+	pub fn ok(content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+		Self::new(200, content_type, body)
+	}
+
+	pub fn not_found() -> Self {
+		Self::new(404, "text/plain", "Not Found")
+	}
+
+	fn method_not_allowed() -> Self {
+		Self::new(405, "text/plain", "Method Not Allowed")
+	}
+
+	fn bad_request() -> Self {
+		Self::new(400, "text/plain", "Bad Request")
+	}
+
+	fn reason(&self) -> &'static str {
+		match self.status {
+			200 => "OK",
+			400 => "Bad Request",
+			404 => "Not Found",
+			405 => "Method Not Allowed",
+			500 => "Internal Server Error",
+			503 => "Service Unavailable",
+			_ => "",
+		}
+	}
+}
+
+/// Serve HTTP requests on `addr`, answering each `GET` with the response `handler` produces for
+/// the requested path. Runs until the returned future is dropped.
+pub async fn serve<F, Fut>(addr: SocketAddr, handler: F) -> io::Result<()>
+where
+	F: Fn(String) -> Fut + Send + Sync + 'static,
+	Fut: Future<Output = Response> + Send + 'static,
+{
+	let listener = TcpListener::bind(addr).await?;
+	log::info!("Serving HTTP on {}", addr);
+	let handler = Arc::new(handler);
+	loop {
+		let (stream, peer) = listener.accept().await?;
+		let handler = handler.clone();
+		smol::spawn(async move {
+			if let Err(e) = handle_connection(stream, &*handler).await {
+				log::debug!("HTTP connection from {} failed: {}", peer, e);
+			}
+		})
+		.detach();
+	}
+}
+
+async fn handle_connection<F, Fut>(mut stream: TcpStream, handler: &F) -> io::Result<()>
+where
+	F: Fn(String) -> Fut,
+	Fut: Future<Output = Response>,
+{
+	let mut reader = BufReader::new(stream.clone());
+	let mut request_line = String::new();
+	reader.read_line(&mut request_line).await?;
+	// drain the headers, we don't need any of them
+	let mut header = String::new();
+	while reader.read_line(&mut header).await? > 2 {
+		header.clear();
+	}
+
+	let mut parts = request_line.split_whitespace();
+	let response = match (parts.next(), parts.next()) {
+		(Some("GET"), Some(path)) => handler(path.to_string()).await,
+		(Some(_), Some(_)) => Response::method_not_allowed(),
+		_ => Response::bad_request(),
+	};
+
+	let head = format!(
+		"HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+		response.status,
+		response.reason(),
+		response.content_type,
+		response.body.len()
+	);
+	stream.write_all(head.as_bytes()).await?;
+	stream.write_all(&response.body).await?;
+	stream.flush().await
+}
+
+/// POST `body` to `url`, which must be a plain `http://` URL.
+/// Returns the status code of the response.
+pub async fn post(url: &str, content_type: &str, body: &[u8]) -> io::Result<u16> {
+	let (authority, path) = split_url(url)?;
+	let addr = if has_port(authority) { authority.to_string() } else { format!("{}:80", authority) };
+	let mut stream = TcpStream::connect(addr).await?;
+	let head = format!(
+		"POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+		path,
+		authority,
+		content_type,
+		body.len()
+	);
+	stream.write_all(head.as_bytes()).await?;
+	stream.write_all(body).await?;
+	stream.flush().await?;
+
+	let mut status_line = String::new();
+	BufReader::new(stream).read_line(&mut status_line).await?;
+	status_line
+		.split_whitespace()
+		.nth(1)
+		.and_then(|status| status.parse().ok())
+		.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP response"))
+}
+
+/// Whether notifications can be POSTed to `url`, which must be a plain `http://` URL with a host.
+pub fn is_supported(url: &str) -> bool {
+	split_url(url).map(|(authority, _)| !authority.is_empty()).unwrap_or(false)
+}
+
+/// Split a `http://` URL into its authority and path.
+fn split_url(url: &str) -> io::Result<(&str, &str)> {
+	let rest = url
+		.strip_prefix("http://")
+		.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("not a http:// URL: {}", url)))?;
+	Ok(match rest.find('/') {
+		Some(i) => (&rest[..i], &rest[i..]),
+		None => (rest, "/"),
+	})
+}
+
+fn has_port(authority: &str) -> bool {
+	// the colons of IPv6 addresses are within brackets
+	authority.rsplit_once(':').map(|(_, port)| !port.contains(']')).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn should_split_urls() {
+		assert_eq!(split_url("http://localhost:8080/hooks/1").unwrap(), ("localhost:8080", "/hooks/1"));
+		assert_eq!(split_url("http://example.com").unwrap(), ("example.com", "/"));
+		assert!(split_url("https://example.com").is_err());
+		assert!(is_supported("http://localhost:8080/hooks/1"));
+		assert!(!is_supported("https://example.com/hook"));
+		assert!(!is_supported("http:///hook"));
+		assert!(has_port("[::1]:80"));
+		assert!(!has_port("[::1]"));
+		assert!(!has_port("example.com"));
+	}
+
+	#[test]
+	fn should_post() {
+		smol::block_on(async {
+			let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+			let url = format!("http://{}/hook", listener.local_addr().unwrap());
+			let server = smol::spawn(async move {
+				let (stream, _) = listener.accept().await.unwrap();
+				let mut reader = BufReader::new(stream.clone());
+				let mut request = String::new();
+				let mut line = String::new();
+				while reader.read_line(&mut line).await.unwrap() > 2 {
+					request.push_str(&line);
+					line.clear();
+				}
+				let mut body = [0u8; 2];
+				smol::io::AsyncReadExt::read_exact(&mut reader, &mut body).await.unwrap();
+				let mut stream = stream;
+				stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").await.unwrap();
+				(request, body)
+			});
+			assert_eq!(post(&url, "application/json", b"{}").await.unwrap(), 204);
+			let (request, body) = server.await;
+			assert!(request.starts_with("POST /hook HTTP/1.1\r\n"));
+			assert!(request.contains("Content-Length: 2\r\n"));
+			assert_eq!(&body, b"{}");
+		});
+	}
+}
@@ -25,17 +25,38 @@ pub use substrate_archive_backend::{ExecutionMethod, ReadOnlyDb, RuntimeConfig,
 
 mod actors;
 pub mod archive;
+pub mod cli;
 pub mod database;
 mod error;
+pub mod export;
+pub mod generic;
+mod group;
+mod http;
 mod logger;
+mod metrics;
+mod parachain;
+mod partitions;
+mod retention;
+pub mod sink;
+mod status;
+mod storage_filter;
 mod tasks;
 mod types;
 mod wasm_tracing;
+mod watch;
 
-pub use self::actors::{ControlConfig, System};
-pub use self::archive::{Archive, ArchiveBuilder, ArchiveConfig, ChainConfig, TracingConfig};
-pub use self::database::{queries, DatabaseConfig};
+pub use self::actors::{ControlConfig, ExecutionOrder, System};
+pub use self::archive::{Archive, ArchiveBuilder, ArchiveConfig, ChainConfig, ChainEntry, TracingConfig};
+pub use self::database::{queries, BlockSelection, DatabaseConfig, PoolConfig, SslMode};
 pub use self::error::ArchiveError;
+pub use self::group::ArchiveGroup;
+pub use self::metrics::MetricsConfig;
+pub use self::parachain::ParachainConfig;
+pub use self::retention::{Pruned, RetentionConfig};
+pub use self::sink::Sink;
+pub use self::status::{ActorStatus, ArchiveStatus, ReexecutionStatus, StatusTracker};
+pub use self::storage_filter::StorageConfig;
+pub use self::watch::WatchNotification;
 
 pub mod chain_traits {
 	//! Traits defining functions on the client needed for indexing
@@ -144,8 +165,10 @@ mod test {
 					"
                     TRUNCATE TABLE metadata CASCADE;
                     TRUNCATE TABLE storage CASCADE;
+                    TRUNCATE TABLE storage_values;
                     TRUNCATE TABLE blocks CASCADE;
-                    TRUNCATE TABLE _background_tasks
+                    TRUNCATE TABLE _background_tasks;
+                    TRUNCATE TABLE pending_tasks
                     ",
 				)
 				.await
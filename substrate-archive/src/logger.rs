@@ -16,12 +16,17 @@
 
 //! logging
 
-use std::{fs, io, path::PathBuf};
+use std::{
+	fs, io,
+	path::PathBuf,
+	sync::atomic::{AtomicBool, Ordering},
+};
 
 use fern::colors::{Color, ColoredLevelConfig};
+use parking_lot::Mutex;
 use serde::Deserialize;
 
-use crate::substrate_archive_default_dir;
+use crate::{status::StatusTracker, substrate_archive_default_dir};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct LoggerConfig {
@@ -58,7 +63,21 @@ fn default_file_log_name() -> String {
 	"substrate-archive.log".into()
 }
 
-pub fn init(config: LoggerConfig) -> io::Result<()> {
+/// Whether the global logger was installed.
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+/// Status of every archive in this process.
+static STATUS_TRACKERS: Mutex<Vec<StatusTracker>> = parking_lot::const_mutex(Vec::new());
+
+/// Install the global logger, recording errors on `status`.
+/// Archives built later in the same process only register their status,
+/// the logger is configured by the first one.
+/// A logged error doesn't tell which archive it is from, so errors are only recorded while one archive is registered.
+pub fn init(config: LoggerConfig, status: StatusTracker) -> io::Result<()> {
+	STATUS_TRACKERS.lock().push(status);
+	if INITIALIZED.swap(true, Ordering::SeqCst) {
+		return Ok(());
+	}
+
 	let colors = ColoredLevelConfig::new()
 		.info(Color::Green)
 		.warn(Color::Yellow)
@@ -85,6 +104,13 @@ pub fn init(config: LoggerConfig) -> io::Result<()> {
 		})
 		.chain(io::stdout());
 
+	// keeps the last error around for `Archive::status`
+	let status_dispatcher = fern::Dispatch::new().level(log::LevelFilter::Error).chain(fern::Output::call(|record| {
+		if let [status] = STATUS_TRACKERS.lock().as_slice() {
+			status.record_error(record.args());
+		}
+	}));
+
 	if let Some(file) = config.file {
 		let mut log_dir = file.dir.unwrap_or_else(substrate_archive_default_dir);
 		fs::create_dir_all(log_dir.as_path())?;
@@ -110,9 +136,18 @@ pub fn init(config: LoggerConfig) -> io::Result<()> {
 				))
 			})
 			.chain(fern::log_file(log_dir).expect("Failed to create log file"));
-		fern::Dispatch::new().chain(stdout_dispatcher).chain(file_dispatcher).apply().expect("Could not init logging");
+		fern::Dispatch::new()
+			.chain(stdout_dispatcher)
+			.chain(file_dispatcher)
+			.chain(status_dispatcher)
+			.apply()
+			.expect("Could not init logging");
 	} else {
-		stdout_dispatcher.apply().expect("Could not init logging");
+		fern::Dispatch::new()
+			.chain(stdout_dispatcher)
+			.chain(status_dispatcher)
+			.apply()
+			.expect("Could not init logging");
 	}
 	Ok(())
 }
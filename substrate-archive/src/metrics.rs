@@ -0,0 +1,257 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Prometheus metrics describing the progress of indexing.
+//! Exposed in the text format on the `/metrics` path of the metrics endpoint.
+
+use std::{collections::HashMap, net::SocketAddr};
+
+use prometheus::{
+	core::Collector, exponential_buckets, proto::MetricFamily, Encoder, Histogram, HistogramOpts, IntCounter, IntGauge,
+	Registry, TextEncoder,
+};
+use serde::Deserialize;
+
+use crate::{error::Result, http::Response};
+
+/// Configure the Prometheus metrics endpoint.
+#[derive(Clone, Debug, Deserialize)]
+pub struct MetricsConfig {
+	/// Address the metrics endpoint listens on.
+	#[serde(default = "default_metrics_address")]
+	pub address: SocketAddr,
+}
+
+impl Default for MetricsConfig {
+	fn default() -> Self {
+		Self { address: default_metrics_address() }
+	}
+}
+
+// 9615 is the default of a substrate node, which is likely running on the same machine.
+fn default_metrics_address() -> SocketAddr {
+	([127, 0, 0, 1], 9616).into()
+}
+
+/// All metrics collected by the archive.
+/// Cloning is cheap, clones update the same underlying metrics.
+#[derive(Clone)]
+pub struct Metrics {
+	registry: Registry,
+	/// Blocks inserted into the `blocks` table.
+	pub(crate) blocks_indexed: IntCounter,
+	/// Highest block number in the `blocks` table.
+	pub(crate) indexed_head: IntGauge,
+	/// Best block number known to the node.
+	pub(crate) best_block: IntGauge,
+	/// Finalized block number known to the node.
+	pub(crate) finalized_block: IntGauge,
+	/// Time it takes to execute a block.
+	pub(crate) execute_block_duration: Histogram,
+	/// Jobs waiting in `_background_tasks`.
+	pub(crate) task_queue_depth: IntGauge,
+	/// Rows inserted into the `storage` table.
+	pub(crate) storage_rows_inserted: IntCounter,
+	/// Number of database actors in the actor pool.
+	pub(crate) db_actors: IntGauge,
+	/// Number of database actors currently handling a message.
+	pub(crate) db_actors_busy: IntGauge,
+	/// Open connections in the Postgres connection pool.
+	pub(crate) db_connections: IntGauge,
+	/// Idle connections in the Postgres connection pool.
+	pub(crate) db_connections_idle: IntGauge,
+	/// Runtime version lookups answered by the `RuntimeVersionCache`.
+	pub(crate) rt_cache_hits: IntCounter,
+	/// Runtime version lookups which missed the `RuntimeVersionCache`.
+	pub(crate) rt_cache_misses: IntCounter,
+	/// Watch notifications which could not be delivered.
+	pub(crate) watch_notifications_failed: IntCounter,
+}
+
+impl Metrics {
+	pub fn new() -> Result<Self> {
+		Self::with_labels(None)
+	}
+
+	/// Metrics of one of several chains archived by the same process.
+	/// Every metric is labelled with `chain="<chain>"`.
+	pub fn for_chain(chain: &str) -> Result<Self> {
+		let labels = std::iter::once(("chain".to_string(), chain.to_string())).collect();
+		Self::with_labels(Some(labels))
+	}
+
+	fn with_labels(labels: Option<HashMap<String, String>>) -> Result<Self> {
+		let registry = Registry::new_custom(Some("substrate_archive".into()), labels)?;
+		Ok(Self {
+			blocks_indexed: register(
+				IntCounter::new("blocks_indexed_total", "Blocks inserted into the database")?,
+				&registry,
+			)?,
+			indexed_head: register(IntGauge::new("indexed_head", "Highest block number in the database")?, &registry)?,
+			best_block: register(IntGauge::new("node_best_block", "Best block number known to the node")?, &registry)?,
+			finalized_block: register(
+				IntGauge::new("node_finalized_block", "Finalized block number known to the node")?,
+				&registry,
+			)?,
+			execute_block_duration: register(
+				Histogram::with_opts(
+					HistogramOpts::new("execute_block_duration_seconds", "Time it takes to execute a block")
+						.buckets(exponential_buckets(0.001, 2.0, 16)?),
+				)?,
+				&registry,
+			)?,
+			task_queue_depth: register(IntGauge::new("task_queue_depth", "Blocks queued for execution")?, &registry)?,
+			storage_rows_inserted: register(
+				IntCounter::new("storage_rows_inserted_total", "Rows inserted into the storage table")?,
+				&registry,
+			)?,
+			db_actors: register(IntGauge::new("db_actors", "Database actors in the actor pool")?, &registry)?,
+			db_actors_busy: register(
+				IntGauge::new("db_actors_busy", "Database actors currently handling a message")?,
+				&registry,
+			)?,
+			db_connections: register(
+				IntGauge::new("db_connections", "Open connections in the Postgres pool")?,
+				&registry,
+			)?,
+			db_connections_idle: register(
+				IntGauge::new("db_connections_idle", "Idle connections in the Postgres pool")?,
+				&registry,
+			)?,
+			rt_cache_hits: register(
+				IntCounter::new("runtime_version_cache_hits_total", "Runtime version lookups served from cache")?,
+				&registry,
+			)?,
+			rt_cache_misses: register(
+				IntCounter::new(
+					"runtime_version_cache_misses_total",
+					"Runtime version lookups which missed the cache",
+				)?,
+				&registry,
+			)?,
+			watch_notifications_failed: register(
+				IntCounter::new(
+					"watch_notifications_failed_total",
+					"Watch notifications dropped, since their callback failed or fell behind",
+				)?,
+				&registry,
+			)?,
+			registry,
+		})
+	}
+
+	/// Encode all metrics in the Prometheus text format.
+	pub fn encode(&self) -> Result<Vec<u8>> {
+		let mut buffer = Vec::new();
+		TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+		Ok(buffer)
+	}
+
+	/// Encode the metrics of several archives together.
+	/// Families with the same name are merged, so each is only described once.
+	pub(crate) fn encode_all<'a>(metrics: impl IntoIterator<Item = &'a Metrics>) -> Result<Vec<u8>> {
+		let mut families: Vec<MetricFamily> = Vec::new();
+		for mut family in metrics.into_iter().flat_map(|m| m.registry.gather()) {
+			match families.iter_mut().find(|f| f.get_name() == family.get_name()) {
+				Some(merged) => {
+					for metric in family.take_metric().into_vec() {
+						merged.mut_metric().push(metric);
+					}
+				}
+				None => families.push(family),
+			}
+		}
+		let mut buffer = Vec::new();
+		TextEncoder::new().encode(&families, &mut buffer)?;
+		Ok(buffer)
+	}
+
+	/// Mark a database actor as busy until the returned guard is dropped.
+	pub(crate) fn db_actor_busy(&self) -> BusyGuard {
+		self.db_actors_busy.inc();
+		BusyGuard(self.db_actors_busy.clone())
+	}
+
+	/// Answer a request to the metrics endpoint.
+	pub(crate) fn respond(&self, path: &str) -> Response {
+		match path {
+			"/metrics" => match self.encode() {
+				Ok(body) => Response::ok("text/plain; version=0.0.4", body),
+				Err(e) => Response::new(500, "text/plain", e.to_string()),
+			},
+			_ => Response::not_found(),
+		}
+	}
+}
+
+/// Decrements the busy gauge on drop.
+pub(crate) struct BusyGuard(IntGauge);
+
+impl Drop for BusyGuard {
+	fn drop(&mut self) {
+		self.0.dec();
+	}
+}
+
+/// Bring a counter up to date with a total that is tracked elsewhere.
+pub(crate) fn sync_counter(counter: &IntCounter, total: u64) {
+	let current = counter.get();
+	if total > current {
+		counter.inc_by(total - current);
+	}
+}
+
+fn register<T: Collector + Clone + 'static>(metric: T, registry: &Registry) -> Result<T> {
+	registry.register(Box::new(metric.clone()))?;
+	Ok(metric)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn should_encode_prefixed_metrics() {
+		let metrics = Metrics::new().unwrap();
+		metrics.blocks_indexed.inc_by(5);
+		sync_counter(&metrics.rt_cache_hits, 10);
+		sync_counter(&metrics.rt_cache_hits, 7);
+		let text = String::from_utf8(metrics.encode().unwrap()).unwrap();
+		assert!(text.contains("substrate_archive_blocks_indexed_total 5"));
+		assert!(text.contains("substrate_archive_runtime_version_cache_hits_total 10"));
+	}
+
+	#[test]
+	fn should_merge_metrics_of_chains() {
+		let polkadot = Metrics::for_chain("polkadot").unwrap();
+		let kusama = Metrics::for_chain("kusama").unwrap();
+		polkadot.indexed_head.set(10);
+		kusama.indexed_head.set(20);
+		let text = String::from_utf8(Metrics::encode_all(vec![&polkadot, &kusama]).unwrap()).unwrap();
+		assert_eq!(text.matches("# TYPE substrate_archive_indexed_head gauge").count(), 1);
+		assert!(text.contains("substrate_archive_indexed_head{chain=\"polkadot\"} 10"));
+		assert!(text.contains("substrate_archive_indexed_head{chain=\"kusama\"} 20"));
+	}
+
+	#[test]
+	fn busy_guard_should_decrement_on_drop() {
+		let metrics = Metrics::new().unwrap();
+		let guard = metrics.db_actor_busy();
+		assert_eq!(metrics.db_actors_busy.get(), 1);
+		std::mem::drop(guard);
+		assert_eq!(metrics.db_actors_busy.get(), 0);
+	}
+}
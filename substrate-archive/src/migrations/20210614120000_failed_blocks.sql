@@ -0,0 +1,12 @@
+-- Blocks whose execution failed more than the configured number of retries.
+-- `data` is the serialized `execute_block` job, so that it can be queued again.
+CREATE TABLE IF NOT EXISTS failed_blocks (
+	id SERIAL PRIMARY KEY,
+	block_num int check (block_num >= 0 and block_num < 2147483647) NOT NULL,
+	hash bytea NOT NULL UNIQUE REFERENCES blocks(hash) ON DELETE CASCADE ON UPDATE CASCADE,
+	spec int,
+	error text,
+	attempts int NOT NULL,
+	data bytea NOT NULL,
+	failed_at timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
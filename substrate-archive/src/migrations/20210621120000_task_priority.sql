@@ -0,0 +1,13 @@
+-- Blocks to execute wait in `pending_tasks`, ordered by their priority, until the archive moves them to coil's queue.
+-- coil fetches jobs ordered by id, so only a few jobs are kept in `_background_tasks`, and jobs of a higher
+-- priority are moved there first. Jobs of the same priority are moved in the order they were queued.
+CREATE TABLE IF NOT EXISTS pending_tasks (
+	id BIGSERIAL PRIMARY KEY,
+	priority INTEGER NOT NULL DEFAULT 0,
+	job_type TEXT NOT NULL,
+	is_async BOOLEAN NOT NULL,
+	data BYTEA NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS pending_tasks_priority_index ON pending_tasks (priority DESC, id);
@@ -0,0 +1,21 @@
+-- Messages waiting to be published by a `PublisherSink`.
+-- Rows are removed once the broker acknowledged them.
+CREATE TABLE IF NOT EXISTS publisher_events (
+	id BIGSERIAL PRIMARY KEY,
+	publisher text NOT NULL,
+	topic text NOT NULL,
+	schema text NOT NULL,
+	content_type text NOT NULL,
+	block_num int check (block_num >= 0 and block_num < 2147483647) NOT NULL,
+	payload bytea NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS publisher_events_publisher_idx ON publisher_events (publisher, id);
+
+-- The last message each publisher delivered.
+CREATE TABLE IF NOT EXISTS publisher_cursors (
+	publisher text PRIMARY KEY,
+	last_event bigint NOT NULL,
+	block_num int NOT NULL,
+	updated_at timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,7 @@
+-- Storage key prefixes whose changes are POSTed to `callback_url`.
+CREATE TABLE IF NOT EXISTS watches (
+	id SERIAL PRIMARY KEY,
+	prefix bytea NOT NULL,
+	callback_url text NOT NULL,
+	created_at timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
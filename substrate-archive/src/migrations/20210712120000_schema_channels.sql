@@ -0,0 +1,30 @@
+-- Namespace notification channels with the schema of the table, so that the archives of
+-- different chains sharing a database each only hear about their own blocks.
+-- Tables in the `public` schema keep the plain channel name.
+CREATE OR REPLACE FUNCTION table_update_trigger_fn()
+   RETURNS TRIGGER
+   LANGUAGE PLPGSQL
+AS $BODY$
+DECLARE
+  channel TEXT := CASE WHEN TG_TABLE_SCHEMA = 'public' THEN TG_ARGV[0] ELSE TG_TABLE_SCHEMA || '.' || TG_ARGV[0] END;
+  id JSON;
+  notification JSON;
+BEGIN
+
+    IF (TG_OP = 'DELETE') THEN
+      id = OLD.id;
+    ELSE
+      id = NEW.id;
+    END IF;
+
+    -- create json payload
+     notification := json_build_object(
+        'table',TG_TABLE_NAME,
+        'action', TG_OP,
+        'id', id
+    );
+
+    PERFORM pg_notify(channel, notification::TEXT);
+    RETURN NULL;
+END;
+$BODY$
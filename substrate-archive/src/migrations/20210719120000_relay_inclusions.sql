@@ -0,0 +1,23 @@
+-- Relay chain blocks in which the blocks of a parachain were backed or included.
+-- A parablock may be backed or included in several relay chain blocks on different forks.
+CREATE TABLE IF NOT EXISTS relay_inclusions (
+	id SERIAL PRIMARY KEY,
+	para_id int NOT NULL,
+	para_hash bytea NOT NULL,
+	relay_block_num int check (relay_block_num >= 0 and relay_block_num < 2147483647) NOT NULL,
+	relay_hash bytea NOT NULL,
+	-- 'backed' or 'included'
+	status text NOT NULL,
+	UNIQUE (para_hash, relay_hash, status)
+);
+
+CREATE INDEX IF NOT EXISTS relay_inclusions_para_hash_idx ON relay_inclusions (para_hash);
+
+-- The last relay chain block scanned for each parachain.
+-- Blocks are scanned in the order they were executed, rather than by storage row id, since ids are handed out
+-- before concurrent transactions commit, so rows may become visible out of id order.
+CREATE TABLE IF NOT EXISTS relay_cursors (
+	para_id int PRIMARY KEY,
+	block_id int NOT NULL,
+	executed_at timestamp NOT NULL
+);
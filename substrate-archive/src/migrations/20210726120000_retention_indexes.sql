@@ -0,0 +1,3 @@
+-- Indexes used by the retention policies to find superseded storage changes and old traces.
+CREATE INDEX IF NOT EXISTS storage_key_block_num_index ON storage (key, block_num);
+CREATE INDEX IF NOT EXISTS state_traces_block_num_index ON state_traces (block_num);
@@ -0,0 +1,86 @@
+-- Range partition `storage` and `state_traces` by block number.
+-- The existing tables become the first partition of each, covering every block they have rows of.
+-- Later partitions are created ahead of the chain by the archive, and recorded in `partitions`.
+-- Unique indexes of partitioned tables must include the partition key, so `block_num` is added to them.
+-- Since the hash of a block determines its number, rows are unique exactly as before.
+-- Partitions must have the constraints of their parent, so the legacy tables get the primary key and check
+-- constraint names of the new tables. A check of the legacy block range lets attaching them skip the scan.
+CREATE TABLE IF NOT EXISTS partitions (
+	name text PRIMARY KEY,
+	table_name text NOT NULL,
+	from_block int NOT NULL,
+	to_block int NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS partitions_table_name_idx ON partitions (table_name, to_block);
+
+ALTER TABLE storage RENAME TO storage_legacy;
+ALTER INDEX storage_block_num_index RENAME TO storage_legacy_block_num_index;
+ALTER INDEX storage_key_block_num_index RENAME TO storage_legacy_key_block_num_index;
+ALTER TABLE storage_legacy DROP CONSTRAINT storage_pkey;
+ALTER TABLE storage_legacy ADD CONSTRAINT storage_legacy_pkey PRIMARY KEY (id, block_num);
+DROP INDEX only_unique_hash_key_storage;
+
+CREATE TABLE storage (
+	id int NOT NULL DEFAULT nextval('storage_id_seq'),
+	block_num int NOT NULL CONSTRAINT storage_block_num_check CHECK (block_num >= 0 and block_num < 2147483647),
+	hash bytea NOT NULL CONSTRAINT storage_hash_fkey REFERENCES blocks(hash) ON DELETE CASCADE ON UPDATE CASCADE,
+	is_full boolean NOT NULL,
+	key bytea NOT NULL,
+	storage bytea,
+	PRIMARY KEY (id, block_num)
+) PARTITION BY RANGE (block_num);
+
+ALTER SEQUENCE storage_id_seq OWNED BY storage.id;
+CREATE UNIQUE INDEX only_unique_hash_key_storage_block_num ON storage (hash, key, md5(storage), block_num);
+CREATE INDEX storage_block_num_index ON storage (block_num);
+CREATE INDEX storage_key_block_num_index ON storage (key, block_num);
+
+ALTER TABLE state_traces RENAME TO state_traces_legacy;
+ALTER INDEX state_traces_block_num_index RENAME TO state_traces_legacy_block_num_index;
+ALTER TABLE state_traces_legacy DROP CONSTRAINT state_traces_pkey;
+ALTER TABLE state_traces_legacy ADD CONSTRAINT state_traces_legacy_pkey PRIMARY KEY (id, block_num);
+
+CREATE TABLE state_traces (
+	id int NOT NULL DEFAULT nextval('state_traces_id_seq'),
+	block_num int NOT NULL CONSTRAINT state_traces_block_num_check CHECK (block_num >= 0 and block_num < 2147483647),
+	hash bytea NOT NULL CONSTRAINT state_traces_hash_fkey REFERENCES blocks(hash) ON DELETE CASCADE ON UPDATE CASCADE,
+	is_event boolean NOT NULL,
+	timestamp timestamp,
+	duration bigint,
+	file varchar,
+	line int,
+	trace_id int,
+	trace_parent_id int,
+	target varchar,
+	name varchar,
+	traces jsonb,
+	PRIMARY KEY (id, block_num)
+) PARTITION BY RANGE (block_num);
+
+ALTER SEQUENCE state_traces_id_seq OWNED BY state_traces.id;
+CREATE INDEX state_traces_block_num_index ON state_traces (block_num);
+
+DO $$
+DECLARE
+	storage_bound int;
+	traces_bound int;
+BEGIN
+	SELECT COALESCE(MAX(block_num), 0) + 1 INTO storage_bound FROM storage_legacy;
+	EXECUTE format(
+		'ALTER TABLE storage_legacy ADD CONSTRAINT storage_legacy_bound CHECK (block_num >= 0 AND block_num < %s)',
+		storage_bound
+	);
+	EXECUTE format('ALTER TABLE storage ATTACH PARTITION storage_legacy FOR VALUES FROM (0) TO (%s)', storage_bound);
+	ALTER TABLE storage_legacy DROP CONSTRAINT storage_legacy_bound;
+	INSERT INTO partitions (name, table_name, from_block, to_block) VALUES ('storage_legacy', 'storage', 0, storage_bound);
+
+	SELECT COALESCE(MAX(block_num), 0) + 1 INTO traces_bound FROM state_traces_legacy;
+	EXECUTE format(
+		'ALTER TABLE state_traces_legacy ADD CONSTRAINT state_traces_legacy_bound CHECK (block_num >= 0 AND block_num < %s)',
+		traces_bound
+	);
+	EXECUTE format('ALTER TABLE state_traces ATTACH PARTITION state_traces_legacy FOR VALUES FROM (0) TO (%s)', traces_bound);
+	ALTER TABLE state_traces_legacy DROP CONSTRAINT state_traces_legacy_bound;
+	INSERT INTO partitions (name, table_name, from_block, to_block) VALUES ('state_traces_legacy', 'state_traces', 0, traces_bound);
+END $$;
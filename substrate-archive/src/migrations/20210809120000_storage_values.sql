@@ -0,0 +1,18 @@
+-- Content-addressed storage values.
+-- `value_hash` is the blake2-256 hash of the value of every change written from now on.
+-- With `deduplicate_values`, the value itself is kept once in `storage_values` and `storage.storage` is NULL.
+-- Postgres has no blake2, so existing rows keep a NULL `value_hash` and their value inline.
+CREATE TABLE IF NOT EXISTS storage_values (
+	hash bytea PRIMARY KEY,
+	value bytea NOT NULL
+);
+
+ALTER TABLE storage ADD COLUMN value_hash bytea;
+
+-- Deduplicated values are not inline, and a key changes at most once in a block, so changes are unique by
+-- their block and key. Unlike the value, these are never NULL, so deletions and existing rows stay unique as well.
+-- Deletions were not unique before, the first row of each is kept.
+DELETE FROM storage a USING storage b
+WHERE a.hash = b.hash AND a.key = b.key AND a.block_num = b.block_num AND a.id > b.id;
+DROP INDEX only_unique_hash_key_storage_block_num;
+CREATE UNIQUE INDEX only_unique_hash_key_block_num ON storage (hash, key, block_num);
@@ -0,0 +1,10 @@
+-- Track when each block was executed, so that restarting only has to look at blocks which were not.
+ALTER TABLE blocks ADD COLUMN executed_at timestamp;
+
+-- Blocks with storage were executed before the column existed.
+UPDATE blocks SET executed_at = now()
+WHERE EXISTS (SELECT 1 FROM storage WHERE storage.block_num = blocks.block_num AND storage.hash = blocks.hash);
+
+CREATE INDEX IF NOT EXISTS blocks_unexecuted_index ON blocks (id) WHERE executed_at IS NULL;
+-- Relay chain blocks are scanned for parachains in the order they were executed.
+CREATE INDEX IF NOT EXISTS blocks_executed_at_index ON blocks (executed_at, id) WHERE executed_at IS NOT NULL;
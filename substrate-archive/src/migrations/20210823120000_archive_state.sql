@@ -0,0 +1,7 @@
+-- State of the archive which has to survive restarts, by name.
+-- `indexer_cursor` is the highest block number up to which every block is indexed.
+CREATE TABLE IF NOT EXISTS archive_state (
+	key text PRIMARY KEY,
+	value int NOT NULL,
+	updated_at timestamp NOT NULL DEFAULT now()
+);
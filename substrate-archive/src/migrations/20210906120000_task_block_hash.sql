@@ -0,0 +1,7 @@
+-- The hash of the block an `execute_block` job executes, so that looking up whether blocks are queued
+-- doesn't decode every job. Recorded after jobs are queued, jobs queued before are recorded on startup.
+ALTER TABLE _background_tasks ADD COLUMN IF NOT EXISTS block_hash bytea;
+ALTER TABLE pending_tasks ADD COLUMN IF NOT EXISTS block_hash bytea;
+
+CREATE INDEX IF NOT EXISTS _background_tasks_block_hash_index ON _background_tasks (block_hash);
+CREATE INDEX IF NOT EXISTS pending_tasks_block_hash_index ON pending_tasks (block_hash);
@@ -0,0 +1,3 @@
+-- Finds the storage changes referencing a deduplicated value, so that values no change references any more are
+-- deleted from `storage_values`. Only changes whose value is not inline reference `storage_values`.
+CREATE INDEX IF NOT EXISTS storage_value_hash_index ON storage (value_hash) WHERE storage IS NULL;
@@ -0,0 +1,2 @@
+-- Callbacks are POSTed over plain HTTP. Existing watches are not checked, the archive skips those it can't notify.
+ALTER TABLE watches ADD CONSTRAINT watches_http_callback_url CHECK (callback_url LIKE 'http://%') NOT VALID;
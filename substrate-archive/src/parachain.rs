@@ -0,0 +1,330 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Linkage of parablocks to the relay chain.
+//!
+//! The archive of a parachain reads the storage of the relay chain from the tables of a sibling archive,
+//! and records the relay chain blocks each parablock was backed and included in, in `relay_inclusions`.
+//! A parablock is backed in the relay chain block which sets `ParaInclusion::PendingAvailability` to a candidate
+//! with its head, and included in the block which sets `Paras::Heads` to its head.
+//!
+//! Relay chain blocks are scanned in the order they were executed, once their storage is committed.
+//! Rescanning a block after it was executed again is harmless, inclusions are only recorded once.
+
+use std::time::Duration;
+
+use codec::{Decode, Encode};
+use serde::Deserialize;
+use sqlx::{Connection, PgConnection, PgPool};
+
+use sp_core::{
+	hashing::{twox_128, twox_64},
+	sr25519, H256,
+};
+use sp_runtime::traits::{Block as BlockT, Hash as _, HashFor};
+
+use crate::{
+	database::{
+		models::{InclusionStatus, RelayInclusion},
+		queries::{self, StorageRow},
+		DatabaseConfig,
+	},
+	error::Result,
+};
+
+/// How often the relay chain storage is scanned, once all of it was scanned.
+const POLL_INTERVAL: Duration = Duration::from_secs(6);
+/// Number of relay chain blocks scanned at once.
+const SCAN_BLOCKS: i64 = 1_000;
+/// Blocks are marked executed after their storage is inserted, in a statement which takes the time it
+/// started as `executed_at`. Blocks marked less than this long ago are not scanned yet, since that statement
+/// may still be committing after later blocks were marked.
+const EXECUTION_LAG: Duration = Duration::from_secs(60);
+
+/// Configure the archive of a parachain.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ParachainConfig {
+	/// Id of the parachain on the relay chain.
+	pub para_id: u32,
+	/// Database of the relay chain archive.
+	/// The url defaults to the url of the parachain archive, so that the relay chain archive can be kept in
+	/// another schema of the same database.
+	#[serde(default)]
+	pub relay: DatabaseConfig,
+}
+
+/// The leading fields of a `CandidatePendingAvailability` of the relay chain, up to the descriptor of the candidate.
+/// The fields following it are not needed, and are left undecoded.
+#[derive(Decode)]
+#[allow(dead_code)]
+struct PendingCandidate {
+	core: u32,
+	hash: H256,
+	descriptor: CandidateDescriptor,
+}
+
+/// The leading fields of the `CandidateDescriptor` of a candidate, up to the hash of its head.
+#[derive(Decode)]
+#[allow(dead_code)]
+struct CandidateDescriptor {
+	para_id: u32,
+	relay_parent: H256,
+	collator: sr25519::Public,
+	persisted_validation_data_hash: H256,
+	pov_hash: H256,
+	erasure_root: H256,
+	signature: sr25519::Signature,
+	para_head: H256,
+}
+
+/// Where to find the relay chain storage of a parachain.
+#[derive(Clone, Debug)]
+pub struct RelayLink {
+	para_id: u32,
+	relay_url: String,
+	heads_key: Vec<u8>,
+	pending_key: Vec<u8>,
+}
+
+impl RelayLink {
+	pub(crate) fn new(para_id: u32, relay_url: String) -> Self {
+		Self {
+			para_id,
+			relay_url,
+			heads_key: para_storage_key(b"Paras", b"Heads", para_id),
+			pending_key: para_storage_key(b"ParaInclusion", b"PendingAvailability", para_id),
+		}
+	}
+
+	/// Decode the parablock a change of relay chain storage backed or included.
+	fn inclusion<B: BlockT>(&self, row: StorageRow) -> Option<RelayInclusion> {
+		// the candidate was removed or the head cleared
+		let value = row.storage?;
+		let (para_hash, status) = if row.key == self.heads_key {
+			match Vec::<u8>::decode(&mut value.as_slice()) {
+				Ok(head) => (HashFor::<B>::hash(&head).as_ref().to_vec(), InclusionStatus::Included),
+				Err(e) => {
+					log::warn!(
+						"Failed to decode the head of para {} at relay block {}: {}",
+						self.para_id,
+						row.block_num,
+						e
+					);
+					return None;
+				}
+			}
+		} else {
+			match PendingCandidate::decode(&mut value.as_slice()) {
+				Ok(candidate) if candidate.descriptor.para_id == self.para_id => {
+					(candidate.descriptor.para_head.as_ref().to_vec(), InclusionStatus::Backed)
+				}
+				Ok(candidate) => {
+					log::warn!(
+						"Candidate of para {} at relay block {} is a candidate of para {}",
+						self.para_id,
+						row.block_num,
+						candidate.descriptor.para_id
+					);
+					return None;
+				}
+				Err(e) => {
+					log::warn!(
+						"Failed to decode the candidate of para {} at relay block {}: {}",
+						self.para_id,
+						row.block_num,
+						e
+					);
+					return None;
+				}
+			}
+		};
+		Some(RelayInclusion { para_hash, relay_block_num: row.block_num, relay_hash: row.hash, status })
+	}
+}
+
+/// Storage key of a map entry with a `Twox64Concat` hashed para id.
+fn para_storage_key(pallet: &[u8], item: &[u8], para_id: u32) -> Vec<u8> {
+	let id = para_id.encode();
+	[&twox_128(pallet)[..], &twox_128(item)[..], &twox_64(&id)[..], &id[..]].concat()
+}
+
+/// Keep linking parablocks to relay chain blocks, as the relay chain archive indexes storage.
+pub(crate) async fn link<B: BlockT>(pool: PgPool, link: RelayLink) {
+	loop {
+		match scan::<B>(&pool, &link).await {
+			Ok(true) => continue,
+			Ok(false) => (),
+			Err(e) => log::warn!("Failed to link para {} to the relay chain: {}", link.para_id, e),
+		}
+		smol::Timer::after(POLL_INTERVAL).await;
+	}
+}
+
+/// Scan the storage of the next executed relay chain blocks.
+/// Returns true if there are more blocks to scan.
+async fn scan<B: BlockT>(pool: &PgPool, link: &RelayLink) -> Result<bool> {
+	let mut relay = PgConnection::connect(&link.relay_url).await?;
+	let para_id = link.para_id as i32;
+	let mut conn = pool.acquire().await?;
+	let after = queries::relay_cursor(&mut conn, para_id).await?;
+	let blocks = queries::executed_blocks(&mut relay, after.as_ref(), EXECUTION_LAG.as_secs_f64(), SCAN_BLOCKS).await?;
+	let last = match blocks.last() {
+		Some(last) => last,
+		None => return Ok(false),
+	};
+	let block_ids = blocks.iter().map(|b| b.id).collect::<Vec<_>>();
+	let keys = [link.heads_key.clone(), link.pending_key.clone()];
+	let changes = queries::storage_changes(&mut relay, &block_ids, &keys).await?;
+	let inclusions = changes.into_iter().filter_map(|row| link.inclusion::<B>(row)).collect::<Vec<_>>();
+	queries::add_relay_inclusions(&mut conn, para_id, &inclusions, last).await?;
+	log::debug!("Linked {} parablocks of para {} to the relay chain", inclusions.len(), link.para_id);
+	Ok(blocks.len() as i64 == SCAN_BLOCKS)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_runtime::{
+		testing::{Block, ExtrinsicWrapper, Header},
+		traits::{BlakeTwo256, Header as HeaderT},
+	};
+
+	type TestBlock = Block<ExtrinsicWrapper<u64>>;
+
+	fn row(key: Vec<u8>, storage: Vec<u8>) -> StorageRow {
+		StorageRow { id: 1, block_num: 42, hash: vec![0x13, 0x37], key, storage: Some(storage) }
+	}
+
+	#[test]
+	fn should_build_keys_of_para() {
+		let link = RelayLink::new(2000, String::new());
+		assert_eq!(&link.heads_key[..16], &twox_128(b"Paras")[..]);
+		assert_eq!(&link.heads_key[16..32], &twox_128(b"Heads")[..]);
+		assert_eq!(&link.heads_key[40..], &2000u32.to_le_bytes()[..]);
+		assert_eq!(&link.pending_key[..16], &twox_128(b"ParaInclusion")[..]);
+	}
+
+	#[test]
+	fn should_link_included_heads() {
+		let link = RelayLink::new(2000, String::new());
+		let header = Header::new_from_number(7);
+		let head = header.encode();
+		let inclusion = link.inclusion::<TestBlock>(row(link.heads_key.clone(), head.encode())).unwrap();
+		assert_eq!(inclusion.para_hash, BlakeTwo256::hash(&head).as_ref().to_vec());
+		assert_eq!(inclusion.para_hash, header.hash().as_ref().to_vec());
+		assert_eq!(inclusion.relay_block_num, 42);
+		assert_eq!(inclusion.status, InclusionStatus::Included);
+	}
+
+	/// An encoded `CandidatePendingAvailability` of `para_id`, with the head `para_head`.
+	fn candidate(para_id: u32, para_head: [u8; 32]) -> Vec<u8> {
+		let descriptor = [
+			&para_id.encode()[..],
+			// relay parent, collator, persisted validation data hash, PoV hash and erasure root
+			&[0u8; 5 * 32],
+			// signature
+			&[0u8; 64],
+			&para_head,
+			// validation code hash
+			&[0u8; 32],
+		]
+		.concat();
+		// core index and candidate hash, and after the descriptor, availability votes and backers,
+		// block numbers and backing group
+		[&3u32.encode()[..], &[0u8; 32], &descriptor, &[0u8; 2], &[0u8; 12]].concat()
+	}
+
+	#[test]
+	fn should_link_backed_candidates() {
+		let link = RelayLink::new(2000, String::new());
+		let inclusion = link.inclusion::<TestBlock>(row(link.pending_key.clone(), candidate(2000, [7u8; 32]))).unwrap();
+		assert_eq!(inclusion.para_hash, vec![7u8; 32]);
+		assert_eq!(inclusion.status, InclusionStatus::Backed);
+		assert!(link.inclusion::<TestBlock>(row(link.pending_key.clone(), candidate(2001, [7u8; 32]))).is_none());
+		assert!(link.inclusion::<TestBlock>(row(link.pending_key.clone(), vec![0u8; 10])).is_none());
+	}
+
+	/// Insert relay chain block `num`, which sets the head of the para to the header of parablock `num`.
+	/// Its storage is inserted and it is marked executed `secs_ago` seconds ago, in one transaction.
+	async fn insert_relay_block(conn: &mut PgConnection, link: &RelayLink, num: i32, secs_ago: f64) {
+		let hash = vec![num as u8; 32];
+		sqlx::query(
+			"INSERT INTO blocks (parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec)
+            VALUES ($1, $1, $2, $1, $1, $1, $1, 0)",
+		)
+		.bind(&hash)
+		.bind(num)
+		.execute(&mut *conn)
+		.await
+		.unwrap();
+		sqlx::query("INSERT INTO storage (block_num, hash, is_full, key, storage) VALUES ($1, $2, false, $3, $4)")
+			.bind(num)
+			.bind(&hash)
+			.bind(&link.heads_key)
+			.bind(Header::new_from_number(num as u64).encode().encode())
+			.execute(&mut *conn)
+			.await
+			.unwrap();
+		sqlx::query("UPDATE blocks SET executed_at = LOCALTIMESTAMP - make_interval(secs => $2) WHERE hash = $1")
+			.bind(&hash)
+			.bind(secs_ago)
+			.execute(&mut *conn)
+			.await
+			.unwrap();
+	}
+
+	async fn linked_relay_blocks(conn: &mut PgConnection) -> Vec<i32> {
+		sqlx::query_as::<_, (i32,)>(
+			"SELECT relay_block_num FROM relay_inclusions WHERE para_id = 2000 ORDER BY relay_block_num",
+		)
+		.fetch_all(conn)
+		.await
+		.unwrap()
+		.into_iter()
+		.map(|(n,)| n)
+		.collect()
+	}
+
+	#[test]
+	fn should_link_blocks_committed_out_of_order() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			let link = RelayLink::new(2000, crate::DATABASE_URL.clone());
+			let mut conn = crate::PG_POOL.acquire().await.unwrap();
+			crate::partitions::create_ahead(&mut conn, 0, 100).await.unwrap();
+			sqlx::query("DELETE FROM relay_inclusions WHERE para_id = 2000").execute(&mut conn).await.unwrap();
+			sqlx::query("DELETE FROM relay_cursors WHERE para_id = 2000").execute(&mut conn).await.unwrap();
+
+			// block 1 gets the lower storage id, but commits after block 2 was scanned
+			let mut slow = crate::PG_POOL.begin().await.unwrap();
+			insert_relay_block(&mut slow, &link, 1, 90.0).await;
+			insert_relay_block(&mut conn, &link, 2, 120.0).await;
+			// too recently executed to be scanned
+			insert_relay_block(&mut conn, &link, 3, 0.0).await;
+
+			assert!(!scan::<TestBlock>(&crate::PG_POOL, &link).await.unwrap());
+			assert_eq!(linked_relay_blocks(&mut conn).await, vec![2]);
+
+			slow.commit().await.unwrap();
+			assert!(!scan::<TestBlock>(&crate::PG_POOL, &link).await.unwrap());
+			assert_eq!(linked_relay_blocks(&mut conn).await, vec![1, 2]);
+
+			sqlx::query("DELETE FROM relay_inclusions WHERE para_id = 2000").execute(&mut conn).await.unwrap();
+			sqlx::query("DELETE FROM relay_cursors WHERE para_id = 2000").execute(&mut conn).await.unwrap();
+		});
+	}
+}
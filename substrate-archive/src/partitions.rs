@@ -0,0 +1,122 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Range partitions of `storage` and `state_traces` by block number.
+//!
+//! Partitions are created ahead of the best block of the node, and recorded in the `partitions` table.
+//! Rows of blocks beyond the last partition, e.g. while the node syncs faster than partitions are created
+//! periodically, have their partitions created before they are inserted.
+//! Old ranges are removed by dropping whole partitions, rather than deleting their rows.
+
+use sqlx::{Connection, PgConnection};
+
+use crate::{database::queries, error::Result};
+
+/// Tables partitioned by block number.
+pub(crate) const PARTITIONED_TABLES: [&str; 2] = ["storage", "state_traces"];
+/// Number of partitions kept ahead of the best block.
+const PARTITIONS_AHEAD: u32 = 2;
+
+/// Create the partitions of each partitioned table which are missing up to `PARTITIONS_AHEAD` partitions
+/// of `size` blocks beyond `head`.
+///
+/// # Returns the block number every partitioned table has partitions up to
+pub(crate) async fn create_ahead(conn: &mut PgConnection, head: u32, size: u32) -> Result<u32> {
+	let size = size.max(1);
+	let until = head.saturating_add(size.saturating_mul(PARTITIONS_AHEAD));
+	let mut tx = conn.begin().await?;
+	// partitions are created both periodically and by inserts, the bounds must not change while creating them
+	queries::lock_partitions(&mut tx).await?;
+	for table in PARTITIONED_TABLES.iter() {
+		let bound = queries::partition_bound(&mut tx, table).await?.unwrap_or(0);
+		for (from, to) in ranges(bound, until, size) {
+			let name = partition_name(table, from);
+			queries::create_partition(&mut tx, table, &name, from, to).await?;
+			log::info!("Created partition {} for blocks {}..{}", name, from, to);
+		}
+	}
+	tx.commit().await?;
+	Ok(until)
+}
+
+/// Drop the partitions of `table` which only hold rows of blocks before `block_num`.
+///
+/// # Returns the number of partitions dropped
+pub(crate) async fn drop_before(conn: &mut PgConnection, table: &str, block_num: u32) -> Result<usize> {
+	let names = queries::partitions_before(conn, table, block_num).await?;
+	for name in names.iter() {
+		queries::drop_partition(conn, name).await?;
+		log::info!("Dropped partition {}", name);
+	}
+	Ok(names.len())
+}
+
+/// The block ranges of the partitions of `size` blocks following `bound`, which reach up to `until`.
+fn ranges(bound: u32, until: u32, size: u32) -> Vec<(u32, u32)> {
+	let mut ranges = Vec::new();
+	let mut from = bound;
+	while from < until {
+		let to = from.saturating_add(size);
+		ranges.push((from, to));
+		from = to;
+	}
+	ranges
+}
+
+fn partition_name(table: &str, from: u32) -> String {
+	format!("{}_p{}", table, from)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn should_plan_ranges_up_to_until() {
+		assert_eq!(ranges(0, 250, 100), vec![(0, 100), (100, 200), (200, 300)]);
+		assert_eq!(ranges(100, 200, 100), vec![(100, 200)]);
+		assert!(ranges(300, 250, 100).is_empty());
+		assert_eq!(ranges(u32::MAX - 10, u32::MAX, 100), vec![(u32::MAX - 10, u32::MAX)]);
+	}
+
+	#[test]
+	fn should_create_contiguous_partitions_ahead() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = crate::PG_POOL.acquire().await.unwrap();
+			assert_eq!(create_ahead(&mut conn, 10, 100).await.unwrap(), 210);
+			for table in PARTITIONED_TABLES.iter() {
+				assert!(queries::partition_bound(&mut conn, table).await.unwrap().unwrap() >= 210);
+				let bounds = sqlx::query_as::<_, (String, i32, i32)>(
+					"SELECT name, from_block, to_block FROM partitions WHERE table_name = $1 ORDER BY from_block",
+				)
+				.bind(table)
+				.fetch_all(&mut conn)
+				.await
+				.unwrap();
+				assert_eq!(bounds[0].1, 0);
+				assert!(bounds.windows(2).all(|w| w[0].2 == w[1].1));
+
+				// a partition is only dropped once every block it holds is before the block
+				let (name, _, to) = &bounds[0];
+				let to = *to as u32;
+				assert!(queries::partitions_before(&mut conn, table, to - 1).await.unwrap().is_empty());
+				assert_eq!(queries::partitions_before(&mut conn, table, to).await.unwrap(), vec![name.clone()]);
+			}
+		});
+	}
+}
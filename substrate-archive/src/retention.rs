@@ -0,0 +1,176 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Retention policies, bounding the growth of `storage` and `state_traces`.
+//!
+//! Storage changes of pallets whose full history is not kept are deleted once they are older than
+//! `storage_blocks` and were superseded by a newer change of the same key, so the state at every block
+//! which is kept can still be read. Traces are deleted once they are older than `trace_blocks`,
+//! dropping the partitions which only hold old traces. Deduplicated values no storage change references any more
+//! are deleted from `storage_values`.
+//! Rows are deleted in batches, so that the archive keeps running while old data is pruned.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{database::queries, error::Result, partitions, storage_filter::parse_prefix};
+
+/// Configure which storage and traces are kept.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RetentionConfig {
+	/// Seconds between runs of the retention policies.
+	#[serde(default = "default_interval")]
+	pub interval: u64,
+	/// Maximum number of rows deleted by one statement.
+	#[serde(default = "default_batch_size")]
+	pub batch_size: u32,
+	/// Storage prefixes whose full history is kept, in the syntax of the storage prefixes,
+	/// e.g. `"Balances"` or `"System.Account"`.
+	#[serde(default)]
+	pub keep_pallets: Vec<String>,
+	/// Keep the storage changes of all other pallets only for this many blocks behind the indexed head.
+	/// Keeps full history if not set.
+	pub storage_blocks: Option<u32>,
+	/// Keep traces only for this many blocks behind the indexed head.
+	/// Keeps all traces if not set.
+	pub trace_blocks: Option<u32>,
+}
+
+const fn default_interval() -> u64 {
+	3600
+}
+
+const fn default_batch_size() -> u32 {
+	10_000
+}
+
+impl RetentionConfig {
+	/// Check that every prefix of `keep_pallets` is valid.
+	pub(crate) fn validate(&self) -> Result<()> {
+		self.keep_prefixes().map(|_| ())
+	}
+
+	/// Storage key prefixes whose full history is kept.
+	fn keep_prefixes(&self) -> Result<Vec<Vec<u8>>> {
+		self.keep_pallets.iter().map(|prefix| parse_prefix(prefix)).collect()
+	}
+}
+
+/// Number of rows deleted by the retention policies.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Pruned {
+	/// Storage changes deleted.
+	pub storage: u64,
+	/// Deduplicated storage values deleted, which no storage change referenced any more.
+	pub values: u64,
+	/// Traces deleted, not counting those of dropped partitions.
+	pub traces: u64,
+	/// Partitions of traces dropped.
+	pub partitions: u64,
+}
+
+/// Keep applying the retention policies every `interval` seconds.
+pub(crate) async fn run(pool: PgPool, config: RetentionConfig) {
+	loop {
+		smol::Timer::after(Duration::from_secs(config.interval)).await;
+		let pruned = async {
+			let mut conn = pool.acquire().await?;
+			apply(&mut conn, &config).await
+		};
+		match pruned.await {
+			Ok(pruned) if pruned == Pruned::default() => {}
+			Ok(pruned) => log::info!(
+				"Pruned {} storage changes, {} storage values, {} traces and {} partitions of traces",
+				pruned.storage,
+				pruned.values,
+				pruned.traces,
+				pruned.partitions
+			),
+			Err(e) => log::error!("Failed to apply retention policies: {}", e),
+		}
+	}
+}
+
+/// Apply the retention policies once, relative to the highest block in the database.
+pub(crate) async fn apply(conn: &mut PgConnection, config: &RetentionConfig) -> Result<Pruned> {
+	let mut pruned = Pruned::default();
+	let head = match queries::max_block(conn).await? {
+		Some(head) => head,
+		None => return Ok(pruned),
+	};
+	let limit = i64::from(config.batch_size.max(1));
+	if let Some(before) = config.storage_blocks.and_then(|blocks| head.checked_sub(blocks)) {
+		let keep = config.keep_prefixes()?;
+		loop {
+			let deleted = queries::delete_superseded_storage(conn, before, &keep, limit).await?;
+			pruned.storage += deleted;
+			if deleted < limit as u64 {
+				break;
+			}
+		}
+	}
+	pruned.values += delete_unreferenced_values(conn, limit).await?;
+	if let Some(before) = config.trace_blocks.and_then(|blocks| head.checked_sub(blocks)) {
+		// whole partitions of old traces are dropped, only the rest is deleted row by row
+		pruned.partitions += partitions::drop_before(conn, "state_traces", before).await? as u64;
+		loop {
+			let deleted = queries::delete_traces_batch(conn, before, limit).await?;
+			pruned.traces += deleted;
+			if deleted < limit as u64 {
+				break;
+			}
+		}
+	}
+	Ok(pruned)
+}
+
+/// Delete the values of `storage_values` which no storage change references any more, since the changes were
+/// pruned. Scans `limit` values at a time.
+///
+/// # Returns the number of values deleted
+pub(crate) async fn delete_unreferenced_values(conn: &mut PgConnection, limit: i64) -> Result<u64> {
+	let (mut after, mut deleted) = (Vec::new(), 0);
+	while let (Some(last), batch) = queries::delete_unreferenced_values(conn, &after, limit).await? {
+		deleted += batch;
+		after = last;
+	}
+	Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn should_keep_pallets_by_prefix() {
+		let config: RetentionConfig =
+			serde_json::from_str(r#"{ "keep_pallets": ["System", "Balances.Account"], "storage_blocks": 1000 }"#)
+				.unwrap();
+		assert_eq!(config.interval, default_interval());
+		assert_eq!(config.trace_blocks, None);
+		let prefixes = config.keep_prefixes().unwrap();
+		assert_eq!(prefixes.len(), 2);
+		// `System.Account` storage keys start with the twox128 of the pallet name
+		assert_eq!(prefixes[0], hex::decode("26aa394eea5630e07c48ae0c9558cef7").unwrap());
+		// storage items are kept by the prefix of the item
+		assert_eq!(prefixes[1].len(), 32);
+
+		let config: RetentionConfig = serde_json::from_str(r#"{ "keep_pallets": ["System."] }"#).unwrap();
+		assert!(config.validate().is_err());
+	}
+}
@@ -0,0 +1,90 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Destinations for the data indexed by the archive.
+//! PostgreSQL is always written to, because indexing depends on it.
+//! Any other sink added with `ArchiveBuilder::sink` receives the same data.
+
+mod postgres;
+mod publisher;
+
+use futures::future::BoxFuture;
+
+use sp_runtime::traits::Block as BlockT;
+
+pub use self::{
+	postgres::PostgresSink,
+	publisher::{Encoding, Envelope, Publisher, PublisherConfig, PublisherSink},
+};
+use crate::error::Result;
+pub use crate::{
+	types::{BatchBlock, BatchStorage, Block, Metadata, Storage},
+	wasm_tracing::{EventMessage, SpanMessage, TraceData, Traces},
+};
+
+/// Receives the data indexed by the archive.
+/// Every method is a no-op by default, so a sink only needs to implement what it is interested in.
+#[async_trait::async_trait]
+pub trait Sink<B: BlockT>: Send + Sync {
+	/// Write a batch of blocks.
+	async fn write_blocks(&self, _blocks: BatchBlock<B>) -> Result<()> {
+		Ok(())
+	}
+
+	/// Write the storage changes of a batch of executed blocks.
+	async fn write_storage(&self, _storage: BatchStorage<B>) -> Result<()> {
+		Ok(())
+	}
+
+	/// Write the metadata of a runtime version.
+	async fn write_metadata(&self, _metadata: Metadata) -> Result<()> {
+		Ok(())
+	}
+
+	/// Write the traces collected while executing a block.
+	async fn write_traces(&self, _traces: Traces) -> Result<()> {
+		Ok(())
+	}
+}
+
+/// Data which is written to sinks.
+pub(crate) trait SinkData<B: BlockT>: Clone + Send + 'static {
+	fn write_to<'a>(self, sink: &'a dyn Sink<B>) -> BoxFuture<'a, Result<()>>;
+}
+
+impl<B: BlockT> SinkData<B> for BatchBlock<B> {
+	fn write_to<'a>(self, sink: &'a dyn Sink<B>) -> BoxFuture<'a, Result<()>> {
+		sink.write_blocks(self)
+	}
+}
+
+impl<B: BlockT> SinkData<B> for BatchStorage<B> {
+	fn write_to<'a>(self, sink: &'a dyn Sink<B>) -> BoxFuture<'a, Result<()>> {
+		sink.write_storage(self)
+	}
+}
+
+impl<B: BlockT> SinkData<B> for Metadata {
+	fn write_to<'a>(self, sink: &'a dyn Sink<B>) -> BoxFuture<'a, Result<()>> {
+		sink.write_metadata(self)
+	}
+}
+
+impl<B: BlockT> SinkData<B> for Traces {
+	fn write_to<'a>(self, sink: &'a dyn Sink<B>) -> BoxFuture<'a, Result<()>> {
+		sink.write_traces(self)
+	}
+}
@@ -0,0 +1,138 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The PostgreSQL sink, which every archive writes to.
+
+use std::{
+	sync::{
+		atomic::{AtomicU32, Ordering},
+		Arc,
+	},
+	time::Duration,
+};
+
+use sp_runtime::traits::{Block as BlockT, NumberFor};
+
+use super::Sink;
+use crate::{
+	database::{
+		models::{DeduplicatedStorage, StorageModel},
+		queries, Database, DbConn,
+	},
+	error::Result,
+	metrics::Metrics,
+	partitions,
+	types::{BatchBlock, BatchStorage, Block, Metadata},
+	wasm_tracing::Traces,
+};
+
+/// Inserts into the tables of the archive database.
+/// Blocks are only inserted once the metadata of their runtime version exists,
+/// and storage once its block exists.
+#[derive(Clone)]
+pub struct PostgresSink {
+	db: Database,
+	metrics: Metrics,
+	/// Keep storage values once in `storage_values`, instead of inline.
+	deduplicate_values: bool,
+	/// Number of blocks in each partition of `storage` and `state_traces`.
+	partition_blocks: u32,
+	/// Block number the partitions are known to reach, shared by the clones of the sink.
+	partition_bound: Arc<AtomicU32>,
+}
+
+impl PostgresSink {
+	pub(crate) fn new(db: Database, metrics: Metrics, deduplicate_values: bool, partition_blocks: u32) -> Self {
+		Self { db, metrics, deduplicate_values, partition_blocks, partition_bound: Arc::new(AtomicU32::new(0)) }
+	}
+
+	/// Create the partitions rows of blocks up to `block_num` are inserted into, if they don't exist yet.
+	async fn ensure_partitions(&self, block_num: u32) -> Result<()> {
+		if block_num < self.partition_bound.load(Ordering::Acquire) {
+			return Ok(());
+		}
+		let bound = partitions::create_ahead(&mut *self.db.conn().await?, block_num, self.partition_blocks).await?;
+		self.partition_bound.fetch_max(bound, Ordering::AcqRel);
+		Ok(())
+	}
+
+	// Returns true if all versions are in database
+	// false if versions are missing
+	async fn db_contains_metadata<B: BlockT>(blocks: &[Block<B>], conn: &mut DbConn) -> Result<bool> {
+		let specs: hashbrown::HashSet<u32> = blocks.iter().map(|b| b.spec).collect();
+		let versions: hashbrown::HashSet<u32> = queries::get_versions(conn).await?.into_iter().collect();
+		Ok(specs.is_subset(&versions))
+	}
+}
+
+#[async_trait::async_trait]
+impl<B> Sink<B> for PostgresSink
+where
+	B: BlockT,
+	NumberFor<B>: Into<u32>,
+{
+	async fn write_blocks(&self, blks: BatchBlock<B>) -> Result<()> {
+		let mut conn = self.db.conn().await?;
+		while !Self::db_contains_metadata(blks.inner(), &mut conn).await? {
+			log::info!("Doesn't contain metadata");
+			smol::Timer::after(Duration::from_millis(50)).await;
+		}
+		std::mem::drop(conn);
+		let rows = self.db.insert(blks).await?;
+		self.metrics.blocks_indexed.inc_by(rows);
+		queries::advance_indexer_cursor(&mut *self.db.conn().await?).await?;
+		Ok(())
+	}
+
+	async fn write_storage(&self, storages: BatchStorage<B>) -> Result<()> {
+		let mut conn = self.db.conn().await?;
+		let mut block_nums = storages.inner().iter().map(|s| s.block_num()).collect::<Vec<_>>();
+		let hashes = storages.inner().iter().map(|s| s.hash().as_ref().to_vec()).collect::<Vec<_>>();
+		block_nums.sort_unstable();
+		if !block_nums.is_empty() {
+			log::debug!("Inserting: {:#?}, {} .. {}", block_nums.len(), block_nums[0], block_nums.last().unwrap());
+		}
+		let len = block_nums.len();
+		while queries::has_blocks::<B>(block_nums.as_slice(), &mut conn).await?.len() != len {
+			smol::Timer::after(std::time::Duration::from_millis(50)).await;
+		}
+		// we drop the connection early so that the insert() has the use of all db connections
+		std::mem::drop(conn);
+		if let Some(max) = block_nums.last() {
+			self.ensure_partitions(*max).await?;
+		}
+		let storage = Vec::<StorageModel<B>>::from(storages);
+		let rows = if self.deduplicate_values {
+			self.db.insert(DeduplicatedStorage(storage)).await?
+		} else {
+			self.db.insert(storage).await?
+		};
+		self.metrics.storage_rows_inserted.inc_by(rows);
+		queries::mark_executed(&mut *self.db.conn().await?, &hashes).await?;
+		Ok(())
+	}
+
+	async fn write_metadata(&self, meta: Metadata) -> Result<()> {
+		self.db.insert(meta).await?;
+		Ok(())
+	}
+
+	async fn write_traces(&self, traces: Traces) -> Result<()> {
+		self.ensure_partitions(traces.block_num()).await?;
+		self.db.insert(traces).await?;
+		Ok(())
+	}
+}
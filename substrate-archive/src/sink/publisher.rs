@@ -0,0 +1,457 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A sink publishing indexed data to a message broker, like Kafka or NATS.
+//!
+//! Messages are first queued in the `publisher_events` table, then published in order.
+//! A message is only removed from the queue once the broker acknowledged it, and the
+//! `publisher_cursors` table records the last acknowledged message of every publisher.
+//! Messages which could not be published are retried on the next write, including after a restart,
+//! so delivery is at-least-once: consumers may see a message twice, but never miss one.
+
+use codec::Encode;
+use futures::lock::Mutex;
+use serde::{Deserialize, Serialize};
+
+use sp_runtime::{
+	generic::SignedBlock,
+	traits::{Block as BlockT, Header as _, NumberFor},
+};
+use sp_storage::{StorageData, StorageKey};
+
+use super::Sink;
+use crate::{
+	database::{self, models::PublisherEvent, queries, Database, DatabaseConfig, PoolConfig},
+	error::Result,
+	types::{BatchBlock, BatchStorage},
+	wasm_tracing::{TraceData, Traces},
+};
+
+/// Maximum number of messages read from the queue at once.
+const RELAY_BATCH: i64 = 1000;
+/// Connections a sink uses at most, one queueing messages and one relaying them.
+const MAX_CONNECTIONS: u32 = 2;
+
+/// Format of the payload of published messages.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+	/// JSON, with hashes and storage as hex strings.
+	Json,
+	/// SCALE, as used by the runtime.
+	Scale,
+}
+
+impl Default for Encoding {
+	fn default() -> Self {
+		Encoding::Json
+	}
+}
+
+impl Encoding {
+	fn content_type(&self) -> &'static str {
+		match self {
+			Encoding::Json => "application/json",
+			Encoding::Scale => "application/scale",
+		}
+	}
+
+	fn encode<T: Serialize + Encode>(&self, payload: &T) -> Result<Vec<u8>> {
+		match self {
+			Encoding::Json => Ok(serde_json::to_vec(payload)?),
+			Encoding::Scale => Ok(payload.encode()),
+		}
+	}
+}
+
+/// Configure a `PublisherSink`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PublisherConfig {
+	/// Name of the publisher, which identifies its queue and cursor.
+	/// Must be unique among the publishers writing to the same database.
+	#[serde(default = "default_name")]
+	pub name: String,
+	/// Messages are published to the `<topic>.blocks`, `<topic>.storage` and `<topic>.traces` topics.
+	#[serde(default = "default_topic")]
+	pub topic: String,
+	/// Format of the payload.
+	#[serde(default)]
+	pub encoding: Encoding,
+}
+
+impl Default for PublisherConfig {
+	fn default() -> Self {
+		Self { name: default_name(), topic: default_topic(), encoding: Encoding::default() }
+	}
+}
+
+fn default_name() -> String {
+	"default".into()
+}
+
+fn default_topic() -> String {
+	"substrate_archive".into()
+}
+
+/// A message to publish.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Envelope {
+	pub topic: String,
+	/// The `schema` header names the payload type and its version, e.g. `substrate_archive.block.v1`.
+	/// The `content-type` header is the encoding of the payload.
+	/// The `block-num` header is the number of the block the payload belongs to.
+	pub headers: Vec<(String, String)>,
+	pub payload: Vec<u8>,
+}
+
+impl From<PublisherEvent> for Envelope {
+	fn from(event: PublisherEvent) -> Self {
+		Self {
+			topic: event.topic,
+			headers: vec![
+				("schema".into(), event.schema),
+				("content-type".into(), event.content_type),
+				("block-num".into(), event.block_num.to_string()),
+			],
+			payload: event.payload,
+		}
+	}
+}
+
+/// Client of a message broker.
+#[async_trait::async_trait]
+pub trait Publisher: Send + Sync + 'static {
+	/// Publish a message. Should only return once the broker acknowledged the message.
+	async fn publish(&self, message: Envelope) -> Result<()>;
+}
+
+/// Payload of messages on the `<topic>.blocks` topic.
+#[derive(Serialize, Encode)]
+struct BlockPayload<'a, B: BlockT> {
+	block_num: u32,
+	hash: B::Hash,
+	spec: u32,
+	block: &'a SignedBlock<B>,
+}
+
+/// Payload of messages on the `<topic>.storage` topic.
+#[derive(Serialize, Encode)]
+struct StoragePayload<'a, B: BlockT> {
+	block_num: u32,
+	hash: &'a B::Hash,
+	full_storage: bool,
+	changes: &'a [(StorageKey, Option<StorageData>)],
+}
+
+/// Payload of messages on the `<topic>.traces` topic.
+#[derive(Serialize, Encode)]
+struct TracesPayload {
+	block_num: u32,
+	hash: Vec<u8>,
+	spans: Vec<TracePayload>,
+	events: Vec<TracePayload>,
+}
+
+/// A span or event. Events have no id and duration.
+#[derive(Serialize, Encode)]
+struct TracePayload {
+	id: Option<u64>,
+	parent_id: Option<u64>,
+	name: String,
+	target: String,
+	level: String,
+	/// Nanoseconds since the unix epoch.
+	timestamp: i64,
+	duration_nanos: Option<i64>,
+	file: Option<String>,
+	line: Option<u32>,
+	values: Vec<(String, String)>,
+}
+
+impl From<&Traces> for TracesPayload {
+	fn from(traces: &Traces) -> Self {
+		let values = |data: &TraceData| {
+			let mut values = data.iter().map(|(k, v)| (k.to_string(), v)).collect::<Vec<_>>();
+			values.sort();
+			values
+		};
+		let spans = traces
+			.spans
+			.iter()
+			.map(|span| TracePayload {
+				id: Some(span.id.into_u64()),
+				parent_id: span.parent_id.as_ref().map(|id| id.into_u64()),
+				name: span.name.clone(),
+				target: span.target.clone(),
+				level: span.level.to_string(),
+				timestamp: span.start_time.timestamp_nanos(),
+				duration_nanos: span.overall_time.num_nanoseconds(),
+				file: span.file.clone(),
+				line: span.line,
+				values: values(&span.values),
+			})
+			.collect();
+		let events = traces
+			.events
+			.iter()
+			.map(|event| TracePayload {
+				id: None,
+				parent_id: event.parent_id.as_ref().map(|id| id.into_u64()),
+				name: event.name.clone(),
+				target: event.target.clone(),
+				level: event.level.to_string(),
+				timestamp: event.time.timestamp_nanos(),
+				duration_nanos: None,
+				file: event.file.clone(),
+				line: event.line,
+				values: values(&event.values),
+			})
+			.collect();
+		Self { block_num: traces.block_num(), hash: traces.hash().to_vec(), spans, events }
+	}
+}
+
+/// Publishes every block, storage change set and trace batch as a message to a broker,
+/// with at-least-once delivery.
+pub struct PublisherSink<P> {
+	config: PublisherConfig,
+	publisher: P,
+	db: Database,
+	/// Held while relaying, so that messages are published in order.
+	relay: Mutex<()>,
+}
+
+impl<P: Publisher> PublisherSink<P> {
+	/// Create a publisher sink, which queues messages in the database at `url`.
+	/// Connects like the archive configured with `db_config`, with its connection options, schema and pool settings,
+	/// but opens at most `MAX_CONNECTIONS` connections.
+	pub async fn new(url: &str, db_config: &DatabaseConfig, config: PublisherConfig, publisher: P) -> Result<Self> {
+		let mut url = database::connection_url(url, db_config)?;
+		if let Some(schema) = &db_config.schema {
+			url = database::schema_url(&url, schema)?;
+		}
+		let max_connections = db_config.pool.max_connections.min(MAX_CONNECTIONS);
+		let pool = PoolConfig {
+			min_connections: db_config.pool.min_connections.min(max_connections),
+			max_connections,
+			..db_config.pool.clone()
+		};
+		let db = Database::new(url, None, &pool).await?;
+		Ok(Self { config, publisher, db, relay: Mutex::new(()) })
+	}
+
+	/// Publish all queued messages in order, stopping at the first message the broker rejects.
+	///
+	/// # Returns the number of messages published
+	pub async fn relay(&self) -> Result<usize> {
+		let _relay = self.relay.lock().await;
+		let mut conn = self.db.conn().await?;
+		let mut published = 0;
+		loop {
+			let events = queries::pending_publisher_events(&mut conn, &self.config.name, RELAY_BATCH).await?;
+			if events.is_empty() {
+				return Ok(published);
+			}
+			let mut acked = Vec::with_capacity(events.len());
+			let mut error = None;
+			for event in events {
+				match self.publisher.publish(event.clone().into()).await {
+					Ok(()) => acked.push(event),
+					Err(e) => {
+						error = Some(e);
+						break;
+					}
+				}
+			}
+			queries::ack_publisher_events(&mut conn, &self.config.name, &acked).await?;
+			published += acked.len();
+			if let Some(e) = error {
+				return Err(e);
+			}
+		}
+	}
+
+	fn event<T: Serialize + Encode>(&self, kind: &str, block_num: u32, payload: &T) -> Result<PublisherEvent> {
+		Ok(PublisherEvent {
+			id: 0,
+			topic: format!("{}.{}", self.config.topic, kind),
+			schema: format!("substrate_archive.{}.v1", kind),
+			content_type: self.config.encoding.content_type().into(),
+			block_num: block_num as i32,
+			payload: self.config.encoding.encode(payload)?,
+		})
+	}
+
+	/// Queue messages, then publish everything that is queued.
+	async fn send(&self, events: Vec<PublisherEvent>) -> Result<()> {
+		let mut conn = self.db.conn().await?;
+		queries::append_publisher_events(&mut conn, &self.config.name, &events).await?;
+		std::mem::drop(conn);
+		self.relay().await?;
+		Ok(())
+	}
+}
+
+#[async_trait::async_trait]
+impl<B, P> Sink<B> for PublisherSink<P>
+where
+	B: BlockT,
+	NumberFor<B>: Into<u32>,
+	P: Publisher,
+{
+	async fn write_blocks(&self, blocks: BatchBlock<B>) -> Result<()> {
+		let events = blocks
+			.inner()
+			.iter()
+			.map(|b| {
+				let block_num: u32 = (*b.inner.block.header().number()).into();
+				let payload =
+					BlockPayload::<B> { block_num, hash: b.inner.block.hash(), spec: b.spec, block: &b.inner };
+				self.event("blocks", block_num, &payload)
+			})
+			.collect::<Result<Vec<_>>>()?;
+		self.send(events).await
+	}
+
+	async fn write_storage(&self, storage: BatchStorage<B>) -> Result<()> {
+		let events = storage
+			.inner()
+			.iter()
+			.map(|s| {
+				let payload = StoragePayload::<B> {
+					block_num: s.block_num(),
+					hash: s.hash(),
+					full_storage: s.is_full(),
+					changes: s.changes(),
+				};
+				self.event("storage", s.block_num(), &payload)
+			})
+			.collect::<Result<Vec<_>>>()?;
+		self.send(events).await
+	}
+
+	async fn write_traces(&self, traces: Traces) -> Result<()> {
+		let event = self.event("traces", traces.block_num(), &TracesPayload::from(&traces))?;
+		self.send(vec![event]).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::error::ArchiveError;
+	use sp_runtime::{
+		generic::{Block as GenericBlock, Header},
+		traits::BlakeTwo256,
+		OpaqueExtrinsic,
+	};
+	use std::sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	};
+
+	type Block = GenericBlock<Header<u32, BlakeTwo256>, OpaqueExtrinsic>;
+
+	/// In-process stand-in for a message broker.
+	#[derive(Clone, Default)]
+	struct MemoryBroker {
+		messages: Arc<parking_lot::Mutex<Vec<Envelope>>>,
+		down: Arc<AtomicBool>,
+	}
+
+	#[async_trait::async_trait]
+	impl Publisher for MemoryBroker {
+		async fn publish(&self, message: Envelope) -> Result<()> {
+			if self.down.load(Ordering::SeqCst) {
+				return Err(ArchiveError::Sink("broker is down".into()));
+			}
+			self.messages.lock().push(message);
+			Ok(())
+		}
+	}
+
+	fn storage(block_num: u32) -> BatchStorage<Block> {
+		let changes = vec![(StorageKey(vec![1, 2]), Some(StorageData(vec![3])))];
+		BatchStorage::new(vec![crate::types::Storage::new(Default::default(), block_num, false, changes)])
+	}
+
+	#[test]
+	fn should_encode_storage_with_headers() {
+		let payload = StoragePayload::<Block> {
+			block_num: 7,
+			hash: &Default::default(),
+			full_storage: false,
+			changes: &[(StorageKey(vec![1, 2]), None)],
+		};
+		let json = Encoding::Json.encode(&payload).unwrap();
+		let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+		assert_eq!(json["block_num"], 7);
+		assert_eq!(json["changes"][0][0], "0x0102");
+		let scale = Encoding::Scale.encode(&payload).unwrap();
+		assert_eq!(&scale[..4], &7u32.encode()[..]);
+
+		let event = PublisherEvent {
+			id: 1,
+			topic: "chain.storage".into(),
+			schema: "substrate_archive.storage.v1".into(),
+			content_type: Encoding::Scale.content_type().into(),
+			block_num: 7,
+			payload: scale,
+		};
+		let envelope = Envelope::from(event);
+		assert!(envelope.headers.contains(&("schema".into(), "substrate_archive.storage.v1".into())));
+		assert!(envelope.headers.contains(&("content-type".into(), "application/scale".into())));
+		assert!(envelope.headers.contains(&("block-num".into(), "7".into())));
+	}
+
+	#[test]
+	fn should_publish_at_least_once() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			let broker = MemoryBroker::default();
+			let config = PublisherConfig { name: "test_at_least_once".into(), ..Default::default() };
+			let sink = PublisherSink::new(&crate::DATABASE_URL, &DatabaseConfig::default(), config, broker.clone())
+				.await
+				.unwrap();
+
+			broker.down.store(true, Ordering::SeqCst);
+			assert!(Sink::<Block>::write_storage(&sink, storage(1)).await.is_err());
+			assert!(Sink::<Block>::write_storage(&sink, storage(2)).await.is_err());
+			assert!(broker.messages.lock().is_empty());
+
+			// the queued messages are delivered, in order, once the broker is back
+			broker.down.store(false, Ordering::SeqCst);
+			Sink::<Block>::write_storage(&sink, storage(3)).await.unwrap();
+			let messages = broker.messages.lock().clone();
+			let block_nums = messages
+				.iter()
+				.map(|m| m.headers.iter().find(|(k, _)| k == "block-num").unwrap().1.clone())
+				.collect::<Vec<_>>();
+			assert_eq!(block_nums, vec!["1", "2", "3"]);
+			assert!(messages.iter().all(|m| m.topic == "substrate_archive.storage"));
+
+			let mut conn = sink.db.conn().await.unwrap();
+			let cursor: (i32,) = sqlx::query_as("SELECT block_num FROM publisher_cursors WHERE publisher = $1")
+				.bind("test_at_least_once")
+				.fetch_one(&mut conn)
+				.await
+				.unwrap();
+			assert_eq!(cursor.0, 3);
+			assert_eq!(sink.relay().await.unwrap(), 0);
+		});
+	}
+}
@@ -0,0 +1,268 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Indexing status of a running archive.
+//! The status is sampled periodically by the actor system, and served on the
+//! `/status`, `/health` and `/ready` paths of the HTTP endpoint.
+//! The endpoint is the one serving metrics, so the status is only served if metrics are.
+
+use std::{
+	fmt::Display,
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+use parking_lot::RwLock;
+use serde::Serialize;
+
+use crate::{database::BlockSelection, http::Response};
+
+/// If the archive is behind the chain and made no progress for this long, it is considered stuck.
+const STALL_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Liveness of the actors making up the archive.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ActorStatus {
+	pub blocks: bool,
+	pub metadata: bool,
+	pub storage: bool,
+	pub db_pool: bool,
+}
+
+impl ActorStatus {
+	/// Returns true if every actor is alive.
+	pub fn all_alive(&self) -> bool {
+		self.blocks && self.metadata && self.storage && self.db_pool
+	}
+}
+
+/// A snapshot of the indexing status.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ArchiveStatus {
+	/// Whether the actor system is running.
+	pub running: bool,
+	/// Highest block in the database.
+	pub indexed_head: Option<u32>,
+	/// Best block of the node.
+	pub best_head: u32,
+	/// Finalized block of the node.
+	pub finalized_head: u32,
+	/// Blocks waiting to be executed.
+	pub pending_jobs: u64,
+	/// Blocks which failed execution at least once and are waiting to be retried.
+	pub failed_jobs: u64,
+	/// The last error encountered by the archive.
+	/// Logged errors are only included if this is the only archive of the process, e.g. not in an `ArchiveGroup`.
+	pub last_error: Option<String>,
+	/// Liveness of each actor.
+	pub actors: ActorStatus,
+	/// Seconds since the indexed head or the execution queue last changed.
+	pub secs_since_progress: u64,
+	/// Progress of the last blocks selected to be executed again.
+	pub reexecution: Option<ReexecutionStatus>,
+}
+
+/// Progress of executing a selection of blocks again.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ReexecutionStatus {
+	/// The blocks executed again.
+	pub blocks: BlockSelection,
+	/// Number of selected blocks.
+	pub total: u64,
+	/// Selected blocks which were not executed again yet.
+	pub remaining: u64,
+}
+
+impl ArchiveStatus {
+	/// The archive is behind the chain and has made no progress for a while.
+	pub fn is_stalled(&self) -> bool {
+		let behind = self.indexed_head.map(|h| h < self.finalized_head).unwrap_or(true) || self.pending_jobs > 0;
+		behind && self.secs_since_progress > STALL_TIMEOUT.as_secs()
+	}
+
+	/// The archive is running, all actors are alive and it is not stuck.
+	pub fn is_healthy(&self) -> bool {
+		self.running && self.actors.all_alive() && !self.is_stalled()
+	}
+}
+
+#[derive(Default)]
+struct Inner {
+	status: ArchiveStatus,
+	/// Whether the last attempt at sampling the status succeeded.
+	sampled: bool,
+	last_progress: Option<Instant>,
+}
+
+/// Shared, updatable status of the archive.
+/// Cloning is cheap, clones update the same status.
+#[derive(Clone, Default)]
+pub struct StatusTracker {
+	inner: Arc<RwLock<Inner>>,
+}
+
+/// Values of the status which are sampled from the databases.
+pub(crate) struct Sample {
+	pub indexed_head: Option<u32>,
+	pub best_head: u32,
+	pub finalized_head: u32,
+	pub pending_jobs: u64,
+	pub failed_jobs: u64,
+	/// Selected blocks which were not executed again yet, if blocks are being executed again.
+	pub reexecution_remaining: Option<u64>,
+}
+
+impl StatusTracker {
+	/// Get a snapshot of the current status.
+	pub fn snapshot(&self) -> ArchiveStatus {
+		Self::snapshot_of(&self.inner.read())
+	}
+
+	/// The archive is healthy, and its status could be sampled recently.
+	pub fn is_ready(&self) -> bool {
+		// one guard: a second read would deadlock behind a waiting writer
+		let inner = self.inner.read();
+		inner.sampled && Self::snapshot_of(&inner).is_healthy()
+	}
+
+	fn snapshot_of(inner: &Inner) -> ArchiveStatus {
+		let mut status = inner.status.clone();
+		status.secs_since_progress = inner.last_progress.map(|t| t.elapsed().as_secs()).unwrap_or(0);
+		status
+	}
+
+	pub(crate) fn set_running(&self, running: bool) {
+		self.inner.write().status.running = running;
+	}
+
+	pub(crate) fn set_actors(&self, actors: ActorStatus) {
+		self.inner.write().status.actors = actors;
+	}
+
+	/// Start reporting the progress of executing `total` selected blocks again.
+	pub(crate) fn start_reexecution(&self, blocks: BlockSelection, total: u64) {
+		self.inner.write().status.reexecution = Some(ReexecutionStatus { blocks, total, remaining: total });
+	}
+
+	/// The blocks being executed again, whose progress is sampled.
+	pub(crate) fn reexecution(&self) -> Option<BlockSelection> {
+		self.inner.read().status.reexecution.as_ref().map(|r| r.blocks)
+	}
+
+	pub(crate) fn record_error(&self, error: impl Display) {
+		self.inner.write().status.last_error = Some(error.to_string());
+	}
+
+	/// Sampling failed. The archive is not ready until the next successful sample.
+	pub(crate) fn sample_failed(&self, error: impl Display) {
+		let mut inner = self.inner.write();
+		inner.sampled = false;
+		inner.status.last_error = Some(error.to_string());
+	}
+
+	pub(crate) fn update(&self, sample: Sample) {
+		let mut inner = self.inner.write();
+		let progressed =
+			inner.status.indexed_head != sample.indexed_head || inner.status.pending_jobs != sample.pending_jobs;
+		if progressed || inner.last_progress.is_none() {
+			inner.last_progress = Some(Instant::now());
+		}
+		inner.sampled = true;
+		let status = &mut inner.status;
+		status.indexed_head = sample.indexed_head;
+		status.best_head = sample.best_head;
+		status.finalized_head = sample.finalized_head;
+		status.pending_jobs = sample.pending_jobs;
+		status.failed_jobs = sample.failed_jobs;
+		if let (Some(reexecution), Some(remaining)) = (status.reexecution.as_mut(), sample.reexecution_remaining) {
+			reexecution.remaining = remaining;
+		}
+	}
+
+	/// Answer a request to one of the status paths.
+	pub(crate) fn respond(&self, path: &str) -> Response {
+		let status = self.snapshot();
+		let healthy = match path {
+			"/health" => status.is_healthy(),
+			"/ready" => self.is_ready(),
+			"/status" => true,
+			_ => return Response::not_found(),
+		};
+		let body = serde_json::to_vec(&status).unwrap_or_default();
+		Response::new(if healthy { 200 } else { 503 }, "application/json", body)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn sample(indexed_head: Option<u32>, pending_jobs: u64) -> Sample {
+		Sample {
+			indexed_head,
+			best_head: 100,
+			finalized_head: 90,
+			pending_jobs,
+			failed_jobs: 0,
+			reexecution_remaining: None,
+		}
+	}
+
+	#[test]
+	fn should_be_ready_once_sampled() {
+		let tracker = StatusTracker::default();
+		tracker.set_running(true);
+		tracker.set_actors(ActorStatus { blocks: true, metadata: true, storage: true, db_pool: true });
+		assert!(tracker.snapshot().is_healthy());
+		assert!(!tracker.is_ready());
+
+		tracker.update(sample(Some(50), 10));
+		assert!(tracker.is_ready());
+
+		tracker.sample_failed("connection refused");
+		assert!(!tracker.is_ready());
+		assert_eq!(tracker.snapshot().last_error.as_deref(), Some("connection refused"));
+	}
+
+	#[test]
+	fn should_be_unhealthy_if_an_actor_died() {
+		let tracker = StatusTracker::default();
+		tracker.set_running(true);
+		tracker.set_actors(ActorStatus { blocks: true, metadata: false, storage: true, db_pool: true });
+		assert!(!tracker.snapshot().is_healthy());
+	}
+
+	#[test]
+	fn should_detect_stalls() {
+		let mut status = ArchiveStatus { indexed_head: Some(50), finalized_head: 90, ..Default::default() };
+		status.secs_since_progress = STALL_TIMEOUT.as_secs() + 1;
+		assert!(status.is_stalled());
+		status.indexed_head = Some(90);
+		assert!(!status.is_stalled());
+	}
+
+	#[test]
+	fn should_track_reexecution_progress() {
+		let tracker = StatusTracker::default();
+		assert_eq!(tracker.reexecution(), None);
+		tracker.start_reexecution(BlockSelection::Spec(9050), 20);
+		assert_eq!(tracker.reexecution(), Some(BlockSelection::Spec(9050)));
+
+		tracker.update(Sample { reexecution_remaining: Some(5), ..sample(Some(50), 5) });
+		let reexecution = tracker.snapshot().reexecution.unwrap();
+		assert_eq!((reexecution.total, reexecution.remaining), (20, 5));
+	}
+}
@@ -0,0 +1,154 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Filters deciding which storage changes are indexed.
+//!
+//! Prefixes are either hex, or the name of a pallet optionally followed by the name of a storage item,
+//! e.g. `Balances` or `System.Account`. Names are hashed like FRAME hashes storage prefixes, so a pallet
+//! is named by the storage prefix it has in the metadata, which is the pallet name unless the runtime
+//! renamed it.
+
+use serde::Deserialize;
+
+use sp_core::hashing::twox_128;
+use sp_runtime::traits::Block as BlockT;
+
+use crate::{
+	error::{ArchiveError, Result},
+	types::Storage,
+};
+
+/// Configure which storage changes are indexed.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct StorageConfig {
+	/// Only index storage keys starting with one of these prefixes.
+	/// All keys are indexed if empty.
+	#[serde(default)]
+	pub include_prefixes: Vec<String>,
+	/// Never index storage keys starting with one of these prefixes.
+	#[serde(default)]
+	pub exclude_prefixes: Vec<String>,
+	/// Keep each distinct value once in `storage_values`, referenced by its hash from `storage`.
+	#[serde(default)]
+	pub deduplicate_values: bool,
+}
+
+/// The storage prefixes of a [`StorageConfig`].
+#[derive(Clone, Debug)]
+pub(crate) struct StorageFilter {
+	include: Vec<Vec<u8>>,
+	exclude: Vec<Vec<u8>>,
+	/// `System.Number`, changed by every block.
+	/// Always indexed, so that every executed block has storage.
+	block_number: Vec<u8>,
+}
+
+impl Default for StorageFilter {
+	/// Index every storage change.
+	fn default() -> Self {
+		Self {
+			include: Vec::new(),
+			exclude: Vec::new(),
+			block_number: [twox_128(b"System"), twox_128(b"Number")].concat(),
+		}
+	}
+}
+
+impl StorageFilter {
+	pub(crate) fn new(config: &StorageConfig) -> Result<Self> {
+		Ok(Self {
+			include: config.include_prefixes.iter().map(|p| parse_prefix(p)).collect::<Result<_>>()?,
+			exclude: config.exclude_prefixes.iter().map(|p| parse_prefix(p)).collect::<Result<_>>()?,
+			..Default::default()
+		})
+	}
+
+	/// Whether changes of `key` are indexed.
+	fn keeps(&self, key: &[u8]) -> bool {
+		let included = self.include.is_empty() || self.include.iter().any(|p| key.starts_with(p));
+		let excluded = self.exclude.iter().any(|p| key.starts_with(p));
+		(included && !excluded) || key == self.block_number.as_slice()
+	}
+
+	/// Remove the changes which are not indexed.
+	pub(crate) fn apply<B: BlockT>(&self, storage: &mut [Storage<B>]) {
+		if self.include.is_empty() && self.exclude.is_empty() {
+			return;
+		}
+		for s in storage.iter_mut() {
+			s.changes.retain(|(key, _)| self.keeps(&key.0));
+		}
+	}
+}
+
+pub(crate) fn parse_prefix(prefix: &str) -> Result<Vec<u8>> {
+	let invalid = || ArchiveError::InvalidPrefix(prefix.to_string());
+	if let Some(hex) = prefix.strip_prefix("0x") {
+		return hex::decode(hex).map_err(|_| invalid());
+	}
+	let mut names = prefix.split('.');
+	match (names.next(), names.next(), names.next()) {
+		(Some(pallet), None, None) if !pallet.is_empty() => Ok(twox_128(pallet.as_bytes()).to_vec()),
+		(Some(pallet), Some(item), None) if !pallet.is_empty() && !item.is_empty() => {
+			Ok([twox_128(pallet.as_bytes()), twox_128(item.as_bytes())].concat())
+		}
+		_ => Err(invalid()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn storage_filter(include: &[&str], exclude: &[&str]) -> StorageFilter {
+		StorageFilter::new(&StorageConfig {
+			include_prefixes: include.iter().map(|p| p.to_string()).collect(),
+			exclude_prefixes: exclude.iter().map(|p| p.to_string()).collect(),
+			deduplicate_values: false,
+		})
+		.unwrap()
+	}
+
+	#[test]
+	fn should_parse_prefixes() {
+		assert_eq!(parse_prefix("0x26aa").unwrap(), vec![0x26, 0xaa]);
+		assert_eq!(parse_prefix("System").unwrap(), twox_128(b"System").to_vec());
+		// `System.Account` storage keys
+		assert_eq!(
+			parse_prefix("System.Account").unwrap(),
+			hex::decode("26aa394eea5630e07c48ae0c9558cef7b99d880ec681799c0cf30e8886371da9").unwrap()
+		);
+		assert!(parse_prefix("0xzz").is_err());
+		assert!(parse_prefix("System.").is_err());
+		assert!(parse_prefix("A.B.C").is_err());
+	}
+
+	#[test]
+	fn should_filter_keys() {
+		let balances = [&twox_128(b"Balances")[..], &[1, 2, 3]].concat();
+		let timestamp = [&twox_128(b"Timestamp")[..], &twox_128(b"Now")[..]].concat();
+		let block_number = [twox_128(b"System"), twox_128(b"Number")].concat();
+
+		let filter = storage_filter(&["Balances", "Staking"], &[]);
+		assert!(filter.keeps(&balances));
+		assert!(!filter.keeps(&timestamp));
+		assert!(filter.keeps(&block_number));
+
+		let filter = storage_filter(&[], &["Timestamp.Now"]);
+		assert!(filter.keeps(&balances));
+		assert!(!filter.keeps(&timestamp));
+	}
+}
@@ -17,15 +17,23 @@
 //! Background tasks that take their parameters from Postgres, and are either
 //! executed on a threadpool or spawned onto the executor.
 
-use std::{marker::PhantomData, panic::AssertUnwindSafe, sync::Arc};
+use std::{
+	fmt::Display,
+	marker::PhantomData,
+	panic::AssertUnwindSafe,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc,
+	},
+	time::{Duration, Instant},
+};
 
+use hashbrown::HashMap;
 use parking_lot::Mutex;
 use serde::de::DeserializeOwned;
 use xtra::prelude::*;
 
 use sc_client_api::backend;
-use sp_api::{ApiExt, ApiRef, ConstructRuntimeApi};
-use sp_block_builder::BlockBuilder as BlockBuilderApi;
 use sp_runtime::{
 	generic::BlockId,
 	traits::{Block as BlockT, Header, NumberFor},
@@ -36,12 +44,13 @@ use substrate_archive_backend::{ApiAccess, ReadOnlyBackend as Backend, ReadOnlyD
 use crate::{
 	actors::StorageAggregator,
 	error::ArchiveError,
+	metrics::Metrics,
 	types::Storage,
 	wasm_tracing::{SpansAndEvents, TraceHandler, Traces},
 };
 
 /// The environment passed to each task
-pub struct Environment<B, R, C, D>
+pub struct Environment<B, C, D>
 where
 	D: ReadOnlyDb,
 	B: BlockT + Unpin,
@@ -54,11 +63,13 @@ where
 	backend: Arc<Backend<B, D>>,
 	client: Arc<C>,
 	storage: Address<StorageAggregator<B>>,
-	_marker: PhantomData<R>,
+	metrics: Metrics,
+	running: RunningTasks,
+	errors: JobErrors,
 }
 
-type Env<B, R, C, D> = AssertUnwindSafe<Environment<B, R, C, D>>;
-impl<B, R, C, D> Environment<B, R, C, D>
+type Env<B, C, D> = AssertUnwindSafe<Environment<B, C, D>>;
+impl<B, C, D> Environment<B, C, D>
 where
 	D: ReadOnlyDb,
 	B: BlockT + Unpin,
@@ -69,8 +80,66 @@ where
 		client: Arc<C>,
 		storage: Address<StorageAggregator<B>>,
 		tracing_targets: Option<String>,
+		metrics: Metrics,
+		running: RunningTasks,
+		errors: JobErrors,
 	) -> Self {
-		Self { backend, client, storage, tracing_targets, _marker: PhantomData }
+		Self { backend, client, storage, tracing_targets, metrics, running, errors }
+	}
+}
+
+/// Counts the tasks which are currently executing, so that shutdown can wait for them.
+#[derive(Clone, Default)]
+pub struct RunningTasks(Arc<AtomicUsize>);
+
+impl RunningTasks {
+	/// Mark a task as running until the returned guard is dropped.
+	fn start(&self) -> RunningGuard {
+		self.0.fetch_add(1, Ordering::SeqCst);
+		RunningGuard(self.0.clone())
+	}
+
+	pub fn count(&self) -> usize {
+		self.0.load(Ordering::SeqCst)
+	}
+
+	/// Wait until no task is running, or until `deadline` has passed.
+	/// Returns false if tasks were still running at the deadline.
+	pub async fn wait_until(&self, deadline: Instant) -> bool {
+		while self.count() > 0 {
+			if Instant::now() >= deadline {
+				return false;
+			}
+			smol::Timer::after(Duration::from_millis(100)).await;
+		}
+		true
+	}
+}
+
+struct RunningGuard(Arc<AtomicUsize>);
+
+impl Drop for RunningGuard {
+	fn drop(&mut self) {
+		self.0.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+/// The last error of every block whose execution is failing, by block hash.
+/// Kept until the block is moved to `failed_blocks`, or executes successfully.
+#[derive(Clone, Default)]
+pub struct JobErrors(Arc<Mutex<HashMap<Vec<u8>, String>>>);
+
+impl JobErrors {
+	fn record(&self, hash: &[u8], error: impl Display) {
+		self.0.lock().insert(hash.to_vec(), error.to_string());
+	}
+
+	fn clear(&self, hash: &[u8]) {
+		self.0.lock().remove(hash);
+	}
+
+	pub fn take(&self, hash: &[u8]) -> Option<String> {
+		self.0.lock().remove(hash)
 	}
 }
 
@@ -116,13 +185,13 @@ where
 	}
 }
 
-struct BlockExecutor<'a, Block, Api, B>
+struct BlockExecutor<'a, Block, C, B>
 where
 	Block: BlockT,
-	Api: BlockBuilderApi<Block> + ApiExt<Block, StateBackend = backend::StateBackendFor<B, Block>>,
+	C: ApiAccess<Block, B>,
 	B: backend::Backend<Block>,
 {
-	api: ApiRef<'a, Api>,
+	client: &'a C,
 	backend: &'a Arc<B>,
 	block: Block,
 	id: BlockId<Block>,
@@ -133,31 +202,29 @@ struct BlockPrep<Block, S, H, N> {
 	block: Block,
 	state: S,
 	hash: H,
-	parent_hash: H,
 	number: N,
 }
 
 type BlockParams<Block, Backend> =
 	BlockPrep<Block, <Backend as backend::Backend<Block>>::State, <Block as BlockT>::Hash, NumberFor<Block>>;
 
-impl<'a, Block, Api, B> BlockExecutor<'a, Block, Api, B>
+impl<'a, Block, C, B> BlockExecutor<'a, Block, C, B>
 where
 	Block: BlockT,
 	NumberFor<Block>: Into<u32>,
-	Api: BlockBuilderApi<Block> + ApiExt<Block, StateBackend = backend::StateBackendFor<B, Block>>,
+	C: ApiAccess<Block, B>,
 	B: backend::Backend<Block>,
 {
-	fn new(api: ApiRef<'a, Api>, backend: &'a Arc<B>, block: Block) -> Self {
+	fn new(client: &'a C, backend: &'a Arc<B>, block: Block) -> Self {
 		let header = block.header();
 		let parent_hash = header.parent_hash();
 		let id = BlockId::Hash(*parent_hash);
 
-		Self { api, backend, block, id }
+		Self { client, backend, block, id }
 	}
 
 	fn prepare_block(block: Block, backend: &B, id: &BlockId<Block>) -> Result<BlockParams<Block, B>, ArchiveError> {
 		let header = block.header();
-		let parent_hash = *header.parent_hash();
 		let hash = header.hash();
 		let number = *header.number();
 
@@ -170,16 +237,13 @@ where
 		// popping a digest item has no effect on storage changes afaik
 		let (mut header, ext) = block.deconstruct();
 		header.digest_mut().pop();
-		Ok(BlockPrep { block: Block::new(header, ext), state, hash, parent_hash, number })
+		Ok(BlockPrep { block: Block::new(header, ext), state, hash, number })
 	}
 
 	fn execute(self) -> Result<BlockChanges<Block>, ArchiveError> {
-		let BlockPrep { block, state, hash, parent_hash, number } =
-			Self::prepare_block(self.block, &self.backend, &self.id)?;
+		let BlockPrep { block, state, hash, number } = Self::prepare_block(self.block, &self.backend, &self.id)?;
 
-		self.api.execute_block(&self.id, block)?;
-		let storage_changes =
-			self.api.into_storage_changes(&state, None, parent_hash).map_err(ArchiveError::ConvertStorageChanges)?;
+		let storage_changes = self.client.execute_block(block, &state)?;
 
 		Ok(BlockChanges {
 			storage_changes: storage_changes.main_storage_changes,
@@ -190,8 +254,8 @@ where
 	}
 
 	fn execute_with_tracing(self, targets: &str) -> Result<(BlockChanges<Block>, Traces), ArchiveError> {
-		let BlockExecutor { block, backend, id, api } = self;
-		let BlockPrep { block, state, hash, parent_hash, number } = Self::prepare_block(block, &backend, &id)?;
+		let BlockExecutor { block, backend, id, client } = self;
+		let BlockPrep { block, state, hash, number } = Self::prepare_block(block, &backend, &id)?;
 
 		let span_events = Arc::new(Mutex::new(SpansAndEvents { spans: Vec::new(), events: Vec::new() }));
 		let handler = TraceHandler::new(&targets, span_events);
@@ -200,14 +264,11 @@ where
 			"execute_block",
 			extrinsics_len = block.extrinsics().len()
 		);
-		let (spans, events, _) = handler.scoped_trace(|| {
+		let (spans, events, changes) = handler.scoped_trace(|| {
 			let _guard = dispatcher_span.enter();
-			api.execute_block(&id, block).map_err(ArchiveError::from)
+			client.execute_block(block, &state).map_err(ArchiveError::from)
 		})?;
 
-		let changes =
-			api.into_storage_changes(&state, None, parent_hash).map_err(ArchiveError::ConvertStorageChanges)?;
-
 		let changes = BlockChanges {
 			storage_changes: changes.main_storage_changes,
 			child_storage: changes.child_storage_changes,
@@ -247,22 +308,36 @@ impl sp_core::traits::SpawnNamed for TaskExecutor {
 // TODO: We should detect when the chain is behind our node, and not execute blocks in this case.
 /// Execute a block, and send it to the database actor
 #[coil::background_job]
-pub fn execute_block<B, RA, Api, D>(
-	env: &Env<B, RA, Api, D>,
+pub fn execute_block<B, Api, D>(
+	env: &Env<B, Api, D>,
 	block: B,
-	_m: PhantomData<(RA, Api, D)>,
+	_m: PhantomData<(Api, D)>,
 ) -> Result<(), coil::PerformError>
 where
 	D: ReadOnlyDb + 'static,
 	B: BlockT + DeserializeOwned + Unpin,
 	NumberFor<B>: Into<u32>,
 	B::Hash: Unpin,
-	RA: ConstructRuntimeApi<B, Api> + Send + Sync + 'static,
-	RA::RuntimeApi: BlockBuilderApi<B> + ApiExt<B, StateBackend = backend::StateBackendFor<Backend<B, D>, B>>,
-	Api: ApiAccess<B, Backend<B, D>, RA> + 'static,
+	Api: ApiAccess<B, Backend<B, D>> + 'static,
 {
-	let api = env.client.runtime_api();
+	let _running = env.running.start();
+	let hash = block.hash();
+	let result = try_execute_block(env, block);
+	match &result {
+		Ok(()) => env.errors.clear(hash.as_ref()),
+		Err(e) => env.errors.record(hash.as_ref(), e),
+	}
+	result
+}
 
+fn try_execute_block<B, Api, D>(env: &Env<B, Api, D>, block: B) -> Result<(), coil::PerformError>
+where
+	D: ReadOnlyDb + 'static,
+	B: BlockT + DeserializeOwned + Unpin,
+	NumberFor<B>: Into<u32>,
+	B::Hash: Unpin,
+	Api: ApiAccess<B, Backend<B, D>> + 'static,
+{
 	if *block.header().parent_hash() == Default::default() {
 		return Ok(());
 	}
@@ -271,10 +346,10 @@ where
 		"Executing Block: {}:{}, version {}",
 		block.header().hash(),
 		block.header().number(),
-		env.client.runtime_version_at(&BlockId::Hash(block.hash())).map_err(|e| format!("{:?}", e))?.spec_version,
+		env.client.runtime_version(&BlockId::Hash(block.hash()))?.spec_version,
 	);
 
-	let block = BlockExecutor::new(api, &env.backend, block);
+	let block = BlockExecutor::new(&*env.client, &env.backend, block);
 
 	let now = std::time::Instant::now();
 	let (storage, traces) = if let Some(targets) = env.tracing_targets.as_ref() {
@@ -282,7 +357,9 @@ where
 	} else {
 		(block.execute()?, Default::default())
 	};
-	log::debug!("Took {:?} to execute block", now.elapsed());
+	let elapsed = now.elapsed();
+	env.metrics.execute_block_duration.observe(elapsed.as_secs_f64());
+	log::debug!("Took {:?} to execute block", elapsed);
 
 	let now = std::time::Instant::now();
 	smol::block_on(env.storage.send(Storage::from(storage)))?;
@@ -41,7 +41,7 @@ use tracing_subscriber::{
 use crate::error::{Result, TracingError};
 
 /// The Event a tracing subscriber collects before sending data to the TracingActor.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EventMessage {
 	pub name: String,
 	pub target: String,
@@ -69,7 +69,7 @@ pub struct SpanMessage {
 }
 
 /// Finished Trace Data Format. Ready for insertion into a relational database.
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Traces {
 	block_num: u32,
 	hash: Vec<u8>,
@@ -222,6 +222,13 @@ impl From<DataType> for String {
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct TraceData(HashMap<String, DataType>);
 
+impl TraceData {
+	/// Iterate over the recorded fields, with their values formatted as strings.
+	pub fn iter(&self) -> impl Iterator<Item = (&str, String)> {
+		self.0.iter().map(|(k, v)| (k.as_str(), v.to_string()))
+	}
+}
+
 impl Visit for TraceData {
 	fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
 		self.0.insert(field.name().to_string(), DataType::String(format!("{:?}", value)));
@@ -0,0 +1,331 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Webhook notifications for changes of watched storage keys.
+//!
+//! Storage key prefixes and their callback URLs are kept in the `watches` table.
+//! Whenever a batch of storage is flushed, every change of a key matching a prefix is POSTed as a
+//! JSON `WatchNotification` to the callback URL of the watch.
+//! The old value is the value of the key before the change, read from the indexed `storage`.
+//! Every callback URL has its own queue and delivery task, so a slow callback only delays its own notifications.
+//! Notifications are delivered in order. Failed callbacks are retried with exponential backoff, and notifications
+//! are dropped after `MAX_ATTEMPTS` or when the queue of their callback is full, counting them in the
+//! `watch_notifications_failed_total` metric.
+//! Watches whose callback URL is not a plain `http://` URL are skipped.
+
+use std::{
+	io,
+	time::{Duration, Instant},
+};
+
+use hashbrown::{HashMap, HashSet};
+
+use serde::Serialize;
+
+use sp_runtime::traits::Block as BlockT;
+
+use crate::{
+	database::{models::Watch, queries, Database},
+	error::Result,
+	http,
+	metrics::Metrics,
+	types::Storage,
+};
+
+/// How often the watches are reloaded from the database.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+/// How long to wait for a callback to respond.
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often a notification is POSTed before it is dropped.
+const MAX_ATTEMPTS: u32 = 5;
+/// How long to wait before the first retry, doubled for every further retry.
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+/// How many notifications may wait for a callback before further ones are dropped.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// The change of a watched storage key, as it is POSTed to the callback URL.
+/// Hashes, keys and values are hex-encoded.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct WatchNotification {
+	pub watch_id: i32,
+	pub block_num: u32,
+	pub hash: String,
+	pub key: String,
+	/// `None` if the key had no earlier value in `storage`, e.g. since it was not indexed.
+	pub old_value: Option<String>,
+	/// `None` if the key was removed.
+	pub new_value: Option<String>,
+}
+
+/// A change of a key matching at least one watch.
+struct WatchedChange {
+	block_num: u32,
+	hash: Vec<u8>,
+	key: Vec<u8>,
+	value: Option<Vec<u8>>,
+	/// Ids and callback URLs of the matching watches.
+	watches: Vec<(i32, String)>,
+}
+
+/// Finds changes of watched keys, and delivers them in the background.
+pub(crate) struct Watcher {
+	db: Database,
+	metrics: Metrics,
+	watches: Vec<Watch>,
+	/// Ids of the watches skipped since their callback URL is not supported.
+	unsupported: HashSet<i32>,
+	refreshed: Option<Instant>,
+	/// Queues of the delivery tasks, by callback URL.
+	callbacks: HashMap<String, flume::Sender<WatchNotification>>,
+}
+
+impl Watcher {
+	pub(crate) fn new(db: Database, metrics: Metrics) -> Self {
+		Self {
+			db,
+			metrics,
+			watches: Vec::new(),
+			unsupported: HashSet::new(),
+			refreshed: None,
+			callbacks: HashMap::new(),
+		}
+	}
+
+	async fn refresh(&mut self) -> Result<()> {
+		if self.refreshed.map(|t| t.elapsed() < REFRESH_INTERVAL).unwrap_or(false) {
+			return Ok(());
+		}
+		let mut conn = self.db.conn().await?;
+		let (watches, unsupported): (Vec<_>, Vec<_>) =
+			queries::watches(&mut conn).await?.into_iter().partition(|w| http::is_supported(&w.callback_url));
+		for watch in unsupported {
+			// watches added before callback URLs were checked
+			if self.unsupported.insert(watch.id) {
+				log::error!(
+					"Skipping watch {}, callback URL {} is not a plain http:// URL",
+					watch.id,
+					watch.callback_url
+				);
+			}
+		}
+		// dropping the queue of a callback stops its delivery task once the queue is drained
+		self.callbacks.retain(|url, _| watches.iter().any(|w| &w.callback_url == url));
+		self.watches = watches;
+		self.refreshed = Some(Instant::now());
+		Ok(())
+	}
+
+	/// Queue notifications for the changes of watched keys in `storage`.
+	/// Must be called before `storage` is inserted, since the old values are read from the database.
+	pub(crate) async fn notify<B: BlockT>(&mut self, storage: &[Storage<B>]) -> Result<()> {
+		self.refresh().await?;
+		if self.watches.is_empty() {
+			return Ok(());
+		}
+		let mut changes = Vec::new();
+		for s in storage.iter() {
+			for (key, value) in s.changes() {
+				let watches = self
+					.watches
+					.iter()
+					.filter(|w| key.0.starts_with(&w.prefix))
+					.map(|w| (w.id, w.callback_url.clone()))
+					.collect::<Vec<_>>();
+				if !watches.is_empty() {
+					changes.push(WatchedChange {
+						block_num: s.block_num(),
+						hash: s.hash().as_ref().to_vec(),
+						key: key.0.clone(),
+						value: value.as_ref().map(|v| v.0.clone()),
+						watches,
+					});
+				}
+			}
+		}
+		if changes.is_empty() {
+			return Ok(());
+		}
+		changes.sort_by_key(|c| c.block_num);
+
+		// the value before the first change of a key in this batch is in the database,
+		// the value before any later change is the value of the preceding change
+		let mut first_changes = HashMap::new();
+		for change in changes.iter() {
+			first_changes.entry(change.key.clone()).or_insert(change.block_num as i32);
+		}
+		let (keys, block_nums): (Vec<_>, Vec<_>) = first_changes.into_iter().unzip();
+		let mut conn = self.db.conn().await?;
+		let mut values: HashMap<_, _> =
+			queries::previous_values(&mut conn, &keys, &block_nums).await?.into_iter().collect();
+
+		for change in changes {
+			let old_value = values.insert(change.key.clone(), change.value.clone()).flatten();
+			for (watch_id, url) in change.watches.iter() {
+				let notification = WatchNotification {
+					watch_id: *watch_id,
+					block_num: change.block_num,
+					hash: to_hex(&change.hash),
+					key: to_hex(&change.key),
+					old_value: old_value.as_deref().map(to_hex),
+					new_value: change.value.as_deref().map(to_hex),
+				};
+				self.queue(url, notification);
+			}
+		}
+		Ok(())
+	}
+
+	/// Queue a notification for delivery to `url`, starting the delivery task of the callback if there is none.
+	fn queue(&mut self, url: &str, notification: WatchNotification) {
+		let metrics = &self.metrics;
+		let tx = self.callbacks.entry(url.to_string()).or_insert_with(|| {
+			let (tx, rx) = flume::bounded(QUEUE_CAPACITY);
+			smol::spawn(deliver(url.to_string(), metrics.clone(), rx)).detach();
+			tx
+		});
+		if let Err(e) = tx.try_send(notification) {
+			let notification = e.into_inner();
+			log::warn!(
+				"Dropping notification of watch {} for block {}, its callback is falling behind",
+				notification.watch_id,
+				notification.block_num
+			);
+			self.metrics.watch_notifications_failed.inc();
+		}
+	}
+}
+
+/// Deliver the notifications queued for the callback `url`, in order.
+async fn deliver(url: String, metrics: Metrics, rx: flume::Receiver<WatchNotification>) {
+	while let Ok(notification) = rx.recv_async().await {
+		if !post(&url, &notification, RETRY_BACKOFF).await {
+			metrics.watch_notifications_failed.inc();
+		}
+	}
+}
+
+/// POST a notification, retrying failed callbacks after `backoff`, doubled for every further retry.
+///
+/// # Returns whether the notification was delivered
+async fn post(url: &str, notification: &WatchNotification, mut backoff: Duration) -> bool {
+	let body = match serde_json::to_vec(notification) {
+		Ok(body) => body,
+		Err(e) => {
+			log::error!("Failed to serialize watch notification: {}", e);
+			return false;
+		}
+	};
+	for attempt in 1..=MAX_ATTEMPTS {
+		if attempt > 1 {
+			smol::Timer::after(backoff).await;
+			backoff *= 2;
+		}
+		let timeout = async {
+			smol::Timer::after(CALLBACK_TIMEOUT).await;
+			Err(io::Error::new(io::ErrorKind::TimedOut, "callback timed out"))
+		};
+		match smol::future::or(http::post(url, "application/json", &body), timeout).await {
+			Ok(status) if (200..300).contains(&status) => {
+				log::debug!("Notified watch {} of block {}", notification.watch_id, notification.block_num);
+				return true;
+			}
+			// the callback rejected the notification, sending it again won't help
+			Ok(status) if (400..500).contains(&status) && status != 408 && status != 429 => {
+				log::error!(
+					"Callback of watch {} rejected block {} with {}",
+					notification.watch_id,
+					notification.block_num,
+					status
+				);
+				return false;
+			}
+			Ok(status) => log::warn!(
+				"Callback of watch {} responded with {} (attempt {}/{})",
+				notification.watch_id,
+				status,
+				attempt,
+				MAX_ATTEMPTS
+			),
+			Err(e) => log::warn!(
+				"Callback of watch {} failed: {} (attempt {}/{})",
+				notification.watch_id,
+				e,
+				attempt,
+				MAX_ATTEMPTS
+			),
+		}
+	}
+	log::error!(
+		"Dropping notification of watch {} for block {} after {} attempts",
+		notification.watch_id,
+		notification.block_num,
+		MAX_ATTEMPTS
+	);
+	false
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+	format!("0x{}", hex::encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use smol::{
+		io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+		net::TcpListener,
+	};
+
+	/// Accept a connection, drain the request and answer with `status`.
+	async fn respond(listener: &TcpListener, status: u16) {
+		let (mut stream, _) = listener.accept().await.unwrap();
+		let mut reader = BufReader::new(stream.clone());
+		let mut line = String::new();
+		while reader.read_line(&mut line).await.unwrap() > 2 {
+			line.clear();
+		}
+		let response = format!("HTTP/1.1 {} \r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+		stream.write_all(response.as_bytes()).await.unwrap();
+	}
+
+	fn notification() -> WatchNotification {
+		WatchNotification {
+			watch_id: 1,
+			block_num: 42,
+			hash: to_hex(&[0; 32]),
+			key: to_hex(b"key"),
+			old_value: None,
+			new_value: Some(to_hex(b"value")),
+		}
+	}
+
+	#[test]
+	fn should_retry_failed_callbacks() {
+		smol::block_on(async {
+			let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+			let url = format!("http://{}/hook", listener.local_addr().unwrap());
+			let server = smol::spawn(async move {
+				respond(&listener, 503).await;
+				respond(&listener, 204).await;
+				respond(&listener, 400).await;
+			});
+			assert!(post(&url, &notification(), Duration::from_millis(1)).await);
+			// client errors are not retried
+			assert!(!post(&url, &notification(), Duration::from_millis(1)).await);
+			server.await;
+		});
+	}
+}